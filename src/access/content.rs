@@ -0,0 +1,170 @@
+//! Keeps stored files from running script as part of the site.
+//!
+//! Uploads are served from the same origin as the admin pages, so an uploaded
+//! HTML or SVG file would otherwise be able to make requests with an admin's
+//! session, or read the keys the upload page keeps.
+
+use actix_web::{
+    Error,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderMap, HeaderValue},
+    middleware::Next,
+};
+use mime_guess::{Mime, mime};
+
+/// Whether browsers run script found in content of type `mime`.
+pub fn is_active(mime: &Mime) -> bool {
+    let essence = mime.essence_str();
+    matches!(
+        essence,
+        "text/html"
+            | "application/xhtml+xml"
+            | "image/svg+xml"
+            | "text/xml"
+            | "application/xml"
+            | "text/xsl"
+            | "text/javascript"
+            | "application/javascript"
+            | "application/ecmascript"
+            | "text/ecmascript"
+    ) || mime.suffix() == Some(mime::XML)
+}
+
+/// Whether content of type `mime` is safe to show inline on the site: images
+/// other than SVG, video, audio and plain text.
+pub fn is_passive(mime: &Mime) -> bool {
+    if is_active(mime) {
+        return false;
+    }
+
+    match mime.type_() {
+        mime::IMAGE | mime::VIDEO | mime::AUDIO => true,
+        mime::TEXT => matches!(
+            mime.subtype().as_str(),
+            "plain" | "markdown" | "csv" | "css"
+        ),
+        _ => false,
+    }
+}
+
+/// Stops browsers from guessing a more dangerous type than the one in
+/// `headers`, and sandboxes the content if its type can run script, which
+/// gives it an origin of its own.
+pub fn protect(headers: &mut HeaderMap) {
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+
+    let active = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<Mime>().ok())
+        .is_none_or(|mime| is_active(&mime));
+    if active {
+        headers.insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static("sandbox"),
+        );
+    }
+}
+
+/// Middleware applying [`protect`] to the stored files of a namespace.
+pub async fn protect_stored_files(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let mut res = next.call(req).await?;
+    protect(res.headers_mut());
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(content_type: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(content_type) = content_type {
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(content_type),
+            );
+        }
+        protect(&mut headers);
+        headers
+    }
+
+    fn value(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+        headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    #[test]
+    fn sandboxes_active_content() {
+        for content_type in [
+            "text/html; charset=utf-8",
+            "image/svg+xml",
+            "application/xml",
+            "application/rss+xml",
+            "text/javascript",
+        ] {
+            let headers = headers(Some(content_type));
+            assert_eq!(
+                value(&headers, header::CONTENT_SECURITY_POLICY),
+                Some("sandbox")
+            );
+            assert_eq!(
+                value(&headers, header::X_CONTENT_TYPE_OPTIONS),
+                Some("nosniff")
+            );
+        }
+    }
+
+    #[test]
+    fn sandboxes_content_of_unknown_type() {
+        assert_eq!(
+            value(&headers(None), header::CONTENT_SECURITY_POLICY),
+            Some("sandbox")
+        );
+        assert_eq!(
+            value(
+                &headers(Some("not a type")),
+                header::CONTENT_SECURITY_POLICY
+            ),
+            Some("sandbox")
+        );
+    }
+
+    #[test]
+    fn tells_passive_content_apart() {
+        for passive in ["image/png", "video/webm", "audio/ogg", "text/plain"] {
+            assert!(is_passive(&passive.parse().unwrap()), "{}", passive);
+        }
+        for other in [
+            "image/svg+xml",
+            "text/html",
+            "application/pdf",
+            "application/octet-stream",
+        ] {
+            assert!(!is_passive(&other.parse().unwrap()), "{}", other);
+        }
+    }
+
+    #[test]
+    fn leaves_passive_content_alone() {
+        for content_type in [
+            "image/png",
+            "video/mp4",
+            "text/plain; charset=utf-8",
+            "application/octet-stream",
+        ] {
+            let headers = headers(Some(content_type));
+            assert!(!headers.contains_key(header::CONTENT_SECURITY_POLICY));
+            assert_eq!(
+                value(&headers, header::X_CONTENT_TYPE_OPTIONS),
+                Some("nosniff")
+            );
+        }
+    }
+}
@@ -0,0 +1,84 @@
+mod content;
+mod signature;
+mod viewer;
+
+use actix_web::{
+    Error, HttpRequest, HttpResponse,
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{self, CacheControl, CacheDirective},
+    middleware::Next,
+    web::Data,
+};
+
+use crate::config::{Config, NamespaceDefinition};
+
+pub use content::{is_passive, protect_stored_files};
+pub use signature::{sign_url, signature_valid, signature_valid_for};
+pub use viewer::viewer_authorized;
+
+/// Decides whether `req` may read from `namespace`, returning the response to
+/// send instead when it may not.
+///
+/// A valid signature always grants access. Otherwise namespaces that require
+/// signed URLs refuse the request, and namespaces with viewers ask for
+/// credentials.
+pub fn check_access(
+    cfg: &Config,
+    namespace_name: &str,
+    namespace: &NamespaceDefinition,
+    req: &HttpRequest,
+) -> Result<(), HttpResponse> {
+    if signature_valid(cfg, req) {
+        return Ok(());
+    }
+
+    if namespace.require_signed_urls {
+        return Err(HttpResponse::Forbidden()
+            .body("This link is missing a valid signature or has expired"));
+    }
+
+    if !viewer_authorized(namespace, req) {
+        return Err(viewer::unauthorized(namespace_name));
+    }
+
+    Ok(())
+}
+
+/// Middleware applying [`check_access`] for `namespace` to everything below
+/// it.
+pub async fn namespace_access<B: MessageBody>(
+    namespace: String,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let cfg = req.app_data::<Data<Config>>().cloned();
+    let definition =
+        cfg.as_ref().and_then(|cfg| cfg.namespaces.get(&namespace));
+
+    let (cfg, definition) = match (&cfg, definition) {
+        (Some(cfg), Some(definition)) => (cfg, definition),
+        _ => return next.call(req).await.map(|r| r.map_into_left_body()),
+    };
+
+    if let Err(response) =
+        check_access(cfg, &namespace, definition, req.request())
+    {
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    let mut res = next.call(req).await?;
+
+    if definition.requires_viewer_auth() || definition.require_signed_urls {
+        // keep shared caches from handing protected files to other people
+        res.headers_mut().insert(
+            header::CACHE_CONTROL,
+            CacheControl(vec![CacheDirective::Private])
+                .to_string()
+                .parse()
+                .expect("cache control should be a valid header value"),
+        );
+    }
+
+    Ok(res.map_into_left_body())
+}
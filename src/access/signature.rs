@@ -0,0 +1,238 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::{HttpRequest, web};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use url::Url;
+
+use crate::config::{Config, SigningConfig};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The query parameters carried by a signed URL.
+#[derive(Deserialize)]
+struct SignatureQuery {
+    expires: u64,
+    sig: String,
+}
+
+/// Returns `url` with `expires` and `sig` query parameters appended, making
+/// it valid for `ttl` from now.
+///
+/// A signature for an archive is also valid for the files inside it, at
+/// `<archive>/!/<path>`.
+pub fn sign_url(cfg: &Config, url: &Url, ttl: Duration) -> Url {
+    // saturating, as `max_ttl_seconds` may be set to anything
+    let expires = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time should not go backwards")
+        .as_secs()
+        .saturating_add(ttl.as_secs());
+
+    let signature = BASE64_URL_SAFE_NO_PAD.encode(
+        mac(
+            &cfg.signing,
+            signed_path(relative_path(cfg, url.path())),
+            expires,
+        )
+        .finalize()
+        .into_bytes(),
+    );
+
+    let mut signed = url.clone();
+    signed
+        .query_pairs_mut()
+        .append_pair("expires", &expires.to_string())
+        .append_pair("sig", &signature);
+    signed
+}
+
+/// Whether the request carries an unexpired signature for its path.
+pub fn signature_valid(cfg: &Config, req: &HttpRequest) -> bool {
+    signature_valid_for(cfg, req.path(), req.query_string())
+}
+
+/// Whether `query` carries an unexpired signature for `path`, which is
+/// percent-encoded the way it's requested.
+pub fn signature_valid_for(cfg: &Config, path: &str, query: &str) -> bool {
+    let query = match web::Query::<SignatureQuery>::from_query(query) {
+        Ok(query) => query.into_inner(),
+        Err(_) => return false,
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time should not go backwards")
+        .as_secs();
+
+    if query.expires < now {
+        return false;
+    }
+
+    let signature = match BASE64_URL_SAFE_NO_PAD.decode(&query.sig) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+
+    mac(
+        &cfg.signing,
+        signed_path(relative_path(cfg, path)),
+        query.expires,
+    )
+    .verify_slice(&signature)
+    .is_ok()
+}
+
+/// `path` without the path of `listen_url`, leaving the namespace and file.
+///
+/// Links are made from `listen_url`, which may have a prefix that a reverse
+/// proxy strips before requests get here, so neither side signs it.
+fn relative_path<'a>(cfg: &Config, path: &'a str) -> &'a str {
+    path.strip_prefix(cfg.web_server.listen_url.path())
+        .or_else(|| path.strip_prefix('/'))
+        .unwrap_or(path)
+}
+
+/// The part of `path` a signature covers: the archive, for paths inside one.
+fn signed_path(path: &str) -> &str {
+    path.split_once("/!/")
+        .map(|(archive, _)| archive)
+        .or_else(|| path.strip_suffix("/!"))
+        .unwrap_or(path)
+}
+
+fn mac(config: &SigningConfig, path: &str, expires: u64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(config.secret.as_bytes())
+        .expect("HMAC should accept keys of any length");
+    mac.update(path.as_bytes());
+    mac.update(b"\n");
+    mac.update(expires.to_string().as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn config(listen_url: &str) -> Config {
+        let mut cfg = Config::default();
+        cfg.signing.secret = "secret".to_string();
+        cfg.web_server.listen_url = listen_url.parse().unwrap();
+        cfg
+    }
+
+    /// The request a reverse proxy passes on for `url`, with the path of
+    /// `listen_url` stripped.
+    fn proxied(cfg: &Config, url: &Url) -> HttpRequest {
+        let path = url
+            .path()
+            .strip_prefix(cfg.web_server.listen_url.path())
+            .unwrap();
+        TestRequest::get()
+            .uri(&format!("/{}?{}", path, url.query().unwrap()))
+            .to_http_request()
+    }
+
+    fn sign(cfg: &Config, path: &str) -> Url {
+        let url = cfg.web_server.listen_url.join(path).unwrap();
+        sign_url(cfg, &url, Duration::from_secs(60))
+    }
+
+    #[test]
+    fn accepts_its_own_signatures() {
+        let cfg = config("http://localhost:3000/");
+        let url = sign(&cfg, "f/a.png");
+        assert!(signature_valid(&cfg, &proxied(&cfg, &url)));
+    }
+
+    #[test]
+    fn accepts_signatures_behind_a_path_prefix() {
+        let cfg = config("https://example.com/media/");
+        let url = sign(&cfg, "f/a.png");
+        assert_eq!(url.path(), "/media/f/a.png");
+
+        assert!(signature_valid(&cfg, &proxied(&cfg, &url)));
+        // and when the proxy passes the prefix on
+        let unstripped = TestRequest::get()
+            .uri(&format!("{}?{}", url.path(), url.query().unwrap()))
+            .to_http_request();
+        assert!(signature_valid(&cfg, &unstripped));
+    }
+
+    #[test]
+    fn huge_lifetimes_do_not_overflow() {
+        let cfg = config("http://localhost:3000/");
+        let url = cfg.web_server.listen_url.join("f/a.png").unwrap();
+        let url = sign_url(&cfg, &url, Duration::from_secs(u64::MAX));
+
+        assert!(url.query().unwrap().contains(&u64::MAX.to_string()));
+        assert!(signature_valid(&cfg, &proxied(&cfg, &url)));
+    }
+
+    #[test]
+    fn refuses_other_paths() {
+        let cfg = config("http://localhost:3000/");
+        let url = sign(&cfg, "f/a.png");
+        let req = TestRequest::get()
+            .uri(&format!("/f/b.png?{}", url.query().unwrap()))
+            .to_http_request();
+        assert!(!signature_valid(&cfg, &req));
+    }
+
+    #[test]
+    fn refuses_other_secrets() {
+        let cfg = config("http://localhost:3000/");
+        let url = sign(&cfg, "f/a.png");
+
+        let mut other = cfg.clone();
+        other.signing.secret = "other".to_string();
+        assert!(!signature_valid(&other, &proxied(&cfg, &url)));
+    }
+
+    #[test]
+    fn refuses_tampered_expiry() {
+        let cfg = config("http://localhost:3000/");
+        let url = sign(&cfg, "f/a.png");
+        let sig = url
+            .query_pairs()
+            .find(|(name, _)| name == "sig")
+            .unwrap()
+            .1
+            .into_owned();
+
+        let req = TestRequest::get()
+            .uri(&format!("/f/a.png?expires={}&sig={}", u64::MAX, sig))
+            .to_http_request();
+        assert!(!signature_valid(&cfg, &req));
+    }
+
+    #[test]
+    fn refuses_expired_signatures() {
+        let cfg = config("http://localhost:3000/");
+        let expires = 1_000;
+        let sig = BASE64_URL_SAFE_NO_PAD.encode(
+            mac(&cfg.signing, "f/a.png", expires)
+                .finalize()
+                .into_bytes(),
+        );
+
+        let req = TestRequest::get()
+            .uri(&format!("/f/a.png?expires={}&sig={}", expires, sig))
+            .to_http_request();
+        assert!(!signature_valid(&cfg, &req));
+    }
+
+    #[test]
+    fn archive_signatures_cover_their_contents() {
+        let cfg = config("http://localhost:3000/");
+        let url = sign(&cfg, "f/a.zip");
+        let req = TestRequest::get()
+            .uri(&format!("/f/a.zip/!/b/c.txt?{}", url.query().unwrap()))
+            .to_http_request();
+        assert!(signature_valid(&cfg, &req));
+    }
+}
@@ -0,0 +1,45 @@
+use actix_web::{HttpRequest, HttpResponse, http::header};
+use base64::{Engine, prelude::BASE64_STANDARD};
+
+use crate::config::NamespaceDefinition;
+
+/// Checks the request's HTTP Basic credentials against the namespace's
+/// viewers. Namespaces without viewers are public.
+pub fn viewer_authorized(
+    namespace: &NamespaceDefinition,
+    req: &HttpRequest,
+) -> bool {
+    if !namespace.requires_viewer_auth() {
+        return true;
+    }
+
+    basic_credentials(req).is_some_and(|(username, password)| {
+        namespace.auth_viewer(&username, &password)
+    })
+}
+
+/// The response asking the browser to prompt for credentials.
+pub fn unauthorized(namespace: &str) -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((
+            header::WWW_AUTHENTICATE,
+            format!("Basic realm=\"{}\", charset=\"UTF-8\"", namespace),
+        ))
+        .body("Authentication required")
+}
+
+/// Extracts the username and password from an `Authorization: Basic` header.
+fn basic_credentials(req: &HttpRequest) -> Option<(String, String)> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, encoded) = value.split_once(' ')?;
+
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = BASE64_STANDARD.decode(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+
+    Some((username.to_string(), password.to_string()))
+}
@@ -0,0 +1,211 @@
+use actix_web::{
+    HttpResponse,
+    web::{self, Data, Json},
+};
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+
+use super::{
+    AdminContext,
+    edit::{Change, Changed, EditError},
+};
+use crate::config::{Config, FileListingConfig, NamespaceDefinition};
+
+/// A namespace as the admin API shows it, without its keys.
+#[derive(Serialize)]
+struct NamespaceSummary<'a> {
+    name: &'a str,
+    path: &'a str,
+    backend: &'static str,
+    /// The namespace file it's defined in, when that isn't the configuration
+    /// file.
+    file: Option<String>,
+    default: bool,
+    viewers: Vec<&'a str>,
+    require_signed_urls: bool,
+    end_to_end_encrypted: bool,
+    encrypted_at_rest: bool,
+    upload_page: bool,
+    max_downloads_cap: u32,
+    file_listing: &'a FileListingConfig,
+    /// Only shown when it's just been generated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<&'a str>,
+}
+
+fn summary<'a>(
+    cfg: &'a Config,
+    name: &'a str,
+    namespace: &'a NamespaceDefinition,
+) -> NamespaceSummary<'a> {
+    let mut viewers: Vec<_> =
+        namespace.viewers.keys().map(String::as_str).collect();
+    viewers.sort();
+
+    NamespaceSummary {
+        name,
+        path: &namespace.path,
+        backend: namespace
+            .backend
+            .as_ref()
+            .unwrap_or(&cfg.storage.backend)
+            .kind(),
+        file: cfg
+            .namespace_files
+            .get(name)
+            .map(|file| file.display().to_string()),
+        default: namespace.path == cfg.storage.default_namespace_path,
+        viewers,
+        require_signed_urls: namespace.require_signed_urls,
+        end_to_end_encrypted: namespace.end_to_end_encrypted,
+        encrypted_at_rest: namespace.encryption_at_rest.enabled,
+        upload_page: namespace.upload_page,
+        max_downloads_cap: namespace.max_downloads_cap,
+        file_listing: &namespace.file_listing,
+        key: None,
+    }
+}
+
+/// Lists every namespace, sorted by name.
+pub async fn list_namespaces(cfg: Data<Config>) -> HttpResponse {
+    let mut names: Vec<_> = cfg.namespaces.keys().collect();
+    names.sort();
+
+    let namespaces: Vec<_> = names
+        .into_iter()
+        .map(|name| summary(&cfg, name, &cfg.namespaces[name]))
+        .collect();
+    HttpResponse::Ok().json(namespaces)
+}
+
+pub async fn get_namespace(
+    cfg: Data<Config>,
+    name: web::Path<String>,
+) -> HttpResponse {
+    match cfg.namespaces.get(name.as_str()) {
+        Some(namespace) => {
+            HttpResponse::Ok().json(summary(&cfg, &name, namespace))
+        }
+        None => not_found(),
+    }
+}
+
+/// Adds a namespace with the settings in the body, responding with its key.
+pub async fn create_namespace(
+    cfg: Data<Config>,
+    admin: Data<AdminContext>,
+    name: web::Path<String>,
+    Json(settings): Json<Map<String, Value>>,
+) -> HttpResponse {
+    let name = name.into_inner();
+    match change(admin, name.clone(), Change::Create(settings)).await {
+        Ok(Changed {
+            definition: Some(definition),
+            key,
+        }) => {
+            let mut summary = summary(&cfg, &name, &definition);
+            summary.key = key.as_deref();
+            HttpResponse::Created().json(summary)
+        }
+        Ok(_) => unreachable!("a created namespace should exist"),
+        Err(response) => response,
+    }
+}
+
+/// Merges the settings in the body into the namespace's, removing the ones
+/// that are `null`.
+pub async fn update_namespace(
+    cfg: Data<Config>,
+    admin: Data<AdminContext>,
+    name: web::Path<String>,
+    Json(settings): Json<Map<String, Value>>,
+) -> HttpResponse {
+    updated(cfg, admin, name.into_inner(), settings).await
+}
+
+/// Changes the namespace's `file_listing` settings, the same way as
+/// [`update_namespace`].
+pub async fn update_file_listing(
+    cfg: Data<Config>,
+    admin: Data<AdminContext>,
+    name: web::Path<String>,
+    Json(settings): Json<Map<String, Value>>,
+) -> HttpResponse {
+    let settings =
+        Map::from_iter([("file_listing".to_string(), Value::Object(settings))]);
+    updated(cfg, admin, name.into_inner(), settings).await
+}
+
+async fn updated(
+    cfg: Data<Config>,
+    admin: Data<AdminContext>,
+    name: String,
+    settings: Map<String, Value>,
+) -> HttpResponse {
+    match change(admin, name.clone(), Change::Update(settings)).await {
+        Ok(Changed {
+            definition: Some(definition),
+            ..
+        }) => HttpResponse::Ok().json(summary(&cfg, &name, &definition)),
+        Ok(_) => unreachable!("an updated namespace should exist"),
+        Err(response) => response,
+    }
+}
+
+/// Removes the namespace from the configuration. Its files are left where
+/// they are.
+pub async fn delete_namespace(
+    admin: Data<AdminContext>,
+    name: web::Path<String>,
+) -> HttpResponse {
+    match change(admin, name.into_inner(), Change::Delete).await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(response) => response,
+    }
+}
+
+/// Gives the namespace a new upload key, responding with it. The old one
+/// stops working once the configuration has been reloaded.
+pub async fn rotate_key(
+    admin: Data<AdminContext>,
+    name: web::Path<String>,
+) -> HttpResponse {
+    match change(admin, name.into_inner(), Change::RotateKey).await {
+        Ok(Changed { key: Some(key), .. }) => {
+            HttpResponse::Ok().json(json!({ "key": key }))
+        }
+        Ok(_) => unreachable!("rotating a key should generate one"),
+        Err(response) => response,
+    }
+}
+
+/// Makes `change` to the namespace `name`, or returns the response
+/// explaining why it couldn't.
+async fn change(
+    admin: Data<AdminContext>,
+    name: String,
+    change: Change,
+) -> Result<Changed, HttpResponse> {
+    super::change(admin, name, change)
+        .await
+        .map_err(|e| match e {
+            EditError::NotFound => not_found(),
+            EditError::Exists | EditError::NotEditable(_) => {
+                HttpResponse::Conflict().json(json!({ "error": e.to_string() }))
+            }
+            EditError::Invalid(ref problems) => {
+                HttpResponse::UnprocessableEntity().json(json!({
+                    "error": e.to_string(),
+                    "problems": problems,
+                }))
+            }
+            EditError::Config(_) | EditError::Interrupted => {
+                HttpResponse::InternalServerError()
+                    .json(json!({ "error": e.to_string() }))
+            }
+        })
+}
+
+fn not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(json!({ "error": "No such namespace" }))
+}
@@ -0,0 +1,329 @@
+//! Changing namespaces in the files they're defined in.
+//!
+//! Edits are made to the file as it's written, without environment
+//! overrides, so they don't end up saved into it. A TOML file keeps its
+//! formatting and comments. Nothing is written unless the configuration
+//! would still be valid afterwards.
+
+use std::{fs, path::Path};
+
+use serde_json::{Map, Value};
+use thiserror::Error;
+use toml_edit::{DocumentMut, Item, Table, TableLike};
+
+use crate::config::{self, Config, ConfigError, NamespaceDefinition, format};
+
+/// What to do to a namespace.
+pub enum Change {
+    /// Adds it to the configuration file with these settings. It's stored
+    /// under its name and given a new key unless they say otherwise.
+    Create(Map<String, Value>),
+    /// Merges these settings into its own, removing any that are `null`.
+    Update(Map<String, Value>),
+    Delete,
+    /// Replaces its key with a new one.
+    RotateKey,
+}
+
+/// A namespace as it is after a change.
+pub struct Changed {
+    pub definition: Option<NamespaceDefinition>,
+    /// Its key, if the change generated it.
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum EditError {
+    #[error("No such namespace")]
+    NotFound,
+    #[error("Namespace already exists")]
+    Exists,
+    /// It's defined somewhere the change can't be saved to, like the
+    /// environment.
+    #[error("Namespace can't be changed: {0}")]
+    NotEditable(String),
+    /// The configuration wouldn't be valid after the change.
+    #[error("The configuration wouldn't be valid")]
+    Invalid(Vec<String>),
+    #[error("Failed to change configuration")]
+    Config(Box<ConfigError>),
+    #[error("Failed to change configuration")]
+    Interrupted,
+}
+
+impl From<ConfigError> for EditError {
+    fn from(e: ConfigError) -> Self {
+        EditError::Config(Box::new(e))
+    }
+}
+
+/// Where the key of the namespace `name` comes from, when it's somewhere
+/// other than the file and so can't be rotated.
+pub fn external_key_source(
+    name: &str,
+    namespace: &NamespaceDefinition,
+) -> Option<String> {
+    if !namespace.key_file.is_empty() {
+        Some(format!("it's read from `{}`", namespace.key_file))
+    } else if !namespace.key_env.is_empty() {
+        Some(format!("it's read from `{}`", namespace.key_env))
+    } else {
+        config::override_variable(&["namespaces", name, "key"])
+            .map(|variable| format!("it's set by `{}`", variable))
+    }
+}
+
+/// Makes `change` to the namespace `name` of the configuration at
+/// `config_path`, and saves it.
+pub fn apply(
+    config_path: &Path,
+    name: &str,
+    change: Change,
+) -> Result<Changed, EditError> {
+    // read afresh, as the configuration being served lags behind the file
+    // until it's reloaded, or for good if reloading it failed
+    let main = format::read(config_path)?;
+    let config = Config::from_document(config_path, main.clone())?;
+
+    let exists = config.namespaces.contains_key(name);
+    match change {
+        Change::Create(_) if exists => return Err(EditError::Exists),
+        Change::Create(_) => {}
+        _ if !exists => return Err(EditError::NotFound),
+        _ => {}
+    }
+
+    // namespaces from the namespace directories are the whole of their file
+    let own_file = config.namespace_files.get(name);
+    let file = own_file.map_or(config_path, |file| file.as_path());
+    let mut document = match own_file {
+        Some(file) => format::read(file)?,
+        None => main,
+    };
+
+    let table = match change {
+        Change::Create(_) => config::add_namespace(&mut document, name)?,
+        _ => namespace_table(&mut document, own_file.is_some(), name)
+            .ok_or_else(|| {
+                EditError::NotEditable(format!(
+                    "`{}` isn't defined in `{}`",
+                    name,
+                    file.display()
+                ))
+            })?,
+    };
+
+    let mut key = None;
+    match &change {
+        Change::Create(settings) => {
+            merge(table, settings).map_err(|e| EditError::Invalid(vec![e]))?;
+            key = table.get("key").and_then(Item::as_str).map(String::from);
+        }
+        Change::Update(settings) => {
+            merge(table, settings).map_err(|e| EditError::Invalid(vec![e]))?
+        }
+        Change::Delete => {}
+        Change::RotateKey => {
+            if let Some(source) =
+                external_key_source(name, &config.namespaces[name])
+            {
+                return Err(EditError::NotEditable(format!(
+                    "its key can't be rotated, as {}",
+                    source
+                )));
+            }
+
+            let new_key = config::generate_upload_key();
+            table.insert("key", toml_edit::value(&new_key));
+            key = Some(new_key);
+        }
+    }
+
+    let definition = match change {
+        Change::Delete => None,
+        _ => Some(read_definition(name, table)?),
+    };
+
+    if definition.is_none() && own_file.is_none() {
+        document
+            .get_mut("namespaces")
+            .and_then(Item::as_table_like_mut)
+            .map(|namespaces| namespaces.remove(name));
+    }
+
+    let problems = match own_file {
+        // the configuration file as it would be written
+        None => match Config::from_document(config_path, document.clone()) {
+            Ok(_) => Vec::new(),
+            Err(ConfigError::Invalid { problems, .. }) => problems,
+            Err(e) => return Err(e.into()),
+        },
+        Some(_) => {
+            let mut candidate = config.clone();
+            match &definition {
+                Some(definition) => {
+                    candidate
+                        .namespaces
+                        .insert(name.to_string(), definition.clone());
+                }
+                None => {
+                    candidate.namespaces.remove(name);
+                }
+            }
+            candidate.problems()
+        }
+    };
+    if !problems.is_empty() {
+        return Err(EditError::Invalid(
+            problems.iter().map(ToString::to_string).collect(),
+        ));
+    }
+
+    match (&definition, own_file) {
+        (None, Some(file)) => {
+            fs::remove_file(file).map_err(|e| ConfigError::IoWrite {
+                path: file.display().to_string(),
+                source: e,
+            })?;
+        }
+        _ => format::write_document(file, &document)?,
+    }
+
+    Ok(Changed { definition, key })
+}
+
+/// The table the namespace `name` is defined by in `document`. A namespace
+/// in a file of its own is the whole document.
+fn namespace_table<'a>(
+    document: &'a mut DocumentMut,
+    own_file: bool,
+    name: &str,
+) -> Option<&'a mut dyn TableLike> {
+    if own_file {
+        return Some(document.as_table_mut());
+    }
+
+    document
+        .get_mut("namespaces")?
+        .as_table_like_mut()?
+        .get_mut(name)?
+        .as_table_like_mut()
+}
+
+/// Merges the JSON `settings` into `table`, removing those that are `null`.
+fn merge(
+    table: &mut dyn TableLike,
+    settings: &Map<String, Value>,
+) -> Result<(), String> {
+    for (key, setting) in settings {
+        match setting {
+            Value::Null => {
+                table.remove(key);
+            }
+            Value::Object(settings) => {
+                if !table.get(key).is_some_and(Item::is_table_like) {
+                    table.insert(key, Item::Table(Table::new()));
+                }
+                let nested = table
+                    .get_mut(key)
+                    .and_then(Item::as_table_like_mut)
+                    .expect("should have just been made a table");
+                merge(nested, settings)
+                    .map_err(|e| format!("{}: {}", key, e))?;
+            }
+            setting => {
+                table.insert(key, Item::Value(toml_value(key, setting)?));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn toml_value(key: &str, value: &Value) -> Result<toml_edit::Value, String> {
+    let invalid = |reason: String| format!("{}: {}", key, reason);
+    toml::Value::try_from(value)
+        .map_err(|e| invalid(e.to_string()))?
+        .to_string()
+        .parse()
+        .map_err(|e: toml_edit::TomlError| invalid(e.to_string()))
+}
+
+/// Reads the namespace `name` from `table`, refusing settings that aren't
+/// recognised rather than saving them.
+fn read_definition(
+    name: &str,
+    table: &dyn TableLike,
+) -> Result<NamespaceDefinition, EditError> {
+    let mut document = DocumentMut::new();
+    for (key, item) in table.iter() {
+        document.insert(key, item.clone());
+    }
+    let content = document.to_string();
+
+    let mut unknown = Vec::new();
+    let definition: Result<NamespaceDefinition, _> = serde_ignored::deserialize(
+        toml::Deserializer::new(&content),
+        |setting| unknown.push(format!("unknown setting `{}`", setting)),
+    );
+    let mut definition =
+        definition.map_err(|e| EditError::Invalid(vec![e.message().into()]))?;
+    if !unknown.is_empty() {
+        return Err(EditError::Invalid(unknown));
+    }
+
+    let problems: Vec<String> = [
+        definition.load_key(name).err(),
+        definition.encryption_at_rest.load_key(name).err(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|e| e.to_string())
+    .collect();
+    if !problems.is_empty() {
+        return Err(EditError::Invalid(problems));
+    }
+
+    Ok(definition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "[namespaces.ferris]\n\
+                          path = \"ferris\"\n\
+                          key = \"k\"\n";
+
+    fn create(config_path: &Path, name: &str) -> Result<Changed, EditError> {
+        apply(config_path, name, Change::Create(Map::new()))
+    }
+
+    #[test]
+    fn creates_a_namespace_only_once() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("config.toml");
+        fs::write(&path, CONFIG).unwrap();
+
+        // the second one comes before any reload
+        let created = create(&path, "cats").unwrap();
+        assert!(matches!(create(&path, "cats"), Err(EditError::Exists)));
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.namespaces.len(), 2);
+        assert_eq!(Some(&config.namespaces["cats"].key), created.key.as_ref());
+    }
+
+    #[test]
+    fn refuses_namespaces_the_config_would_not_load_with() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("config.toml");
+        fs::write(&path, CONFIG).unwrap();
+
+        assert!(matches!(
+            create(&path, "upload"),
+            Err(EditError::Invalid(_))
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), CONFIG);
+    }
+}
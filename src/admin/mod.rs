@@ -0,0 +1,177 @@
+//! Managing the server while it's running: an API for namespaces, and pages
+//! for moderating what's been uploaded.
+//!
+//! API requests carry `admin.token` as a bearer token, while the pages are
+//! signed in to with one of `admin.users`. Changes to namespaces are saved
+//! to the file the namespace is defined in, and the configuration is
+//! reloaded to apply them.
+
+mod api;
+mod edit;
+mod session;
+mod ui;
+
+use std::{path::PathBuf, sync::Mutex, time::Instant};
+
+use actix_web::{
+    Error, HttpMessage, HttpResponse,
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web::{self, Data},
+};
+use log::{error, info};
+use rand::RngCore;
+use serde_json::json;
+
+use crate::{config::Config, reload::Reloader};
+use edit::{Change, Changed, EditError};
+
+pub use api::{
+    create_namespace, delete_namespace, get_namespace, list_namespaces,
+    rotate_key, update_file_listing, update_namespace,
+};
+pub use ui::{
+    dashboard, delete_file, log_in, log_out, login_page, namespace_page,
+    rotate_key_page, update_file_listing_page,
+};
+
+/// What the admin API and pages need to save and apply changes, which lasts
+/// as long as the process.
+pub struct AdminContext {
+    pub config_path: PathBuf,
+    pub reloader: Reloader,
+    /// Held while a file is changed, so two changes can't overwrite each
+    /// other.
+    pub lock: Mutex<()>,
+    /// Signs session cookies, so restarting the server signs everyone out.
+    session_key: [u8; 32],
+    started: Instant,
+}
+
+impl AdminContext {
+    pub fn new(config_path: PathBuf, reloader: Reloader) -> AdminContext {
+        let mut session_key = [0u8; 32];
+        rand::rng().fill_bytes(&mut session_key);
+
+        AdminContext {
+            config_path,
+            reloader,
+            lock: Mutex::new(()),
+            session_key,
+            started: Instant::now(),
+        }
+    }
+}
+
+/// The admin signed in to the admin pages.
+#[derive(Clone)]
+pub struct AdminUser(pub String);
+
+/// Middleware refusing requests without the admin token. The admin API
+/// doesn't exist at all while `admin.token` is empty.
+pub async fn require_token<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let admin = match req.app_data::<Data<Config>>() {
+        Some(cfg) => cfg.admin.clone(),
+        None => return next.call(req).await.map(|r| r.map_into_left_body()),
+    };
+
+    if !admin.enabled() {
+        let response = HttpResponse::NotFound().body("Not found");
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, token)| token.trim());
+
+    if !token.is_some_and(|token| admin.authorizes(token)) {
+        let response = HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+            .json(json!({ "error": "Failed to authenticate" }));
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    next.call(req).await.map(|r| r.map_into_left_body())
+}
+
+/// Middleware sending requests without a session to the login page, and
+/// making the signed in [`AdminUser`] available to the rest. The admin pages
+/// don't exist at all while `admin.users` is empty.
+pub async fn require_login<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let cfg = req.app_data::<Data<Config>>().cloned();
+    let context = req.app_data::<Data<AdminContext>>().cloned();
+    let (cfg, context) = match (cfg, context) {
+        (Some(cfg), Some(context)) => (cfg, context),
+        _ => return next.call(req).await.map(|r| r.map_into_left_body()),
+    };
+
+    if !cfg.admin.has_users() {
+        let response = HttpResponse::NotFound().body("Not found");
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    match session::user(req.request(), &context.session_key, &cfg.admin) {
+        Some(username) => {
+            req.extensions_mut().insert(AdminUser(username));
+            next.call(req).await.map(|r| r.map_into_left_body())
+        }
+        None => {
+            let response = HttpResponse::SeeOther()
+                .insert_header((header::LOCATION, page_path(&cfg, "login")))
+                .finish();
+            Ok(req.into_response(response).map_into_right_body())
+        }
+    }
+}
+
+/// The path of `page` in the admin pages as links and redirects need it,
+/// under the path of `listen_url`, which a reverse proxy may serve the server
+/// at.
+fn page_path(cfg: &Config, page: &str) -> String {
+    cfg.web_server
+        .listen_url
+        .join(&format!("_admin/{}", page))
+        .expect("should be able to join with an admin page")
+        .path()
+        .to_string()
+}
+
+/// Makes `change` to the namespace `name`, saving it and reloading the
+/// configuration.
+async fn change(
+    admin: Data<AdminContext>,
+    name: String,
+    change: Change,
+) -> Result<Changed, EditError> {
+    let changed = web::block(move || {
+        let _lock = admin.lock.lock().expect("should not be poisoned");
+        let changed = edit::apply(&admin.config_path, &name, change);
+        if changed.is_ok() {
+            info!("Saved changes to namespace `{}`", name);
+            admin.reloader.reload();
+        }
+        changed
+    })
+    .await
+    .unwrap_or_else(|e| {
+        error!("Failed to change configuration: {}", e);
+        Err(EditError::Interrupted)
+    });
+
+    if let Err(EditError::Config(e)) = &changed {
+        error!("Failed to change configuration: {}", e);
+    }
+    changed
+}
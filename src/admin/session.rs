@@ -0,0 +1,117 @@
+//! Sessions of the admin pages.
+//!
+//! A session is a cookie holding who signed in and when it expires, signed
+//! along with their password, so changing it signs them out everywhere.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::{
+    HttpRequest,
+    cookie::{Cookie, SameSite, time},
+};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::AdminConfig;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const COOKIE_NAME: &str = "mediaserver_admin";
+
+/// A cookie signing `username` in for `admin.session_lifetime_seconds`, sent
+/// back only to the admin pages at `path`.
+pub fn cookie(
+    key: &[u8],
+    admin: &AdminConfig,
+    username: &str,
+    path: &str,
+    secure: bool,
+) -> Cookie<'static> {
+    // saturating, as `session_lifetime_seconds` may be set to anything
+    let expires = now().saturating_add(admin.session_lifetime_seconds);
+    let password = admin.users.get(username).map_or("", String::as_str);
+    let signature = BASE64_URL_SAFE_NO_PAD.encode(
+        mac(key, username, password, expires)
+            .finalize()
+            .into_bytes(),
+    );
+
+    Cookie::build(
+        COOKIE_NAME,
+        format!(
+            "{}.{}.{}",
+            BASE64_URL_SAFE_NO_PAD.encode(username),
+            expires,
+            signature
+        ),
+    )
+    .path(path.to_string())
+    .http_only(true)
+    .secure(secure)
+    // also keeps other sites from submitting the pages' forms
+    .same_site(SameSite::Strict)
+    .max_age(time::Duration::seconds(
+        admin
+            .session_lifetime_seconds
+            .try_into()
+            .unwrap_or(i64::MAX),
+    ))
+    .finish()
+}
+
+/// A cookie replacing the session one for the admin pages at `path`,
+/// signing out.
+pub fn removal_cookie(path: &str, secure: bool) -> Cookie<'static> {
+    let mut cookie = Cookie::build(COOKIE_NAME, "")
+        .path(path.to_string())
+        .http_only(true)
+        .secure(secure)
+        .same_site(SameSite::Strict)
+        .finish();
+    cookie.make_removal();
+    cookie
+}
+
+/// Who `req` is signed in as, if its session is valid.
+pub fn user(
+    req: &HttpRequest,
+    key: &[u8],
+    admin: &AdminConfig,
+) -> Option<String> {
+    let cookie = req.cookie(COOKIE_NAME)?;
+    let mut parts = cookie.value().splitn(3, '.');
+    let username = BASE64_URL_SAFE_NO_PAD.decode(parts.next()?).ok()?;
+    let username = String::from_utf8(username).ok()?;
+    let expires: u64 = parts.next()?.parse().ok()?;
+    let signature = BASE64_URL_SAFE_NO_PAD.decode(parts.next()?).ok()?;
+
+    if expires < now() {
+        return None;
+    }
+    let password = admin.users.get(&username)?;
+
+    mac(key, &username, password, expires)
+        .verify_slice(&signature)
+        .is_ok()
+        .then_some(username)
+}
+
+fn mac(key: &[u8], username: &str, password: &str, expires: u64) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    for part in [username.as_bytes(), password.as_bytes()] {
+        // lengths first, so no two sessions sign the same bytes
+        mac.update(&(part.len() as u64).to_be_bytes());
+        mac.update(part);
+    }
+    mac.update(&expires.to_be_bytes());
+    mac
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time should not go backwards")
+        .as_secs()
+}
@@ -0,0 +1,825 @@
+use std::{cmp::Reverse, io, time::Duration};
+
+use actix_web::{
+    HttpRequest, HttpResponse,
+    http::{StatusCode, header},
+    web::{self, Data, Form, Query, ReqData},
+};
+use bytesize::ByteSize;
+use log::{error, info, warn};
+use num_format::{Locale, ToFormattedString};
+use percent_encoding::utf8_percent_encode;
+use serde::Deserialize;
+use serde_json::{Map, json};
+use url::Url;
+
+use super::{
+    AdminContext, AdminUser,
+    edit::{self, Change, EditError},
+    page_path, session,
+};
+use crate::{
+    access,
+    config::{Config, NamespaceDefinition},
+    downloads::DownloadLimits,
+    media::MediaLibrary,
+    mirror::Mirror,
+    render::{
+        ListingCache, PATH_SEGMENT, individual_listing, render_page,
+        template::{
+            AdminDashboard, AdminFileRow, AdminLogin, AdminNamespace,
+            AdminNamespaceRow,
+        },
+    },
+    storage::{self, Entry, NamespaceStorage, Storage, join_key},
+};
+
+/// How many of the most recent uploads the dashboard shows.
+const RECENT_UPLOADS: usize = 24;
+
+/// How many of a namespace's files its page shows, most recent first.
+const NAMESPACE_FILES: usize = 500;
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteForm {
+    /// The file's path in the namespace.
+    path: String,
+    /// The page to go back to afterwards.
+    return_to: String,
+}
+
+/// Checkboxes, which are only sent when they're ticked.
+#[derive(Deserialize)]
+pub struct FileListingForm {
+    show: Option<String>,
+    use_fancy_renderer: Option<String>,
+    archive_downloads: Option<String>,
+}
+
+/// What a page was sent back to after deleting a file.
+#[derive(Deserialize)]
+pub struct DeletedQuery {
+    deleted: Option<String>,
+    failed: Option<String>,
+}
+
+/// A message shown at the top of a page.
+#[derive(Default)]
+struct Notice {
+    text: String,
+    is_error: bool,
+    problems: Vec<String>,
+    /// A key that's just been generated, which is only ever shown once.
+    new_key: String,
+}
+
+impl Notice {
+    fn info(text: String) -> Notice {
+        Notice {
+            text,
+            ..Notice::default()
+        }
+    }
+
+    fn error(e: EditError) -> Notice {
+        let problems = match &e {
+            EditError::Invalid(problems) => problems.clone(),
+            _ => Vec::new(),
+        };
+        Notice {
+            text: e.to_string(),
+            is_error: true,
+            problems,
+            ..Notice::default()
+        }
+    }
+
+    fn of_deletion(query: DeletedQuery) -> Notice {
+        match (query.deleted, query.failed) {
+            (_, Some(failed)) => Notice {
+                text: format!("Failed to delete `{}`", failed),
+                is_error: true,
+                ..Notice::default()
+            },
+            (Some(deleted), None) => {
+                Notice::info(format!("Deleted `{}`", deleted))
+            }
+            (None, None) => Notice::default(),
+        }
+    }
+}
+
+pub async fn login_page(cfg: Data<Config>) -> HttpResponse {
+    if !cfg.admin.has_users() {
+        return HttpResponse::NotFound().body("Not found");
+    }
+
+    render_page(&AdminLogin {
+        username: "",
+        notice: "",
+        notice_is_error: false,
+        problems: &[],
+        admin_href: &page_path(&cfg, ""),
+    })
+}
+
+pub async fn log_in(
+    cfg: Data<Config>,
+    admin: Data<AdminContext>,
+    Form(form): Form<LoginForm>,
+) -> HttpResponse {
+    if !cfg.admin.has_users() {
+        return HttpResponse::NotFound().body("Not found");
+    }
+
+    if !cfg.admin.authenticates(&form.username, &form.password) {
+        // debug-formatted, so a username can't forge lines in the log
+        warn!("Failed admin sign in as {:?}", form.username);
+        let mut response = render_page(&AdminLogin {
+            username: "",
+            notice: "Wrong username or password",
+            notice_is_error: true,
+            problems: &[],
+            admin_href: &page_path(&cfg, ""),
+        });
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        return response;
+    }
+
+    info!("`{}` signed in to the admin pages", form.username);
+    HttpResponse::SeeOther()
+        .cookie(session::cookie(
+            &admin.session_key,
+            &cfg.admin,
+            &form.username,
+            &cookie_path(&cfg),
+            is_secure(&cfg),
+        ))
+        .insert_header((header::LOCATION, page_path(&cfg, "")))
+        .finish()
+}
+
+pub async fn log_out(cfg: Data<Config>) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .cookie(session::removal_cookie(&cookie_path(&cfg), is_secure(&cfg)))
+        .insert_header((header::LOCATION, page_path(&cfg, "login")))
+        .finish()
+}
+
+/// How the server is doing, every namespace with how much it holds, and the
+/// most recent uploads.
+#[allow(clippy::too_many_arguments)]
+pub async fn dashboard(
+    cfg: Data<Config>,
+    storage: Data<Storage>,
+    admin: Data<AdminContext>,
+    mirror: Data<Mirror>,
+    media: Data<MediaLibrary>,
+    limits: Data<DownloadLimits>,
+    user: ReqData<AdminUser>,
+    Query(query): Query<DeletedQuery>,
+) -> HttpResponse {
+    let mut names: Vec<String> = cfg.namespaces.keys().cloned().collect();
+    names.sort();
+
+    let listed = {
+        let storage = storage.clone();
+        let names = names.clone();
+        web::block(move || {
+            names
+                .iter()
+                .map(|name| {
+                    storage
+                        .namespace(name)
+                        .ok_or_else(|| io::Error::other("it has no storage"))
+                        .and_then(stored_files)
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+    };
+    let listed = match listed {
+        Ok(listed) => listed,
+        Err(e) => {
+            error!("Failed to list stored files: {}", e);
+            return HttpResponse::InternalServerError()
+                .body("Failed to list stored files");
+        }
+    };
+
+    let mut rows = Vec::with_capacity(names.len());
+    let mut recent = Vec::new();
+    let (mut total_files, mut total_size) = (0, 0);
+
+    for (name, files) in names.iter().zip(listed) {
+        let namespace = &cfg.namespaces[name];
+        let mut row = AdminNamespaceRow {
+            name: name.clone(),
+            path: namespace.path.clone(),
+            backend: namespace
+                .backend
+                .as_ref()
+                .unwrap_or(&cfg.storage.backend)
+                .kind(),
+            file_count: String::new(),
+            usage: String::new(),
+            flags: cfg.namespace_flags(name).join(", "),
+            error: String::new(),
+        };
+
+        match files {
+            Ok(files) => {
+                let size: u64 = files.iter().map(|(_, entry)| entry.size).sum();
+                row.file_count = files.len().to_formatted_string(&Locale::en);
+                row.usage = ByteSize::b(size).display().iec().to_string();
+                total_files += files.len();
+                total_size += size;
+                recent.extend(files.into_iter().map(|file| (name, file)));
+            }
+            Err(e) => {
+                error!("Failed to list files of `{}`: {}", name, e);
+                row.error = "Failed to list files".to_string();
+            }
+        }
+        rows.push(row);
+    }
+
+    recent.sort_by_key(|(_, (_, entry))| Reverse(entry.modified));
+    let recent_uploads: Vec<_> = recent
+        .into_iter()
+        .take(RECENT_UPLOADS)
+        .filter_map(|(name, (key, entry))| {
+            let storage = storage.namespace(name)?;
+            Some(file_row(&cfg, &media, &limits, storage, name, key, entry))
+        })
+        .collect();
+
+    let mirror = mirror.status();
+    let mut health = vec![
+        ("Version", env!("CARGO_PKG_VERSION").to_string(), true),
+        ("Uptime", uptime(admin.started.elapsed()), true),
+        (
+            "Configuration",
+            admin.config_path.display().to_string(),
+            true,
+        ),
+        (
+            "Stored",
+            format!(
+                "{} files, {} in {} namespace{}",
+                total_files.to_formatted_string(&Locale::en),
+                ByteSize::b(total_size).display().iec(),
+                names.len(),
+                if names.len() == 1 { "" } else { "s" }
+            ),
+            rows.iter().all(|row| row.error.is_empty()),
+        ),
+        (
+            "Mirror",
+            if mirror.enabled {
+                format!(
+                    "{} pending, {} failing, {}s behind",
+                    mirror.pending, mirror.failing, mirror.lag_seconds
+                )
+            } else {
+                "Off".to_string()
+            },
+            mirror.failing == 0,
+        ),
+    ];
+    if let Some(last_error) = mirror.last_error.filter(|_| mirror.enabled) {
+        health.push(("Last mirror error", last_error, mirror.failing == 0));
+    }
+    health.push((
+        "Listing cache",
+        on_off(cfg.file_listing_render.cache.enabled),
+        true,
+    ));
+    health.push(("Admin API", on_off(cfg.admin.enabled()), true));
+
+    let notice = Notice::of_deletion(query);
+    let admin_href = page_path(&cfg, "");
+    render_page(&AdminDashboard {
+        username: &user.0,
+        notice: &notice.text,
+        notice_is_error: notice.is_error,
+        problems: &notice.problems,
+        health: &health,
+        namespaces: &rows,
+        recent_uploads: &recent_uploads,
+        show_namespace: true,
+        return_to: &admin_href,
+        admin_href: &admin_href,
+    })
+}
+
+/// A namespace's settings, key and files.
+pub async fn namespace_page(
+    cfg: Data<Config>,
+    storage: Data<Storage>,
+    media: Data<MediaLibrary>,
+    limits: Data<DownloadLimits>,
+    user: ReqData<AdminUser>,
+    name: web::Path<String>,
+    Query(query): Query<DeletedQuery>,
+) -> HttpResponse {
+    let Some(definition) = cfg.namespaces.get(name.as_str()) else {
+        return HttpResponse::NotFound().body("Not found");
+    };
+
+    render_namespace(
+        &cfg,
+        &storage,
+        &media,
+        &limits,
+        &user.0,
+        &name,
+        definition,
+        Notice::of_deletion(query),
+    )
+    .await
+}
+
+/// Gives the namespace a new key, showing it once.
+pub async fn rotate_key_page(
+    cfg: Data<Config>,
+    storage: Data<Storage>,
+    admin: Data<AdminContext>,
+    media: Data<MediaLibrary>,
+    limits: Data<DownloadLimits>,
+    user: ReqData<AdminUser>,
+    name: web::Path<String>,
+) -> HttpResponse {
+    let Some(definition) = cfg.namespaces.get(name.as_str()) else {
+        return HttpResponse::NotFound().body("Not found");
+    };
+
+    let changed =
+        super::change(admin, name.to_string(), Change::RotateKey).await;
+    let notice = match changed {
+        Ok(changed) => {
+            info!("`{}` rotated the key of `{}`", user.0, name);
+            Notice {
+                new_key: changed.key.unwrap_or_default(),
+                ..Notice::default()
+            }
+        }
+        Err(e) => Notice::error(e),
+    };
+
+    render_namespace(
+        &cfg, &storage, &media, &limits, &user.0, &name, definition, notice,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn update_file_listing_page(
+    cfg: Data<Config>,
+    storage: Data<Storage>,
+    admin: Data<AdminContext>,
+    media: Data<MediaLibrary>,
+    limits: Data<DownloadLimits>,
+    user: ReqData<AdminUser>,
+    name: web::Path<String>,
+    Form(form): Form<FileListingForm>,
+) -> HttpResponse {
+    if !cfg.namespaces.contains_key(name.as_str()) {
+        return HttpResponse::NotFound().body("Not found");
+    }
+
+    let settings = Map::from_iter([(
+        "file_listing".to_string(),
+        json!({
+            "show": form.show.is_some(),
+            "use_fancy_renderer": form.use_fancy_renderer.is_some(),
+            "archive_downloads": form.archive_downloads.is_some(),
+        }),
+    )]);
+    let changed =
+        super::change(admin, name.to_string(), Change::Update(settings)).await;
+
+    // show what was saved rather than what's still being served
+    let (notice, definition) = match changed {
+        Ok(changed) => (
+            Notice::info("Saved; it takes effect in a moment".to_string()),
+            changed.definition,
+        ),
+        Err(e) => (Notice::error(e), None),
+    };
+    let definition = definition
+        .as_ref()
+        .unwrap_or_else(|| &cfg.namespaces[name.as_str()]);
+
+    render_namespace(
+        &cfg, &storage, &media, &limits, &user.0, &name, definition, notice,
+    )
+    .await
+}
+
+/// Deletes a file, along with its copy in the mirror and anything cached
+/// about it, then goes back to the page it was deleted from.
+#[allow(clippy::too_many_arguments)]
+pub async fn delete_file(
+    cfg: Data<Config>,
+    storage: Data<Storage>,
+    listing_cache: Data<ListingCache>,
+    mirror: Data<Mirror>,
+    req: HttpRequest,
+    user: ReqData<AdminUser>,
+    name: web::Path<String>,
+    Form(form): Form<DeleteForm>,
+) -> HttpResponse {
+    let (Some(storage), Some(key)) =
+        (storage.namespace(&name), storage::sanitize_key(&form.path))
+    else {
+        return HttpResponse::NotFound().body("Not found");
+    };
+    let storage = storage.clone();
+
+    // only ever back to an admin page
+    let admin_href = page_path(&cfg, "");
+    let return_to = if form.return_to.starts_with(&admin_href) {
+        form.return_to.as_str()
+    } else {
+        admin_href.as_str()
+    };
+    let encoded_key: String =
+        url::form_urlencoded::byte_serialize(key.as_bytes()).collect();
+
+    let deleted = {
+        let storage = storage.clone();
+        let key = key.clone();
+        web::block(move || storage.delete(&key)).await
+    };
+    let location = match deleted {
+        Ok(Ok(_)) => {
+            let backend_key = storage.backend_key(&key);
+            info!("`{}` deleted {}", user.0, backend_key);
+            mirror.delete(&name, &key);
+            if let Some(media) = req.app_data::<Data<MediaLibrary>>() {
+                media.remove(&backend_key);
+            }
+            if let Some(limits) = req.app_data::<Data<DownloadLimits>>() {
+                limits.remove(&backend_key);
+            }
+            listing_cache.invalidate_path(&backend_key);
+            format!("{}?deleted={}", return_to, encoded_key)
+        }
+        Ok(Err(e)) => {
+            error!("Failed to delete {}: {}", storage.backend_key(&key), e);
+            format!("{}?failed={}", return_to, encoded_key)
+        }
+        Err(e) => {
+            error!("Failed to delete {}: {}", storage.backend_key(&key), e);
+            format!("{}?failed={}", return_to, encoded_key)
+        }
+    };
+
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn render_namespace(
+    cfg: &Data<Config>,
+    storage: &Data<Storage>,
+    media: &MediaLibrary,
+    limits: &DownloadLimits,
+    username: &str,
+    name: &str,
+    definition: &NamespaceDefinition,
+    mut notice: Notice,
+) -> HttpResponse {
+    let Some(namespace_storage) = storage.namespace(name).cloned() else {
+        return HttpResponse::NotFound().body("Not found");
+    };
+
+    let listed = {
+        let namespace_storage = namespace_storage.clone();
+        web::block(move || stored_files(&namespace_storage)).await
+    };
+    let mut files = match listed {
+        Ok(Ok(files)) => files,
+        Ok(Err(e)) => {
+            error!("Failed to list files of `{}`: {}", name, e);
+            if notice.text.is_empty() {
+                notice.text = "Failed to list files".to_string();
+                notice.is_error = true;
+            }
+            Vec::new()
+        }
+        Err(e) => {
+            error!("Failed to list files of `{}`: {}", name, e);
+            return HttpResponse::InternalServerError()
+                .body("Failed to list files");
+        }
+    };
+
+    let file_count = files.len().to_formatted_string(&Locale::en);
+    let usage = ByteSize::b(files.iter().map(|(_, entry)| entry.size).sum())
+        .display()
+        .iec()
+        .to_string();
+    let files_truncated = files.len() > NAMESPACE_FILES;
+
+    files.sort_by_key(|(_, entry)| Reverse(entry.modified));
+    let files: Vec<_> = files
+        .into_iter()
+        .take(NAMESPACE_FILES)
+        .map(|(key, entry)| {
+            file_row(cfg, media, limits, &namespace_storage, name, key, entry)
+        })
+        .collect();
+
+    let mut viewers: Vec<_> =
+        definition.viewers.keys().map(String::as_str).collect();
+    viewers.sort();
+    let settings = [
+        ("Path", definition.path.clone()),
+        (
+            "Backend",
+            definition
+                .backend
+                .as_ref()
+                .unwrap_or(&cfg.storage.backend)
+                .kind()
+                .to_string(),
+        ),
+        (
+            "Defined in",
+            cfg.namespace_files
+                .get(name)
+                .map(|file| file.display().to_string())
+                .unwrap_or_else(|| "the configuration file".to_string()),
+        ),
+        (
+            "Viewers",
+            if viewers.is_empty() {
+                "anyone (public)".to_string()
+            } else {
+                viewers.join(", ")
+            },
+        ),
+        ("Signed URLs only", yes_no(definition.require_signed_urls)),
+        (
+            "End-to-end encrypted",
+            yes_no(definition.end_to_end_encrypted),
+        ),
+        (
+            "Encrypted at rest",
+            yes_no(definition.encryption_at_rest.enabled),
+        ),
+        ("Upload page", on_off(definition.upload_page)),
+        (
+            "Download limit cap",
+            definition.max_downloads_cap.to_string(),
+        ),
+        (
+            "Image processing",
+            on_off(definition.image_processing.enabled),
+        ),
+        (
+            "Image transforms",
+            on_off(definition.image_transforms.enabled),
+        ),
+    ];
+
+    let key_source =
+        edit::external_key_source(name, definition).unwrap_or_default();
+    let admin_href = page_path(cfg, "");
+    let return_to = page_path(cfg, &format!("namespaces/{}", name));
+
+    render_page(&AdminNamespace {
+        username,
+        notice: &notice.text,
+        notice_is_error: notice.is_error,
+        problems: &notice.problems,
+        name,
+        settings: &settings,
+        key: &definition.key,
+        new_key: &notice.new_key,
+        key_source: &key_source,
+        file_listing: &definition.file_listing,
+        file_count: &file_count,
+        usage: &usage,
+        files: &files,
+        files_truncated,
+        show_namespace: false,
+        return_to: &return_to,
+        admin_href: &admin_href,
+    })
+}
+
+/// Every file in `storage` and its path, leaving out hidden ones.
+fn stored_files(
+    storage: &NamespaceStorage,
+) -> io::Result<Vec<(String, Entry)>> {
+    let mut files = Vec::new();
+    let mut directories = vec![String::new()];
+
+    while let Some(directory) = directories.pop() {
+        for entry in storage.list(&directory)? {
+            if entry.name.starts_with('.') {
+                continue;
+            }
+            let key = join_key(&directory, &entry.name);
+            if entry.is_dir {
+                directories.push(key);
+            } else {
+                files.push((key, entry));
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// A row for the file at `key` in the namespace `name`, with links that work
+/// whatever the namespace's access rules. Files with a download limit get
+/// neither a link nor a thumbnail, as loading them would use up downloads.
+fn file_row(
+    cfg: &Config,
+    media: &MediaLibrary,
+    limits: &DownloadLimits,
+    storage: &NamespaceStorage,
+    name: &str,
+    key: String,
+    entry: Entry,
+) -> AdminFileRow {
+    let definition = &cfg.namespaces[name];
+    let backend_key = storage.backend_key(&key);
+    let media_info = media.get(&backend_key);
+    let limit = limits.get(&backend_key);
+
+    let encoded_key: Vec<_> = key
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect();
+    let url = |prefix: &str| -> Url {
+        cfg.web_server
+            .listen_url
+            .join(&format!("{}{}/{}", prefix, name, encoded_key.join("/")))
+            .expect("should be able to join with a namespace and key")
+    };
+    // a signature gets past viewer credentials as well
+    let sign = |url: Url| -> String {
+        if definition.require_signed_urls || definition.requires_viewer_auth() {
+            access::sign_url(
+                cfg,
+                &url,
+                Duration::from_secs(cfg.signing.default_ttl_seconds),
+            )
+            .to_string()
+        } else {
+            url.to_string()
+        }
+    };
+
+    let is_image = mime_guess::from_path(&key)
+        .first()
+        .is_some_and(|mime| mime.type_() == "image");
+    let thumbnail_href = if definition.end_to_end_encrypted || limit.is_some() {
+        String::new()
+    } else if media_info.as_ref().is_some_and(|info| info.poster) {
+        sign(url("_poster/"))
+    } else if is_image {
+        let mut thumbnail = url("");
+        let transforms = &definition.image_transforms;
+        let width = transforms.widths.iter().copied().min();
+        if let Some(width) = width.filter(|_| transforms.enabled) {
+            thumbnail.set_query(Some(&format!("w={}", width)));
+        }
+        sign(thumbnail)
+    } else {
+        String::new()
+    };
+
+    let listing = individual_listing(cfg, entry, String::new());
+    AdminFileRow {
+        namespace: name.to_string(),
+        key,
+        emoji: listing.emoji,
+        timestamp: listing.timestamp,
+        byte_size: listing.byte_size,
+        media_summary: media_info
+            .map(|info| info.summary())
+            .unwrap_or_default(),
+        file_href: if limit.is_some() {
+            String::new()
+        } else {
+            sign(url(""))
+        },
+        thumbnail_href,
+        downloads_left: limit
+            .map(|limit| {
+                format!(
+                    "{} of {} downloads left",
+                    limit.max_downloads.saturating_sub(limit.downloads),
+                    limit.max_downloads
+                )
+            })
+            .unwrap_or_default(),
+    }
+}
+
+/// Where session cookies are sent back to, which is every admin page.
+fn cookie_path(cfg: &Config) -> String {
+    page_path(cfg, "").trim_end_matches('/').to_string()
+}
+
+/// Whether cookies should only be sent over HTTPS, going by the public URL.
+fn is_secure(cfg: &Config) -> bool {
+    cfg.web_server.listen_url.scheme() == "https"
+}
+
+fn uptime(elapsed: Duration) -> String {
+    let minutes = elapsed.as_secs() / 60;
+    match (minutes / (24 * 60), minutes / 60 % 24, minutes % 60) {
+        (0, 0, minutes) => format!("{}m", minutes),
+        (0, hours, minutes) => format!("{}h {}m", hours, minutes),
+        (days, hours, minutes) => format!("{}d {}h {}m", days, hours, minutes),
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+fn on_off(value: bool) -> String {
+    if value { "on" } else { "off" }.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use actix_web::{App, HttpMessage, test};
+
+    use super::*;
+    use crate::reload::Reloads;
+
+    #[actix_web::test]
+    async fn dashboard_leaves_limited_files_alone() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut cfg = Config::default();
+        cfg.storage.uploads_directory =
+            directory.path().join("uploads").display().to_string();
+        cfg.media.enabled = false;
+        cfg.web_server.listen_url = "http://localhost/media/".parse().unwrap();
+
+        let storage = Storage::new(&cfg);
+        cfg.create_uploads_directory(&storage).unwrap();
+        let namespace = storage.namespace("ferris").unwrap();
+        fs::write(namespace.local_path("a.png").unwrap(), b"png").unwrap();
+        let key = namespace.backend_key("a.png");
+
+        let limits = Data::new(
+            DownloadLimits::load(directory.path().join("downloads.json"))
+                .unwrap(),
+        );
+        limits.insert(key.clone(), 1).unwrap();
+        let media = Data::new(MediaLibrary::load(&cfg).unwrap());
+        let storage = Data::new(storage);
+        let mirror = Data::new(
+            Mirror::start(
+                &cfg,
+                storage.clone().into_inner(),
+                directory.path().join("mirror.json"),
+            )
+            .unwrap(),
+        );
+        let admin = Data::new(AdminContext::new(
+            directory.path().join("config.toml"),
+            Reloads::new().reloader(),
+        ));
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(cfg))
+                .app_data(storage)
+                .app_data(limits.clone())
+                .app_data(media)
+                .app_data(mirror)
+                .app_data(admin)
+                .route("/_admin/", web::get().to(dashboard)),
+        )
+        .await;
+        let req = test::TestRequest::get().uri("/_admin/").to_request();
+        req.extensions_mut().insert(AdminUser("admin".to_string()));
+        let body = test::call_and_read_body(&app, req).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.contains("1 of 1 downloads left"));
+        assert!(body.contains("/media/_admin/namespaces/ferris/delete"));
+        assert!(!body.contains("/ferris/a.png"));
+        assert_eq!(limits.get(&key).unwrap().downloads, 0);
+    }
+}
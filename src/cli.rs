@@ -0,0 +1,271 @@
+//! Command line arguments, and the commands besides `serve`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use clap::{Parser, Subcommand, ValueEnum};
+use rand::RngCore;
+use toml_edit::value;
+
+use crate::config::{self, Config, ConfigError, Format, StringGenerator};
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "A very configurable personal file uploading service"
+)]
+pub struct Cli {
+    /// The configuration file to use.
+    #[arg(short, long, global = true, default_value = "mediaserver.toml")]
+    pub config: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the server (the default).
+    Serve,
+    /// Write a configuration file with the defaults.
+    Init {
+        /// Replace the file if it already exists.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check that the configuration file loads.
+    Check,
+    /// Print a new random key.
+    GenKey {
+        #[arg(value_enum, default_value_t = KeyKind::Upload)]
+        kind: KeyKind,
+    },
+    /// Add a namespace to the configuration file, printing its upload key.
+    AddNamespace {
+        name: String,
+        /// Where its files are stored; defaults to the namespace's name.
+        #[arg(long)]
+        path: Option<String>,
+        /// Its upload key; one is generated if not given.
+        #[arg(long)]
+        key: Option<String>,
+    },
+    /// List the namespaces in the configuration file.
+    ListNamespaces,
+    /// Print the JSON Schema of the configuration file.
+    Schema,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum KeyKind {
+    /// A namespace's upload `key`.
+    Upload,
+    /// The `signing.secret` for signed URLs.
+    Signing,
+    /// A base64 key for `encryption_at_rest.key`.
+    Encryption,
+    /// The `admin.token` for the admin API.
+    Admin,
+}
+
+/// Runs `command`, which isn't `serve`, against the configuration at
+/// `config_path`.
+pub fn run(command: Command, config_path: &Path) -> Result<(), ConfigError> {
+    match command {
+        Command::Serve => unreachable!("`serve` runs the server"),
+        Command::Init { force } => init(config_path, force),
+        Command::Check => check(config_path),
+        Command::GenKey { kind } => {
+            println!("{}", generate_key(kind));
+            Ok(())
+        }
+        Command::AddNamespace { name, path, key } => {
+            add_namespace(config_path, &name, path, key)
+        }
+        Command::ListNamespaces => list_namespaces(config_path),
+        Command::Schema => {
+            let schema = serde_json::to_string_pretty(&Config::json_schema())
+                .expect("a JSON value should always serialize");
+            println!("{}", schema);
+            Ok(())
+        }
+    }
+}
+
+fn init(config_path: &Path, force: bool) -> Result<(), ConfigError> {
+    let exists =
+        fs::exists(config_path).map_err(|e| ConfigError::IoExists {
+            path: config_path.display().to_string(),
+            source: e,
+        })?;
+    if exists && !force {
+        return Err(ConfigError::AlreadyExists {
+            path: config_path.display().to_string(),
+        });
+    }
+
+    Config::write_default(config_path)?;
+    println!(
+        "Wrote the default configuration to `{}`; edit it, then run `serve`",
+        config_path.display()
+    );
+    Ok(())
+}
+
+fn check(config_path: &Path) -> Result<(), ConfigError> {
+    let config = Config::load(config_path)?;
+    println!(
+        "`{}` is valid, with {} upload namespace{}",
+        config_path.display(),
+        config.namespaces.len(),
+        if config.namespaces.len() == 1 {
+            ""
+        } else {
+            "s"
+        }
+    );
+    Ok(())
+}
+
+fn generate_key(kind: KeyKind) -> String {
+    match kind {
+        KeyKind::Upload => config::generate_upload_key(),
+        KeyKind::Signing => config::generate_signing_secret(),
+        KeyKind::Admin => StringGenerator::Random {
+            length: 64,
+            max_attempts_before_grow: 0,
+        }
+        .generate(),
+        KeyKind::Encryption => {
+            let mut key = [0u8; 32];
+            rand::rng().fill_bytes(&mut key);
+            BASE64_STANDARD.encode(key)
+        }
+    }
+}
+
+/// Adds a namespace to the file at `config_path`, keeping the rest of it,
+/// comments included, as it is.
+fn add_namespace(
+    config_path: &Path,
+    name: &str,
+    path: Option<String>,
+    key: Option<String>,
+) -> Result<(), ConfigError> {
+    if Format::of(config_path) != Format::Toml {
+        return Err(ConfigError::NotToml {
+            path: config_path.display().to_string(),
+        });
+    }
+
+    // also migrates it, if it's for an older version
+    Config::load(config_path)?;
+
+    let mut document = config::format::read(config_path)?;
+    let namespace = config::add_namespace(&mut document, name)?;
+    if let Some(path) = path {
+        namespace.insert("path", value(path));
+    }
+    if let Some(key) = key {
+        namespace.insert("key", value(key));
+    }
+    let key = namespace
+        .get("key")
+        .and_then(|key| key.as_str())
+        .unwrap_or_default()
+        .to_string();
+
+    // so nothing the next `serve` or `check` would refuse gets written
+    Config::from_document(config_path, document.clone())?;
+
+    config::format::write_document(config_path, &document)?;
+
+    println!("Added namespace `{}` with upload key:", name);
+    println!("{}", key);
+    Ok(())
+}
+
+fn list_namespaces(config_path: &Path) -> Result<(), ConfigError> {
+    let config = Config::load(config_path)?;
+    let mut names: Vec<_> = config.namespaces.keys().collect();
+    names.sort();
+
+    let rows: Vec<[String; 4]> = names
+        .into_iter()
+        .map(|name| {
+            let namespace = &config.namespaces[name];
+            let backend = namespace
+                .backend
+                .as_ref()
+                .unwrap_or(&config.storage.backend)
+                .kind();
+
+            [
+                name.clone(),
+                namespace.path.clone(),
+                backend.to_string(),
+                config.namespace_flags(name).join(", "),
+            ]
+        })
+        .collect();
+
+    let header = ["NAME", "PATH", "BACKEND", "FLAGS"].map(str::to_string);
+    let widths: Vec<usize> = (0..3)
+        .map(|column| {
+            rows.iter()
+                .chain([&header])
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    for row in [&header].into_iter().chain(&rows) {
+        let line = format!(
+            "{:<name$}  {:<path$}  {:<backend$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            name = widths[0],
+            path = widths[1],
+            backend = widths[2],
+        );
+        println!("{}", line.trim_end());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "[signing]\n\
+                          secret = \"s\"\n\
+                          [namespaces.ferris]\n\
+                          path = \"ferris\"\n\
+                          key = \"k\"\n";
+
+    #[test]
+    fn refuses_namespaces_the_config_would_not_load_with() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("config.toml");
+        fs::write(&path, CONFIG).unwrap();
+
+        for (name, path_setting) in [("upload", None), ("copy", Some("ferris"))]
+        {
+            let result =
+                add_namespace(&path, name, path_setting.map(Into::into), None);
+
+            assert!(
+                matches!(result, Err(ConfigError::Invalid { .. })),
+                "`{}` should have been refused",
+                name
+            );
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), CONFIG);
+    }
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::constant_time_eq;
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// Token for the admin API, sent as `Authorization: Bearer <token>`.
+    /// Leave empty to turn the admin API off.
+    pub token: String,
+    /// Usernames and passwords allowed to sign in to the admin pages at
+    /// `/_admin/`. Leave empty to turn the admin pages off.
+    pub users: HashMap<String, String>,
+    /// How long signing in to the admin pages lasts, at most a year.
+    pub session_lifetime_seconds: u64,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            token: String::new(),
+            users: HashMap::new(),
+            session_lifetime_seconds: 12 * 60 * 60,
+        }
+    }
+}
+
+impl AdminConfig {
+    pub fn enabled(&self) -> bool {
+        !self.token.is_empty()
+    }
+
+    /// Whether `token` is the admin token.
+    pub fn authorizes(&self, token: &str) -> bool {
+        self.enabled() && constant_time_eq(token, &self.token)
+    }
+
+    pub fn has_users(&self) -> bool {
+        !self.users.is_empty()
+    }
+
+    /// Whether `password` is the password of the admin `username`.
+    pub fn authenticates(&self, username: &str, password: &str) -> bool {
+        self.users
+            .get(username)
+            .is_some_and(|expected| constant_time_eq(password, expected))
+    }
+}
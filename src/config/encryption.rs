@@ -0,0 +1,82 @@
+use std::{fmt, fs};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::ConfigError;
+
+/// A 256-bit key for encrypting files at rest.
+#[derive(Clone)]
+pub struct EncryptionKey(pub [u8; 32]);
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, JsonSchema)]
+#[serde(default)]
+pub struct EncryptionAtRestConfig {
+    pub enabled: bool,
+    /// Base64-encoded 32-byte key.
+    pub key: String,
+    /// Path to a file holding the key, either as 32 raw bytes or base64.
+    /// Takes precedence over `key`.
+    pub key_file: String,
+    #[serde(skip)]
+    resolved_key: Option<EncryptionKey>,
+}
+
+impl EncryptionAtRestConfig {
+    /// The key to encrypt with, if encryption is enabled and the key has been
+    /// loaded with [`EncryptionAtRestConfig::load_key`].
+    pub fn key(&self) -> Option<&EncryptionKey> {
+        self.resolved_key.as_ref().filter(|_| self.enabled)
+    }
+
+    /// Reads and decodes the configured key.
+    pub fn load_key(&mut self, namespace: &str) -> Result<(), ConfigError> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let invalid = |reason: String| ConfigError::EncryptionKey {
+            namespace: namespace.to_string(),
+            reason,
+        };
+
+        let raw = if !self.key_file.is_empty() {
+            let content =
+                fs::read(&self.key_file).map_err(|e| ConfigError::IoRead {
+                    path: self.key_file.clone(),
+                    source: e,
+                })?;
+
+            if content.len() == 32 {
+                content
+            } else {
+                let text = String::from_utf8(content).map_err(|_| {
+                    invalid("key file is neither 32 bytes nor base64".into())
+                })?;
+                BASE64_STANDARD
+                    .decode(text.trim())
+                    .map_err(|e| invalid(format!("key file: {}", e)))?
+            }
+        } else if !self.key.is_empty() {
+            BASE64_STANDARD
+                .decode(self.key.trim())
+                .map_err(|e| invalid(format!("key: {}", e)))?
+        } else {
+            return Err(invalid("neither `key` nor `key_file` is set".into()));
+        };
+
+        let key: [u8; 32] = raw.try_into().map_err(|raw: Vec<u8>| {
+            invalid(format!("key must be 32 bytes, got {}", raw.len()))
+        })?;
+
+        self.resolved_key = Some(EncryptionKey(key));
+        Ok(())
+    }
+}
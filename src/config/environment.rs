@@ -0,0 +1,320 @@
+//! Overriding settings in the configuration file with environment variables,
+//! for deployments where editing the file is awkward, like containers.
+
+use std::env;
+
+use serde_json::Value as Json;
+use toml_edit::{DocumentMut, Item, Table, TableLike, Value};
+
+use super::{Config, ConfigError};
+
+/// Environment variables starting with this name a setting to override.
+const PREFIX: &str = "MEDIASERVER__";
+
+/// Sets each setting named by a `MEDIASERVER__` environment variable in
+/// `document`. The rest of the name is the setting's path, with `__` between
+/// tables; `MEDIASERVER__NAMESPACES__FERRIS__KEY` sets `key` in
+/// `[namespaces.ferris]`. Sections match names regardless of case, and ones
+/// that don't match anything are taken as lowercase. Values are read as TOML,
+/// unless the setting is a string.
+///
+/// `document` holds the settings at the path `scope`, and only variables for
+/// settings inside it are applied. Settings of the namespaces in `elsewhere`
+/// are left for the documents they're in.
+pub fn apply_overrides(
+    document: &mut DocumentMut,
+    scope: &[&str],
+    elsewhere: &[&str],
+) -> Result<(), ConfigError> {
+    let mut overrides = Vec::new();
+    for (variable, value) in env::vars_os() {
+        let Some(variable) = variable
+            .to_str()
+            .filter(|variable| variable.starts_with(PREFIX))
+        else {
+            continue;
+        };
+        let value = value.into_string().map_err(|_| {
+            ConfigError::EnvironmentOverride {
+                variable: variable.to_string(),
+                reason: "its value isn't UTF-8".into(),
+            }
+        })?;
+        overrides.push((variable.to_string(), value));
+    }
+
+    apply(document, scope, elsewhere, overrides)
+}
+
+/// The environment variable overriding the setting at `path`, if one is set.
+pub fn override_variable(path: &[&str]) -> Option<String> {
+    env::vars_os()
+        .filter_map(|(variable, _)| variable.into_string().ok())
+        .find(|variable| {
+            variable.strip_prefix(PREFIX).is_some_and(|setting| {
+                let segments: Vec<&str> = setting.split("__").collect();
+                segments.len() == path.len()
+                    && segments.iter().zip(path).all(|(a, b)| same_name(a, b))
+            })
+        })
+}
+
+/// Whether the section of a variable `a` names the setting `b`.
+fn same_name(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+/// Applies the `overrides`, given as variable names and values, as
+/// [`apply_overrides`] does.
+fn apply(
+    document: &mut DocumentMut,
+    scope: &[&str],
+    elsewhere: &[&str],
+    mut overrides: Vec<(String, String)>,
+) -> Result<(), ConfigError> {
+    // so a table is always created before the settings inside it
+    overrides.sort();
+    let schema = Config::json_schema();
+
+    for (variable, value) in overrides {
+        let invalid = |reason: String| ConfigError::EnvironmentOverride {
+            variable: variable.clone(),
+            reason,
+        };
+
+        let segments: Vec<String> = variable[PREFIX.len()..]
+            .split("__")
+            .map(str::to_string)
+            .collect();
+        if segments.iter().any(String::is_empty) {
+            return Err(invalid("it has an empty section".into()));
+        }
+
+        let in_scope = segments.len() >= scope.len()
+            && segments.iter().zip(scope).all(|(a, b)| same_name(a, b));
+        let is_elsewhere = matches!(
+            segments.as_slice(),
+            [namespaces, name, ..]
+                if same_name(namespaces, "namespaces")
+                    && elsewhere.iter().any(|other| same_name(name, other))
+        );
+        if !in_scope || (scope.is_empty() && is_elsewhere) {
+            continue;
+        }
+
+        let Some((setting, tables)) = segments[scope.len()..].split_last()
+        else {
+            return Err(invalid("it names a table, not a setting".into()));
+        };
+        let is_string = is_string_setting(&schema, &segments);
+        apply_override(
+            document.as_table_mut(),
+            tables,
+            setting,
+            &value,
+            is_string,
+        )
+        .map_err(invalid)?;
+    }
+
+    Ok(())
+}
+
+/// Sets `setting` in the table at `tables` in `root` to `raw`, taken as a
+/// string if `is_string`, returning why it couldn't be.
+fn apply_override(
+    root: &mut Table,
+    tables: &[String],
+    setting: &str,
+    raw: &str,
+    is_string: bool,
+) -> Result<(), String> {
+    let mut table: &mut dyn TableLike = root;
+    for name in tables {
+        let name = key_in(table, name);
+        if table.get(&name).is_none() {
+            let mut new = Table::new();
+            new.set_implicit(true);
+            table.insert(&name, Item::Table(new));
+        }
+        table = table
+            .get_mut(&name)
+            .and_then(Item::as_table_like_mut)
+            .ok_or_else(|| format!("`{}` isn't a table", name))?;
+    }
+    let setting = key_in(table, setting);
+    let setting = setting.as_str();
+
+    // strings stay strings, even if they look like something else; anything
+    // else is read as TOML, falling back to a string
+    let value = match table.get(setting) {
+        _ if is_string => Value::from(raw),
+        Some(Item::Value(Value::String(_))) => Value::from(raw),
+        _ => raw.parse::<Value>().unwrap_or_else(|_| Value::from(raw)),
+    };
+    table.insert(setting, Item::Value(value));
+
+    Ok(())
+}
+
+/// Whether the setting at `path` is a string going by `schema`, the
+/// configuration's JSON Schema, so a key like `12345` isn't read as a number.
+fn is_string_setting(schema: &Json, path: &[String]) -> bool {
+    let mut nodes = variants(schema, schema);
+    for segment in path {
+        nodes = nodes
+            .into_iter()
+            .filter_map(|node| {
+                node.get("properties")
+                    .and_then(Json::as_object)
+                    .and_then(|properties| {
+                        properties
+                            .iter()
+                            .find(|(name, _)| same_name(segment, name))
+                            .map(|(_, property)| property)
+                    })
+                    .or_else(|| {
+                        node.get("additionalProperties")
+                            .filter(|property| property.is_object())
+                    })
+            })
+            .flat_map(|node| variants(schema, node))
+            .collect();
+    }
+
+    let mut types = nodes
+        .iter()
+        .filter_map(|node| node.get("type"))
+        .flat_map(|types| match types {
+            Json::Array(types) => types.iter().collect(),
+            other => vec![other],
+        })
+        .filter_map(Json::as_str)
+        .filter(|kind| *kind != "null")
+        .peekable();
+    types.peek().is_some() && types.all(|kind| kind == "string")
+}
+
+/// `node` along with the schemas in `schema` it refers to or is one of.
+fn variants<'a>(schema: &'a Json, node: &'a Json) -> Vec<&'a Json> {
+    let mut found = vec![node];
+    let definition = node
+        .get("$ref")
+        .and_then(Json::as_str)
+        .and_then(|reference| reference.strip_prefix("#/$defs/"))
+        .and_then(|name| schema.get("$defs")?.get(name));
+    if let Some(definition) = definition {
+        found.extend(variants(schema, definition));
+    }
+    for combined in ["allOf", "anyOf", "oneOf"] {
+        for member in node
+            .get(combined)
+            .and_then(Json::as_array)
+            .into_iter()
+            .flatten()
+        {
+            found.extend(variants(schema, member));
+        }
+    }
+    found
+}
+
+/// The key in `table` the section `name` is for: `name` itself, one that
+/// matches it regardless of case, or failing that, `name` lowercased.
+fn key_in(table: &dyn TableLike, name: &str) -> String {
+    if table.contains_key(name) {
+        return name.to_string();
+    }
+
+    table
+        .iter()
+        .map(|(key, _)| key)
+        .find(|key| same_name(key, name))
+        .map_or_else(|| name.to_lowercase(), str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "[namespaces.Ferris]\n\
+                          path = \"ferris\"\n\
+                          key = \"k\"\n";
+
+    fn overrides(variables: &[(&str, &str)]) -> Vec<(String, String)> {
+        variables
+            .iter()
+            .map(|(variable, value)| (variable.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn overrides_mixed_case_namespaces() {
+        let mut document: DocumentMut = CONFIG.parse().unwrap();
+        let variables = overrides(&[
+            ("MEDIASERVER__NAMESPACES__FERRIS__KEY", "upper"),
+            ("MEDIASERVER__NAMESPACES__Ferris__PATH", "crab"),
+        ]);
+
+        apply(&mut document, &[], &[], variables).unwrap();
+
+        assert_eq!(
+            document.to_string(),
+            "[namespaces.Ferris]\n\
+             path = \"crab\"\n\
+             key = \"upper\"\n"
+        );
+    }
+
+    #[test]
+    fn overrides_mixed_case_namespaces_in_files_of_their_own() {
+        let variables =
+            overrides(&[("MEDIASERVER__NAMESPACES__FERRIS__KEY", "upper")]);
+
+        let mut document = DocumentMut::new();
+        apply(&mut document, &[], &["Ferris"], variables.clone()).unwrap();
+        assert_eq!(document.to_string(), "");
+
+        let mut document: DocumentMut = "key = \"k\"\n".parse().unwrap();
+        apply(&mut document, &["namespaces", "Ferris"], &[], variables)
+            .unwrap();
+        assert_eq!(document.to_string(), "key = \"upper\"\n");
+    }
+
+    #[test]
+    fn keeps_new_string_settings_strings() {
+        let mut document = DocumentMut::new();
+        let variables = overrides(&[
+            ("MEDIASERVER__NAMESPACES__NEW__KEY", "12345"),
+            ("MEDIASERVER__NAMESPACES__NEW__MAX_DOWNLOADS_CAP", "5"),
+            ("MEDIASERVER__SIGNING__SECRET", "123456"),
+            ("MEDIASERVER__STORAGE__BACKEND__BUCKET", "2024"),
+        ]);
+
+        apply(&mut document, &[], &[], variables).unwrap();
+
+        assert_eq!(
+            document["namespaces"]["new"]["key"].as_str(),
+            Some("12345")
+        );
+        assert_eq!(
+            document["namespaces"]["new"]["max_downloads_cap"].as_integer(),
+            Some(5)
+        );
+        assert_eq!(document["signing"]["secret"].as_str(), Some("123456"));
+        assert_eq!(
+            document["storage"]["backend"]["bucket"].as_str(),
+            Some("2024")
+        );
+    }
+
+    #[test]
+    fn lowercases_new_settings() {
+        let mut document = DocumentMut::new();
+        let variables = overrides(&[("MEDIASERVER__WEB_SERVER__PORT", "80")]);
+
+        apply(&mut document, &[], &[], variables).unwrap();
+
+        assert_eq!(document["web_server"]["port"].as_integer(), Some(80));
+    }
+}
@@ -26,9 +26,127 @@ pub enum ConfigError {
         #[source]
         source: toml::de::Error,
     },
+    #[error("Failed to parse `{path}` as JSON: {source}")]
+    JsonParse {
+        path: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Failed to parse `{path}` as YAML: {source}")]
+    YamlParse {
+        path: String,
+        #[source]
+        source: serde_yaml::Error,
+    },
+    #[error("Invalid settings in `{path}`: {reason}")]
+    Settings { path: String, reason: String },
     #[error("Failed to serialize as TOML: {source}")]
     TomlWrite {
         #[source]
         source: toml::ser::Error,
     },
+    #[error("Failed to parse `{path}` as TOML: {source}")]
+    TomlEdit {
+        path: String,
+        #[source]
+        source: toml_edit::TomlError,
+    },
+    #[error("`config_version` is {version}, which isn't a version")]
+    InvalidVersion { version: String },
+    #[error(
+        "`config_version` is {version}, but this build only understands up \
+         to {supported}"
+    )]
+    NewerVersion { version: u32, supported: u32 },
+    #[error("Invalid environment variable `{variable}`: {reason}")]
+    EnvironmentOverride { variable: String, reason: String },
+    #[error(
+        "Namespace `{namespace}` reads its key from `{variable}`, which isn't \
+         set"
+    )]
+    KeyEnv { namespace: String, variable: String },
+    #[error("Invalid encryption key for namespace `{namespace}`: {reason}")]
+    EncryptionKey { namespace: String, reason: String },
+    #[error("`{path}` already exists")]
+    AlreadyExists { path: String },
+    #[error("Namespace `{namespace}` already exists")]
+    NamespaceExists { namespace: String },
+    #[error(
+        "Namespace `{namespace}` is defined in both `{first}` and `{second}`"
+    )]
+    DuplicateNamespace {
+        namespace: String,
+        first: String,
+        second: String,
+    },
+    #[error(
+        "`{path}` isn't TOML, so it can't be edited; add a file to one of its \
+         `namespace_directories` instead"
+    )]
+    NotToml { path: String },
+    #[error("`{key}` isn't a table")]
+    NotATable { key: String },
+    #[error("`{path}` has {}:{}", count(.problems), list(.problems))]
+    Invalid {
+        path: String,
+        problems: Vec<ConfigError>,
+    },
+    #[error("Namespace `{namespace}` has an invalid name: {reason}")]
+    InvalidNamespaceName { namespace: String, reason: String },
+    #[error("Namespace `{namespace}` has an invalid `path`: {reason}")]
+    InvalidPath { namespace: String, reason: String },
+    #[error(
+        "Namespaces `{}` and `{}` both store their files at `{path}`",
+        .namespaces.0,
+        .namespaces.1
+    )]
+    DuplicatePath {
+        path: String,
+        namespaces: (String, String),
+    },
+    #[error(
+        "Namespace `{inner}` stores its files at `{inner_path}`, inside \
+         `{outer_path}` where namespace `{outer}` stores its own"
+    )]
+    NestedPath {
+        outer: String,
+        outer_path: String,
+        inner: String,
+        inner_path: String,
+    },
+    #[error("Namespace `{namespace}` has an empty `key`")]
+    EmptyKey { namespace: String },
+    #[error(
+        "Namespace `{namespace}` generates file names with a `length` of 0"
+    )]
+    EmptyFileNames { namespace: String },
+    #[error(
+        "`storage.default_namespace_path` is `{path}`, but no namespace \
+         stores its files there"
+    )]
+    UnknownDefaultNamespace { path: String },
+    #[error(
+        "`admin.session_lifetime_seconds` is {seconds}, but can't be more \
+         than {max} (a year)"
+    )]
+    SessionLifetime { seconds: u64, max: u64 },
+    #[error(
+        "`signing.secret` is empty, but namespace `{namespace}` needs it to \
+         sign URLs"
+    )]
+    EmptySigningSecret { namespace: String },
+}
+
+fn count(problems: &[ConfigError]) -> String {
+    match problems.len() {
+        1 => "a problem".to_string(),
+        count => format!("{} problems", count),
+    }
+}
+
+fn list(problems: &[ConfigError]) -> String {
+    problems
+        .iter()
+        .map(|problem| format!("\n  - {}", problem))
+        .collect()
 }
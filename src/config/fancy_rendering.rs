@@ -1,9 +1,11 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use super::FancyRendererEmojis;
+use super::{FancyRendererEmojis, ListingCacheConfig};
 
-#[derive(Deserialize, Default, Serialize, Debug, Clone)]
+#[derive(Deserialize, Default, Serialize, Debug, Clone, JsonSchema)]
 #[serde(default)]
 pub struct FancyRendererConfig {
     pub emoji: FancyRendererEmojis,
+    pub cache: ListingCacheConfig,
 }
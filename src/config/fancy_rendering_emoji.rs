@@ -1,11 +1,9 @@
-use std::{
-    collections::HashMap,
-    fs::{DirEntry, Metadata},
-};
+use std::{collections::HashMap, path::Path};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
 #[serde(default)]
 pub struct FancyRendererEmojis {
     pub directory: String,
@@ -14,14 +12,8 @@ pub struct FancyRendererEmojis {
 }
 
 impl FancyRendererEmojis {
-    pub fn resolve_emoji(
-        &self,
-        dir_entry: &DirEntry,
-        metadata: &Metadata,
-    ) -> String {
-        let is_dir = metadata.is_dir();
-        let ext_string = dir_entry
-            .path()
+    pub fn resolve_emoji(&self, file_name: &str, is_dir: bool) -> String {
+        let ext_string = Path::new(file_name)
             .extension()
             .and_then(|e| e.to_str())
             .unwrap_or("")
@@ -1,10 +1,16 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
 #[serde(default)]
 pub struct FileListingConfig {
     pub show: bool,
     pub use_fancy_renderer: bool,
+    /// Whether listings offer a zip/tar.gz download of the whole directory.
+    /// Each one is built as it's downloaded, so it's off unless asked for.
+    pub archive_downloads: bool,
+    /// Directories whose contents add up to more than this are refused.
+    pub max_archive_size_bytes: u64,
 }
 
 impl Default for FileListingConfig {
@@ -12,6 +18,8 @@ impl Default for FileListingConfig {
         Self {
             show: false,
             use_fancy_renderer: true,
+            archive_downloads: false,
+            max_archive_size_bytes: 1024 * 1024 * 1024,
         }
     }
 }
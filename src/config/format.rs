@@ -0,0 +1,199 @@
+//! Reading and writing configuration files as TOML, JSON or YAML, going by
+//! their extension.
+//!
+//! Whatever the format, a file is handled as a TOML document, so migrations
+//! and environment overrides only have to deal with one. Only TOML files keep
+//! their formatting and comments when they're rewritten, and point at the
+//! line a mistake is on.
+
+use std::{fs, path::Path};
+
+use log::warn;
+use serde::{Serialize, de::DeserializeOwned};
+use toml_edit::DocumentMut;
+
+use super::ConfigError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    /// The format of the file at `path`, going by its extension. Anything
+    /// that isn't JSON or YAML is taken to be TOML.
+    pub fn of(path: &Path) -> Format {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+
+        match extension.as_deref() {
+            Some("json") => Format::Json,
+            Some("yaml" | "yml") => Format::Yaml,
+            _ => Format::Toml,
+        }
+    }
+
+    /// Whether `path` has the extension of a configuration file.
+    pub fn is_config_file(path: &Path) -> bool {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase)
+            .is_some_and(|extension| {
+                matches!(extension.as_str(), "toml" | "json" | "yaml" | "yml")
+            })
+    }
+}
+
+/// Reads the file at `path` as a TOML document.
+pub fn read(path: &Path) -> Result<DocumentMut, ConfigError> {
+    let content =
+        fs::read_to_string(path).map_err(|e| ConfigError::IoRead {
+            path: path.display().to_string(),
+            source: e,
+        })?;
+
+    let value = match Format::of(path) {
+        Format::Toml => {
+            return content.parse().map_err(|e| ConfigError::TomlEdit {
+                path: path.display().to_string(),
+                source: e,
+            });
+        }
+        Format::Json => serde_json::from_str::<serde_json::Value>(&content)
+            .map_err(|e| ConfigError::JsonParse {
+                path: path.display().to_string(),
+                source: e,
+            })?,
+        Format::Yaml => serde_yaml::from_str::<serde_json::Value>(&content)
+            .map_err(|e| ConfigError::YamlParse {
+                path: path.display().to_string(),
+                source: e,
+            })?,
+    };
+
+    let invalid = |reason: String| ConfigError::Settings {
+        path: path.display().to_string(),
+        reason,
+    };
+    let table = match toml::Value::try_from(without_nulls(value)) {
+        Ok(toml::Value::Table(table)) => table,
+        Ok(_) => return Err(invalid("it isn't a table of settings".into())),
+        Err(e) => return Err(invalid(e.to_string())),
+    };
+    toml::to_string(&table)
+        .map_err(|e| invalid(e.to_string()))?
+        .parse()
+        .map_err(|e| ConfigError::TomlEdit {
+            path: path.display().to_string(),
+            source: e,
+        })
+}
+
+/// Reads settings of type `T` from `document`, which was read from `path`,
+/// warning about any that aren't recognised.
+pub fn deserialize<T: DeserializeOwned>(
+    path: &Path,
+    document: &DocumentMut,
+) -> Result<T, ConfigError> {
+    let content = document.to_string();
+    let warn_unknown = |setting: serde_ignored::Path| {
+        warn!(
+            "Ignoring unknown setting `{}` in `{}`",
+            setting,
+            path.display()
+        )
+    };
+
+    if Format::of(path) == Format::Toml {
+        return serde_ignored::deserialize(
+            toml::Deserializer::new(&content),
+            warn_unknown,
+        )
+        .map_err(|e| ConfigError::TomlParse {
+            path: path.display().to_string(),
+            source: e,
+        });
+    }
+
+    // lines in the document don't match lines in the file, so errors are
+    // left without them
+    let invalid = |reason: String| ConfigError::Settings {
+        path: path.display().to_string(),
+        reason,
+    };
+    let table: toml::Table =
+        toml::from_str(&content).map_err(|e| invalid(e.message().into()))?;
+    serde_ignored::deserialize(toml::Value::Table(table), warn_unknown)
+        .map_err(|e| invalid(e.message().into()))
+}
+
+/// Writes `document` to `path` in the file's format.
+pub fn write_document(
+    path: &Path,
+    document: &DocumentMut,
+) -> Result<(), ConfigError> {
+    match Format::of(path) {
+        Format::Toml => write(path, document.to_string()),
+        _ => {
+            let table: toml::Table = toml::from_str(&document.to_string())
+                .map_err(|e| ConfigError::Settings {
+                    path: path.display().to_string(),
+                    reason: e.message().into(),
+                })?;
+            write_value(path, &table)
+        }
+    }
+}
+
+/// Writes `value` to `path` in the file's format.
+pub fn write_value<T: Serialize>(
+    path: &Path,
+    value: &T,
+) -> Result<(), ConfigError> {
+    let invalid = |reason: String| ConfigError::Settings {
+        path: path.display().to_string(),
+        reason,
+    };
+
+    let serialized = match Format::of(path) {
+        Format::Toml => toml::to_string_pretty(value)
+            .map_err(|e| ConfigError::TomlWrite { source: e })?,
+        Format::Json => serde_json::to_string_pretty(value)
+            .map(|json| json + "\n")
+            .map_err(|e| invalid(e.to_string()))?,
+        Format::Yaml => {
+            serde_yaml::to_string(value).map_err(|e| invalid(e.to_string()))?
+        }
+    };
+
+    write(path, serialized)
+}
+
+fn write(path: &Path, content: String) -> Result<(), ConfigError> {
+    fs::write(path, content).map_err(|e| ConfigError::IoWrite {
+        path: path.display().to_string(),
+        source: e,
+    })
+}
+
+/// `value` without `null`s, which TOML has no way to write. A setting that's
+/// `null` is left at its default, same as one that isn't there.
+fn without_nulls(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => serde_json::Value::Object(
+            object
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, without_nulls(value)))
+                .collect(),
+        ),
+        serde_json::Value::Array(array) => serde_json::Value::Array(
+            array.into_iter().map(without_nulls).collect(),
+        ),
+        value => value,
+    }
+}
@@ -0,0 +1,49 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// What format processed images are saved in.
+#[derive(
+    Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageOutputFormat {
+    /// Keep the format the image was uploaded in.
+    Original,
+    Png,
+    Jpeg,
+    /// Lossless; `quality` doesn't apply.
+    Webp,
+    Avif,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+#[serde(default)]
+pub struct ImageProcessingConfig {
+    /// Re-encode uploaded JPEG, PNG and WebP images, which drops their
+    /// metadata (EXIF, including GPS coordinates). Animated PNG and WebP
+    /// images and GIFs only have their metadata removed, and TIFF, HEIF and
+    /// AVIF images are refused, as theirs can't be. Other files are stored
+    /// as they are.
+    pub enabled: bool,
+    /// Rotate images the way their EXIF orientation says to before it's
+    /// dropped, so they don't end up sideways.
+    pub auto_orient: bool,
+    /// Downscale images so neither side is longer than this. 0 never
+    /// downscales.
+    pub max_dimension: u32,
+    pub format: ImageOutputFormat,
+    /// 1-100, for JPEG and AVIF.
+    pub quality: u8,
+}
+
+impl Default for ImageProcessingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            auto_orient: true,
+            max_dimension: 0,
+            format: ImageOutputFormat::Original,
+            quality: 80,
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+#[serde(default)]
+pub struct ImageTransformConfig {
+    /// Serve resized and re-encoded variants of images when asked for with
+    /// `?w=&h=&fit=&format=&q=`.
+    pub enabled: bool,
+    /// The only values `w` may take, so nobody can fill the variant cache
+    /// with every size there is.
+    pub widths: Vec<u32>,
+    /// The only values `h` may take.
+    pub heights: Vec<u32>,
+    /// The only values `q` may take. Leaving `q` out always works.
+    pub qualities: Vec<u8>,
+}
+
+impl Default for ImageTransformConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            widths: vec![160, 320, 640, 1280, 1920],
+            heights: vec![160, 320, 640, 1280, 1920],
+            qualities: vec![50, 80, 90],
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+#[serde(default)]
+pub struct ListingCacheConfig {
+    pub enabled: bool,
+    /// Upper bound on the number of rendered listings kept in memory.
+    pub max_entries: usize,
+    /// Invalidate cached listings when the uploads directory changes on disk,
+    /// not only when files are uploaded through the server.
+    pub watch_filesystem: bool,
+}
+
+impl Default for ListingCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_entries: 1024,
+            watch_filesystem: true,
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+#[serde(default)]
+pub struct MediaConfig {
+    /// Read the duration, dimensions, codecs and bitrate of uploaded video
+    /// and audio files, shown in listings and upload responses.
+    pub enabled: bool,
+    /// The ffmpeg binary used to grab poster frames from videos. Posters are
+    /// skipped when it can't be run; leave empty to never try.
+    pub ffmpeg_path: String,
+    /// How far into a video its poster frame is taken from. Videos shorter
+    /// than this get the frame halfway through.
+    pub poster_offset_seconds: f64,
+    /// Give up on a poster frame after this long.
+    pub poster_timeout_seconds: u64,
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ffmpeg_path: "ffmpeg".to_string(),
+            poster_offset_seconds: 1.0,
+            poster_timeout_seconds: 30,
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Bringing configuration files written for older versions up to date, so
+//! renamed settings aren't silently replaced by their defaults.
+
+use toml_edit::{DocumentMut, Item, Key, TableLike, value};
+
+use super::ConfigError;
+
+/// The version of the configuration format this build reads and writes.
+/// Files without a `config_version` are version 1.
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Each migration takes a file from the version at its index plus one to the
+/// next.
+const MIGRATIONS: [fn(&mut DocumentMut); 1] = [to_version_2];
+
+/// Migrates `document` to [`CONFIG_VERSION`], returning the version it was if
+/// that changed any of its settings, and so it's worth rewriting.
+pub fn migrate(document: &mut DocumentMut) -> Result<Option<u32>, ConfigError> {
+    let version = match document.get("config_version") {
+        None => 1,
+        Some(item) => item
+            .as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .filter(|version| *version >= 1)
+            .ok_or_else(|| ConfigError::InvalidVersion {
+                version: item.to_string().trim().to_string(),
+            })?,
+    };
+
+    if version > CONFIG_VERSION {
+        return Err(ConfigError::NewerVersion {
+            version,
+            supported: CONFIG_VERSION,
+        });
+    }
+    if version == CONFIG_VERSION {
+        return Ok(None);
+    }
+
+    let before = document.to_string();
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        migration(document);
+    }
+    let changed = document.to_string() != before;
+    document["config_version"] = value(i64::from(CONFIG_VERSION));
+
+    Ok(changed.then_some(version))
+}
+
+/// `file_system_path` and `default_namespace_fs_path` became `path` and
+/// `default_namespace_path`, as they're key prefixes with S3.
+fn to_version_2(document: &mut DocumentMut) {
+    if let Some(storage) = document
+        .get_mut("storage")
+        .and_then(Item::as_table_like_mut)
+    {
+        rename(
+            storage,
+            "default_namespace_fs_path",
+            "default_namespace_path",
+        );
+    }
+
+    if let Some(namespaces) = document
+        .get_mut("namespaces")
+        .and_then(Item::as_table_like_mut)
+    {
+        for (_, namespace) in namespaces.iter_mut() {
+            if let Some(namespace) = namespace.as_table_like_mut() {
+                rename(namespace, "file_system_path", "path");
+            }
+        }
+    }
+}
+
+/// Renames the setting `from` in `table` to `to`, keeping any comments above
+/// it, unless `to` is already set.
+fn rename(table: &mut dyn TableLike, from: &str, to: &str) {
+    if table.contains_key(to) {
+        return;
+    }
+    let Some(key) = table.key(from).cloned() else {
+        return;
+    };
+    let Some(item) = table.remove(from) else {
+        return;
+    };
+
+    let key = Key::new(to).with_leaf_decor(key.leaf_decor().clone());
+    table.entry_format(&key).or_insert(item);
+}
@@ -0,0 +1,30 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{FilesystemConfig, StorageBackendConfig};
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+#[serde(default)]
+pub struct MirrorConfig {
+    pub enabled: bool,
+    /// Where copies go. Files keep the keys they have in their namespace's
+    /// backend, so namespaces are laid out the same way in the mirror.
+    pub backend: StorageBackendConfig,
+    /// How long to wait before retrying a failed copy. Doubles on every
+    /// failure, up to `max_retry_interval_seconds`.
+    pub retry_interval_seconds: u64,
+    pub max_retry_interval_seconds: u64,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            backend: StorageBackendConfig::Filesystem(FilesystemConfig {
+                directory: Some("mirror".to_string()),
+            }),
+            retry_interval_seconds: 30,
+            max_retry_interval_seconds: 60 * 60,
+        }
+    }
+}
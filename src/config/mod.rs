@@ -1,90 +1,216 @@
+mod admin;
+mod encryption;
+mod environment;
 mod error;
 mod fancy_rendering;
 mod fancy_rendering_emoji;
 mod file_listing;
+pub mod format;
+mod image_processing;
+mod image_transforms;
+mod listing_cache;
+mod media;
+mod migration;
+mod mirror;
 mod namespace_def;
 mod random_string_generator;
+mod signing;
 mod storage;
+mod storage_backend;
+mod validation;
+mod views;
 mod web_server;
 
-use log::info;
+use log::{info, warn};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, io, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+use toml_edit::{DocumentMut, Item, Table, TableLike, value};
 
+use crate::storage::Storage;
+
+pub use admin::AdminConfig;
+pub use encryption::{EncryptionAtRestConfig, EncryptionKey};
+pub use environment::override_variable;
 pub use error::ConfigError;
 pub use fancy_rendering::FancyRendererConfig;
 pub use fancy_rendering_emoji::FancyRendererEmojis;
 pub use file_listing::FileListingConfig;
+pub use format::Format;
+pub use image_processing::{ImageOutputFormat, ImageProcessingConfig};
+pub use image_transforms::ImageTransformConfig;
+pub use listing_cache::ListingCacheConfig;
+pub use media::MediaConfig;
+pub use migration::CONFIG_VERSION;
+pub use mirror::MirrorConfig;
 pub use namespace_def::NamespaceDefinition;
 pub use random_string_generator::StringGenerator;
+pub use signing::SigningConfig;
 pub use storage::StorageConfig;
+pub use storage_backend::{FilesystemConfig, S3Config, StorageBackendConfig};
+pub use views::ViewConfig;
 pub use web_server::WebServerConfig;
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+/// Where namespaces are read from besides the configuration file, unless
+/// `namespace_directories` says otherwise.
+const DEFAULT_NAMESPACE_DIRECTORY: &str = "namespaces.d";
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
 #[serde(default)]
 pub struct Config {
+    /// The version of the format the file is in, so older files can be
+    /// migrated when settings are renamed.
+    pub config_version: u32,
+    /// Directories of files that each hold one namespace, named after the
+    /// file, relative to the configuration file. Missing ones are skipped.
+    pub namespace_directories: Vec<String>,
     pub web_server: WebServerConfig,
     pub file_listing_render: FancyRendererConfig,
     pub storage: StorageConfig,
+    pub signing: SigningConfig,
+    pub media: MediaConfig,
+    pub admin: AdminConfig,
+    #[schemars(transform = generated_default)]
     pub namespaces: HashMap<String, NamespaceDefinition>,
+    /// The files namespaces from the namespace directories were read from.
+    #[serde(skip)]
+    pub namespace_files: HashMap<String, PathBuf>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            config_version: CONFIG_VERSION,
+            namespace_directories: vec![DEFAULT_NAMESPACE_DIRECTORY.into()],
             web_server: WebServerConfig::default(),
             file_listing_render: FancyRendererConfig::default(),
             storage: StorageConfig::default(),
+            signing: SigningConfig::default(),
+            media: MediaConfig::default(),
+            admin: AdminConfig::default(),
             namespaces: HashMap::from([(
                 "ferris".to_string(),
                 NamespaceDefinition::default(),
             )]),
+            namespace_files: HashMap::new(),
         }
     }
 }
 
-static CONFIG_PATH: &str = "mediaserver.toml";
-
 impl Config {
-    pub fn read() -> Result<Config, ConfigError> {
-        if !fs::exists(CONFIG_PATH).map_err(|e| ConfigError::IoExists {
-            path: CONFIG_PATH.into(),
-            source: e,
-        })? {
-            info!(
-                "Failed to find `{}`; writing default config...",
-                CONFIG_PATH
-            );
-
-            let serialized = toml::to_string_pretty(&Self::default())
-                .map_err(|e| ConfigError::TomlWrite { source: e })?;
-
-            fs::write(CONFIG_PATH, serialized).map_err(|e| {
-                ConfigError::IoWrite {
-                    path: CONFIG_PATH.into(),
-                    source: e,
-                }
-            })?;
+    /// Writes the default configuration to `path`, in the format its
+    /// extension is for, replacing whatever is there.
+    pub fn write_default(path: &Path) -> Result<(), ConfigError> {
+        let mut config = Self::default();
+        config.signing.secret = generate_signing_secret();
+        format::write_value(path, &config)
+    }
+
+    /// Reads the configuration file at `path`, and the files in its
+    /// namespace directories, overridden by any `MEDIASERVER__` environment
+    /// variables, and the keys they refer to, then checks the result,
+    /// reporting every problem found at once. Files are read as TOML, JSON
+    /// or YAML going by their extension.
+    ///
+    /// A file written for an older version is migrated, and rewritten with
+    /// the original kept next to it. A file without a `signing.secret` gets
+    /// a new one written to it, so signed URLs outlive a restart. Settings
+    /// that aren't recognised are warned about.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let mut document = format::read(path)?;
+        if let Some(version) = migration::migrate(&mut document)? {
+            rewrite_migrated(path, version, &document);
+        }
+        if add_signing_secret(&mut document) {
+            match format::write_document(path, &document) {
+                Ok(()) => info!(
+                    "Added a new `signing.secret` to `{}`",
+                    path.display()
+                ),
+                Err(e) => warn!(
+                    "Couldn't add a new `signing.secret` to `{}`, so URLs \
+                     signed now stop working on restart: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+
+        Self::from_document(path, document)
+    }
+
+    /// The configuration the file at `path` would load as if it held
+    /// `document`, for checking a change before it's written.
+    pub fn from_document(
+        path: &Path,
+        mut document: DocumentMut,
+    ) -> Result<Config, ConfigError> {
+        migration::migrate(&mut document)?;
 
-            info!("Done! Edit the config file and start again");
+        // the directories can be overridden too
+        let mut overridden = document.clone();
+        environment::apply_overrides(&mut overridden, &[], &[])?;
+        let directories = namespace_directories(&overridden);
+        let included = read_namespace_files(path, &document, &directories)?;
 
-            std::process::exit(0);
+        let elsewhere: Vec<&str> =
+            included.iter().map(|(name, ..)| name.as_str()).collect();
+        environment::apply_overrides(&mut document, &[], &elsewhere)?;
+        let mut config: Config = format::deserialize(path, &document)?;
+
+        for (name, file, mut document) in included {
+            environment::apply_overrides(
+                &mut document,
+                &["namespaces", &name],
+                &[],
+            )?;
+            let namespace = format::deserialize(&file, &document)?;
+            config.namespaces.insert(name.clone(), namespace);
+            config.namespace_files.insert(name, file);
         }
 
-        let content = fs::read_to_string(CONFIG_PATH).map_err(|e| {
-            ConfigError::IoRead {
-                path: CONFIG_PATH.into(),
-                source: e,
-            }
-        })?;
+        let mut problems = Vec::new();
+        for (name, namespace) in &mut config.namespaces {
+            problems.extend(namespace.load_key(name).err());
+            problems.extend(namespace.encryption_at_rest.load_key(name).err());
+        }
+        problems.extend(config.problems());
 
-        toml::from_str(&content).map_err(|e| ConfigError::TomlParse {
-            path: CONFIG_PATH.into(),
-            source: e,
-        })
+        if !problems.is_empty() {
+            return Err(ConfigError::Invalid {
+                path: path.display().to_string(),
+                problems,
+            });
+        }
+
+        Ok(config)
+    }
+
+    /// The namespace directories of the configuration file at
+    /// `config_path`.
+    pub fn namespace_directories(&self, config_path: &Path) -> Vec<PathBuf> {
+        self.namespace_directories
+            .iter()
+            .map(|directory| relative_to(config_path, directory))
+            .collect()
+    }
+
+    /// The JSON Schema for the configuration file, for editors to complete
+    /// and check it with.
+    pub fn json_schema() -> serde_json::Value {
+        schemars::schema_for!(Config).to_value()
     }
 
-    pub fn create_uploads_directory(&self) -> io::Result<()> {
+    /// Creates the directories mediaserver keeps its own files in, and
+    /// prepares each namespace's storage, failing if a backend can't be used.
+    pub fn create_uploads_directory(
+        &self,
+        storage: &Storage,
+    ) -> io::Result<()> {
         let uploads_path: PathBuf = self.get_uploads_path();
         if !fs::exists(&uploads_path)? {
             fs::create_dir_all(&uploads_path)?;
@@ -95,21 +221,74 @@ impl Config {
             fs::create_dir(temp_path)?;
         }
 
-        for namespace in &self.namespaces {
-            let namespace_path = namespace.1.get_path(self);
+        let state_path = self.get_state_path();
+        if !fs::exists(&state_path)? {
+            fs::create_dir(state_path)?;
+        }
 
-            if !fs::exists(&namespace_path)? {
-                fs::create_dir(&namespace_path)?;
-                info!(
-                    "Created namespace directory: '{}'",
-                    &namespace_path.display()
-                );
-            }
+        let posters_path = self.get_posters_path();
+        if !fs::exists(&posters_path)? {
+            fs::create_dir(posters_path)?;
+        }
+
+        let image_cache_path = self.get_image_cache_path();
+        if !fs::exists(&image_cache_path)? {
+            fs::create_dir_all(image_cache_path)?;
+        }
+
+        for name in self.namespaces.keys() {
+            let Some(storage) = storage.namespace(name) else {
+                continue;
+            };
+
+            storage.initialise().map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "failed to set up storage for namespace '{}': {}",
+                        name, e
+                    ),
+                )
+            })?;
         }
 
         Ok(())
     }
 
+    /// The namespace served at the root, i.e. the one stored at
+    /// `storage.default_namespace_path`.
+    pub fn default_namespace(&self) -> Option<(&String, &NamespaceDefinition)> {
+        self.namespaces.iter().find(|(_, namespace)| {
+            namespace.path == self.storage.default_namespace_path
+        })
+    }
+
+    /// Short descriptions of how the namespace `name` is set up, like
+    /// `private` or `signed`.
+    pub fn namespace_flags(&self, name: &str) -> Vec<&'static str> {
+        let Some(namespace) = self.namespaces.get(name) else {
+            return Vec::new();
+        };
+
+        let mut flags = Vec::new();
+        if namespace.path == self.storage.default_namespace_path {
+            flags.push("default");
+        }
+        if namespace.requires_viewer_auth() {
+            flags.push("private");
+        }
+        if namespace.require_signed_urls {
+            flags.push("signed");
+        }
+        if namespace.end_to_end_encrypted {
+            flags.push("end-to-end encrypted");
+        }
+        if namespace.encryption_at_rest.enabled {
+            flags.push("encrypted at rest");
+        }
+        flags
+    }
+
     pub fn get_uploads_path(&self) -> PathBuf {
         PathBuf::from(&self.storage.uploads_directory)
     }
@@ -117,4 +296,385 @@ impl Config {
     pub fn get_temp_path(&self) -> PathBuf {
         self.get_uploads_path().join(".temp")
     }
+
+    /// Where the server keeps its own bookkeeping, like download counts.
+    pub fn get_state_path(&self) -> PathBuf {
+        self.get_uploads_path().join(".state")
+    }
+
+    /// Where poster frames of videos are kept.
+    pub fn get_posters_path(&self) -> PathBuf {
+        self.get_state_path().join("posters")
+    }
+
+    /// Where transformed variants of images are kept.
+    pub fn get_image_cache_path(&self) -> PathBuf {
+        self.get_uploads_path().join(".cache").join("images")
+    }
+}
+
+/// A new random upload key for a namespace.
+pub fn generate_upload_key() -> String {
+    StringGenerator::Random {
+        length: 128,
+        max_attempts_before_grow: 0,
+    }
+    .generate()
+}
+
+/// A new random `signing.secret`.
+pub fn generate_signing_secret() -> String {
+    StringGenerator::Random {
+        length: 64,
+        max_attempts_before_grow: 0,
+    }
+    .generate()
+}
+
+/// Adds the namespace `name` to `document`, a configuration file, stored
+/// under its name and with a new upload key, returning its table for any
+/// other settings. Fails if the file already defines it.
+pub fn add_namespace<'a>(
+    document: &'a mut DocumentMut,
+    name: &str,
+) -> Result<&'a mut dyn TableLike, ConfigError> {
+    let namespaces = document
+        .entry("namespaces")
+        .or_insert_with(|| {
+            let mut namespaces = Table::new();
+            namespaces.set_implicit(true);
+            Item::Table(namespaces)
+        })
+        .as_table_like_mut()
+        .ok_or_else(|| ConfigError::NotATable {
+            key: "namespaces".to_string(),
+        })?;
+    if namespaces.contains_key(name) {
+        return Err(ConfigError::NamespaceExists {
+            namespace: name.to_string(),
+        });
+    }
+
+    let mut namespace = Table::new();
+    namespace["path"] = value(name);
+    namespace["key"] = value(generate_upload_key());
+    namespaces.insert(name, Item::Table(namespace));
+
+    Ok(namespaces
+        .get_mut(name)
+        .and_then(Item::as_table_like_mut)
+        .expect("should have just been added"))
+}
+
+/// Compares secrets in constant time, so they can't be guessed a character
+/// at a time.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Leaves the default out of the schema of a setting whose default holds a
+/// freshly generated secret, as it's no use to anyone else.
+fn generated_default(schema: &mut schemars::Schema) {
+    schema.remove("default");
+}
+
+/// Gives `document`, a configuration file, a new `signing.secret` unless it
+/// has one or it's set in the environment, returning whether it did. If
+/// `signing` isn't a table, that's reported once it's deserialized.
+fn add_signing_secret(document: &mut DocumentMut) -> bool {
+    if override_variable(&["signing", "secret"]).is_some() {
+        return false;
+    }
+    let Some(signing) = document
+        .entry("signing")
+        .or_insert_with(|| Item::Table(Table::new()))
+        .as_table_like_mut()
+    else {
+        return false;
+    };
+    if signing.contains_key("secret") {
+        return false;
+    }
+
+    signing.insert("secret", value(generate_signing_secret()));
+    true
+}
+
+/// Replaces the file at `path` with its migrated `document`, keeping the
+/// original as `<path>.v<version>.bak`. Failing to is only a warning, as the
+/// migrated settings are used either way.
+fn rewrite_migrated(path: &Path, version: u32, document: &DocumentMut) {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{}.bak", version));
+    let backup = PathBuf::from(backup);
+
+    let result = fs::copy(path, &backup)
+        .map_err(|e| ConfigError::IoWrite {
+            path: backup.display().to_string(),
+            source: e,
+        })
+        .and_then(|_| format::write_document(path, document));
+    match result {
+        Ok(()) => info!(
+            "Migrated `{}` from version {} to {}, keeping the original as \
+             `{}`",
+            path.display(),
+            version,
+            CONFIG_VERSION,
+            backup.display()
+        ),
+        Err(e) => warn!(
+            "`{}` is for version {}, and couldn't be rewritten for version \
+             {}; using it migrated for now: {}",
+            path.display(),
+            version,
+            CONFIG_VERSION,
+            e
+        ),
+    }
+}
+
+/// `path` taken as relative to the directory of the configuration file at
+/// `config_path`, unless it's absolute.
+fn relative_to(config_path: &Path, path: &str) -> PathBuf {
+    match config_path.parent() {
+        Some(parent) => parent.join(path),
+        None => PathBuf::from(path),
+    }
+}
+
+/// The `namespace_directories` set in `document`. If they aren't strings,
+/// that's reported once it's deserialized.
+fn namespace_directories(document: &DocumentMut) -> Vec<String> {
+    match document.get("namespace_directories") {
+        Some(directories) => directories
+            .as_array()
+            .map(|directories| {
+                directories
+                    .iter()
+                    .filter_map(|directory| directory.as_str())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        None => vec![DEFAULT_NAMESPACE_DIRECTORY.to_string()],
+    }
+}
+
+/// Reads the namespace files in `directories`, returning each one's name,
+/// path and contents. A namespace can only be defined once across all of
+/// them and `document`, the configuration file at `config_path`.
+fn read_namespace_files(
+    config_path: &Path,
+    document: &DocumentMut,
+    directories: &[String],
+) -> Result<Vec<(String, PathBuf, DocumentMut)>, ConfigError> {
+    // where each namespace came from, to point at both copies of one
+    let mut defined_in: HashMap<String, PathBuf> = document
+        .get("namespaces")
+        .and_then(|namespaces| namespaces.as_table_like())
+        .map(|namespaces| {
+            namespaces
+                .iter()
+                .map(|(name, _)| (name.to_string(), config_path.to_path_buf()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut files = Vec::new();
+    for directory in directories {
+        let directory = relative_to(config_path, directory);
+        let entries = match fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(ConfigError::IoRead {
+                    path: directory.display().to_string(),
+                    source: e,
+                });
+            }
+        };
+
+        let mut paths = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| ConfigError::IoRead {
+                path: directory.display().to_string(),
+                source: e,
+            })?;
+            let path = entry.path();
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if !hidden
+                && path.is_file()
+                && format::Format::is_config_file(&path)
+            {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        for path in paths {
+            let name = path
+                .file_stem()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| ConfigError::Settings {
+                    path: path.display().to_string(),
+                    reason: "its name isn't UTF-8".into(),
+                })?
+                .to_string();
+
+            if let Some(first) = defined_in.get(&name) {
+                return Err(ConfigError::DuplicateNamespace {
+                    namespace: name,
+                    first: first.display().to_string(),
+                    second: path.display().to_string(),
+                });
+            }
+            defined_in.insert(name.clone(), path.clone());
+
+            let document = format::read(&path)?;
+            files.push((name, path, document));
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const CONFIG: &str = "namespace_directories = [\"a.d\", \"b.d\"]\n\
+                          [namespaces.ferris]\n\
+                          path = \"ferris\"\n\
+                          key = \"k\"\n";
+
+    /// A directory holding `files`, given as their paths and contents.
+    fn directory(files: &[(&str, &str)]) -> TempDir {
+        let directory = tempfile::tempdir().unwrap();
+        for (path, content) in files {
+            let path = directory.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        directory
+    }
+
+    fn load(directory: &TempDir) -> Result<Config, ConfigError> {
+        Config::load(&directory.path().join("config.toml"))
+    }
+
+    fn duplicate(directory: &TempDir) -> (String, PathBuf, PathBuf) {
+        match load(directory) {
+            Err(ConfigError::DuplicateNamespace {
+                namespace,
+                first,
+                second,
+            }) => (namespace, first.into(), second.into()),
+            other => panic!("expected a duplicate namespace, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn merges_namespace_directories() {
+        let directory = directory(&[
+            ("config.toml", CONFIG),
+            ("a.d/toml.toml", "path = \"toml\"\nkey = \"k\"\n"),
+            ("a.d/json.json", r#"{"path": "json", "key": "k"}"#),
+            ("b.d/yaml.yml", "path: yaml\nkey: k\n"),
+            ("b.d/.hidden.toml", "path = \"hidden\"\nkey = \"k\"\n"),
+            ("b.d/notes.txt", "not a namespace"),
+        ]);
+
+        let config = load(&directory).unwrap();
+
+        let mut names: Vec<&String> = config.namespaces.keys().collect();
+        names.sort();
+        assert_eq!(names, ["ferris", "json", "toml", "yaml"]);
+        assert_eq!(config.namespaces["yaml"].path, "yaml");
+        assert_eq!(
+            config.namespace_files["json"],
+            directory.path().join("a.d/json.json")
+        );
+        assert!(!config.namespace_files.contains_key("ferris"));
+    }
+
+    #[test]
+    fn skips_missing_directories() {
+        let directory = directory(&[("config.toml", CONFIG)]);
+
+        assert_eq!(load(&directory).unwrap().namespaces.len(), 1);
+    }
+
+    #[test]
+    fn refuses_namespaces_already_in_the_config_file() {
+        let directory = directory(&[
+            ("config.toml", CONFIG),
+            ("b.d/ferris.toml", "path = \"other\"\nkey = \"k\"\n"),
+        ]);
+
+        assert_eq!(
+            duplicate(&directory),
+            (
+                "ferris".to_string(),
+                directory.path().join("config.toml"),
+                directory.path().join("b.d/ferris.toml"),
+            )
+        );
+    }
+
+    #[test]
+    fn refuses_namespaces_in_two_directories() {
+        let directory = directory(&[
+            ("config.toml", CONFIG),
+            ("a.d/cats.json", r#"{"path": "cats", "key": "k"}"#),
+            ("b.d/cats.toml", "path = \"more-cats\"\nkey = \"k\"\n"),
+        ]);
+
+        assert_eq!(
+            duplicate(&directory),
+            (
+                "cats".to_string(),
+                directory.path().join("a.d/cats.json"),
+                directory.path().join("b.d/cats.toml"),
+            )
+        );
+    }
+
+    #[test]
+    fn refuses_namespaces_in_two_formats() {
+        let directory = directory(&[
+            ("config.toml", CONFIG),
+            ("a.d/cats.toml", "path = \"cats\"\nkey = \"k\"\n"),
+            ("a.d/cats.yaml", "path: cats\nkey: k\n"),
+        ]);
+
+        assert_eq!(
+            duplicate(&directory),
+            (
+                "cats".to_string(),
+                directory.path().join("a.d/cats.toml"),
+                directory.path().join("a.d/cats.yaml"),
+            )
+        );
+    }
+
+    #[test]
+    fn keeps_the_signing_secret_it_generates() {
+        let directory = directory(&[("config.toml", CONFIG)]);
+
+        let first = load(&directory).unwrap().signing.secret;
+        let second = load(&directory).unwrap().signing.secret;
+
+        assert_eq!(first.len(), 64);
+        assert_eq!(first, second);
+        let written =
+            fs::read_to_string(directory.path().join("config.toml")).unwrap();
+        assert!(written.contains(&first));
+    }
 }
@@ -1,50 +1,112 @@
-use std::{
-    collections::HashMap,
-    fs,
-    io::{self, ErrorKind},
-    path::PathBuf,
-};
+use std::{collections::HashMap, env, fs, io};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use super::{Config, FileListingConfig, StringGenerator};
+use super::{
+    ConfigError, EncryptionAtRestConfig, FileListingConfig,
+    ImageProcessingConfig, ImageTransformConfig, StorageBackendConfig,
+    StringGenerator, ViewConfig, constant_time_eq,
+};
+use crate::storage::NamespaceStorage;
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
 #[serde(default)]
 pub struct NamespaceDefinition {
-    pub file_system_path: String,
+    /// Where the namespace's files live inside its storage backend.
+    #[serde(alias = "file_system_path")]
+    pub path: String,
+    /// Stores this namespace somewhere other than `storage.backend`.
+    pub backend: Option<StorageBackendConfig>,
+    #[schemars(transform = super::generated_default)]
     pub key: String,
+    /// Path to a file holding `key`, like a Docker or Kubernetes secret.
+    /// Takes precedence over `key` and `key_env`.
+    pub key_file: String,
+    /// Name of an environment variable holding `key`. Takes precedence over
+    /// `key`.
+    pub key_env: String,
     pub file_listing: FileListingConfig,
     pub file_name_generator: StringGenerator,
+    /// Usernames and passwords allowed to view this namespace's files and
+    /// listings. Leave empty to make the namespace public.
+    pub viewers: HashMap<String, String>,
+    /// Only serve files from this namespace through signed, expiring URLs.
+    pub require_signed_urls: bool,
+    /// The most downloads an upload may be limited to via `max_downloads`.
+    /// Set to 0 to refuse limited-download uploads.
+    pub max_downloads_cap: u32,
+    /// Uploads are encrypted by the client before they're sent. They're
+    /// stored as opaque `.enc` blobs and linked through a page that decrypts
+    /// them in the browser.
+    pub end_to_end_encrypted: bool,
+    /// Serve a page for uploading from a browser at `/_upload/<namespace>`.
+    pub upload_page: bool,
+    /// Encrypt files on disk, decrypting them transparently when served.
+    pub encryption_at_rest: EncryptionAtRestConfig,
+    /// Strips metadata from, resizes and re-encodes uploaded images.
+    pub image_processing: ImageProcessingConfig,
+    /// Serves resized variants of images through query parameters.
+    pub image_transforms: ImageTransformConfig,
+    /// Pages shown around files opened in a browser.
+    pub views: ViewConfig,
 }
 
 impl NamespaceDefinition {
     pub fn auth<'a>(
         namespaces: &'a HashMap<String, NamespaceDefinition>,
         namespace: &'a String,
-        key: &'a String,
+        key: &'a str,
     ) -> Option<&'a NamespaceDefinition> {
         let namespace = namespaces.get(namespace)?;
 
-        if &namespace.key != key {
+        if !constant_time_eq(&namespace.key, key) {
             return None;
         }
 
         Some(namespace)
     }
 
-    pub fn get_path(&self, config: &Config) -> PathBuf {
-        config.get_uploads_path().join(&self.file_system_path)
+    /// Replaces `key` with the one in `key_file` or `key_env`, if either is
+    /// set.
+    pub fn load_key(&mut self, namespace: &str) -> Result<(), ConfigError> {
+        if !self.key_file.is_empty() {
+            let key = fs::read_to_string(&self.key_file).map_err(|e| {
+                ConfigError::IoRead {
+                    path: self.key_file.clone(),
+                    source: e,
+                }
+            })?;
+            self.key = key.trim().to_string();
+        } else if !self.key_env.is_empty() {
+            let key =
+                env::var(&self.key_env).map_err(|_| ConfigError::KeyEnv {
+                    namespace: namespace.to_string(),
+                    variable: self.key_env.clone(),
+                })?;
+            self.key = key.trim().to_string();
+        }
+
+        Ok(())
     }
 
+    pub fn requires_viewer_auth(&self) -> bool {
+        !self.viewers.is_empty()
+    }
+
+    pub fn auth_viewer(&self, username: &str, password: &str) -> bool {
+        self.viewers
+            .get(username)
+            .is_some_and(|expected| constant_time_eq(password, expected))
+    }
+
+    /// Picks a name for a new file and claims it in `storage`, returning the
+    /// file's key.
     pub fn create_random_file_name(
         &self,
-        config: &Config,
+        storage: &NamespaceStorage,
         file_extension: &str,
-    ) -> Result<PathBuf, io::Error> {
-        let base_dir = self.get_path(config);
-        fs::create_dir_all(&base_dir)?;
-
+    ) -> Result<String, io::Error> {
         match &self.file_name_generator {
             // For Random, pull out the starting length...
             StringGenerator::Random {
@@ -61,34 +123,26 @@ impl NamespaceDefinition {
                         max_attempts_before_grow: *max_attempts_before_grow,
                     };
                     let candidate_name = string_gen.generate();
-                    let candidate = base_dir
-                        .join(format!("{candidate_name}.{file_extension}"));
-
-                    match fs::OpenOptions::new()
-                        .write(true)
-                        .create_new(true)
-                        .open(&candidate)
-                    {
-                        Ok(_) => return Ok(candidate),
-                        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
-                            attempts += 1;
-                            if &attempts > string_gen.max_attempts_before_grow()
-                            {
-                                length += 1; // bump length
-                                attempts = 0; // reset counter
-                            }
-                            // retry with the (possibly) new length
-                            continue;
-                        }
-                        Err(e) => return Err(e),
+                    let candidate =
+                        format!("{candidate_name}.{file_extension}");
+
+                    if storage.reserve(&candidate)? {
+                        return Ok(candidate);
                     }
+
+                    attempts += 1;
+                    if &attempts > string_gen.max_attempts_before_grow() {
+                        length += 1; // bump length
+                        attempts = 0; // reset counter
+                    }
+                    // retry with the (possibly) new length
                 }
             }
 
             // chances of a collision are near-zero
             StringGenerator::Uuid => {
                 let file_name = self.file_name_generator.generate();
-                Ok(base_dir.join(format!("{file_name}.{file_extension}")))
+                Ok(format!("{file_name}.{file_extension}"))
             }
         }
     }
@@ -97,14 +151,77 @@ impl NamespaceDefinition {
 impl Default for NamespaceDefinition {
     fn default() -> Self {
         Self {
-            file_system_path: "ferris".to_string(),
+            path: "ferris".to_string(),
+            backend: None,
             key: StringGenerator::Random {
                 length: 128,
                 max_attempts_before_grow: 0,
             }
             .generate(),
+            key_file: String::new(),
+            key_env: String::new(),
             file_listing: FileListingConfig::default(),
             file_name_generator: StringGenerator::default(),
+            viewers: HashMap::new(),
+            require_signed_urls: false,
+            max_downloads_cap: 100,
+            end_to_end_encrypted: false,
+            upload_page: false,
+            encryption_at_rest: EncryptionAtRestConfig::default(),
+            image_processing: ImageProcessingConfig::default(),
+            image_transforms: ImageTransformConfig::default(),
+            views: ViewConfig::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn namespace() -> NamespaceDefinition {
+        NamespaceDefinition {
+            key: "upload-key".to_string(),
+            viewers: HashMap::from([(
+                "alice".to_string(),
+                "hunter2".to_string(),
+            )]),
+            ..NamespaceDefinition::default()
+        }
+    }
+
+    #[test]
+    fn authenticates_viewers() {
+        let namespace = namespace();
+        assert!(namespace.auth_viewer("alice", "hunter2"));
+        assert!(!namespace.auth_viewer("alice", "hunter3"));
+        assert!(!namespace.auth_viewer("alice", "hunter22"));
+        assert!(!namespace.auth_viewer("alice", ""));
+        assert!(!namespace.auth_viewer("bob", "hunter2"));
+    }
+
+    #[test]
+    fn authenticates_uploads() {
+        let namespaces = HashMap::from([("f".to_string(), namespace())]);
+        let name = "f".to_string();
+        assert!(
+            NamespaceDefinition::auth(&namespaces, &name, "upload-key")
+                .is_some()
+        );
+        assert!(
+            NamespaceDefinition::auth(&namespaces, &name, "upload-kez")
+                .is_none()
+        );
+        assert!(
+            NamespaceDefinition::auth(&namespaces, &name, "upload").is_none()
+        );
+        assert!(
+            NamespaceDefinition::auth(
+                &namespaces,
+                &"g".to_string(),
+                "upload-key"
+            )
+            .is_none()
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use rand::{Rng, distr::Alphanumeric};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 #[serde(tag = "type")]
 pub enum StringGenerator {
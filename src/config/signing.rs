@@ -0,0 +1,26 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+#[serde(default)]
+pub struct SigningConfig {
+    /// Secret used to sign download URLs. Changing it invalidates every
+    /// signed URL handed out so far. One is generated and written to the
+    /// file when it's left out.
+    pub secret: String,
+    /// How long signed URLs stay valid when the client doesn't ask for a
+    /// specific lifetime.
+    pub default_ttl_seconds: u64,
+    /// Upper bound on the lifetime a client may ask for.
+    pub max_ttl_seconds: u64,
+}
+
+impl Default for SigningConfig {
+    fn default() -> Self {
+        Self {
+            secret: String::new(),
+            default_ttl_seconds: 60 * 60,
+            max_ttl_seconds: 60 * 60 * 24 * 7,
+        }
+    }
+}
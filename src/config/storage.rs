@@ -1,19 +1,34 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+use super::{MirrorConfig, StorageBackendConfig};
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
 #[serde(default)]
 pub struct StorageConfig {
-    pub default_namespace_fs_path: String,
+    #[serde(alias = "default_namespace_fs_path")]
+    pub default_namespace_path: String,
     pub max_file_size_bytes: usize,
+    /// Holds the files when the backend is the filesystem, along with the
+    /// server's temporary files and bookkeeping either way.
     pub uploads_directory: String,
+    pub backend: StorageBackendConfig,
+    /// Copy every upload to a second backend in the background.
+    pub mirror: MirrorConfig,
+    /// How much disk space transformed variants of images may take up before
+    /// the least recently used ones are removed.
+    pub image_cache_max_size_bytes: u64,
 }
 
 impl Default for StorageConfig {
     fn default() -> Self {
         Self {
-            default_namespace_fs_path: "ferris".to_string(),
+            default_namespace_path: "ferris".to_string(),
             max_file_size_bytes: 1024 * 1024 * 100,
             uploads_directory: "uploads".to_string(),
+            backend: StorageBackendConfig::default(),
+            mirror: MirrorConfig::default(),
+            image_cache_max_size_bytes: 1024 * 1024 * 1024,
         }
     }
 }
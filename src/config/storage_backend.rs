@@ -0,0 +1,71 @@
+use std::str::FromStr;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+#[serde(tag = "type")]
+pub enum StorageBackendConfig {
+    /// Files live in a directory on this machine.
+    Filesystem(FilesystemConfig),
+
+    /// Files live in an S3-compatible object store, such as AWS S3 or MinIO.
+    S3(Box<S3Config>),
+}
+
+impl StorageBackendConfig {
+    /// The backend's `type`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            StorageBackendConfig::Filesystem(_) => "filesystem",
+            StorageBackendConfig::S3(_) => "s3",
+        }
+    }
+}
+
+impl Default for StorageBackendConfig {
+    fn default() -> Self {
+        Self::Filesystem(FilesystemConfig::default())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, JsonSchema)]
+#[serde(default)]
+pub struct FilesystemConfig {
+    /// Defaults to `storage.uploads_directory`.
+    pub directory: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+#[serde(default)]
+pub struct S3Config {
+    #[serde(with = "url_serde")]
+    #[schemars(with = "String")]
+    pub endpoint: Url,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Address the bucket as `endpoint/bucket` rather than
+    /// `bucket.endpoint`. Most self-hosted stores expect this.
+    pub path_style: bool,
+    /// Prepended to every object key, to share a bucket with other data.
+    pub prefix: String,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            endpoint: Url::from_str("http://localhost:9000")
+                .expect("default 'endpoint' should be parseable"),
+            bucket: "mediaserver".to_string(),
+            region: "us-east-1".to_string(),
+            access_key: String::new(),
+            secret_key: String::new(),
+            path_style: true,
+            prefix: String::new(),
+        }
+    }
+}
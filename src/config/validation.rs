@@ -0,0 +1,414 @@
+//! Checks for mistakes in a configuration that parses, but wouldn't work.
+
+use super::{Config, ConfigError, StringGenerator};
+
+/// Names the server's own routes are at, which a namespace can't be served
+/// at. Names starting with `_` are kept for the server too.
+const RESERVED_NAMES: [&str; 2] = ["upload", "sign"];
+
+/// The longest an admin session can last, a year.
+const MAX_SESSION_LIFETIME_SECONDS: u64 = 365 * 24 * 60 * 60;
+
+impl Config {
+    /// Everything wrong with this configuration, in a stable order.
+    pub fn problems(&self) -> Vec<ConfigError> {
+        let mut problems = Vec::new();
+
+        let mut names: Vec<&String> = self.namespaces.keys().collect();
+        names.sort();
+
+        // namespaces that share a backend can't share a path in it, or keep
+        // their files inside another's
+        let mut paths = Vec::<(Option<String>, Vec<&str>, &str)>::new();
+
+        for &name in &names {
+            let namespace = &self.namespaces[name];
+
+            if let Some(reason) = invalid_name(name) {
+                problems.push(ConfigError::InvalidNamespaceName {
+                    namespace: name.clone(),
+                    reason,
+                });
+            }
+
+            let path = namespace.path.trim_matches('/');
+            // fine in a backend of its own, but a shared one would hold every
+            // other namespace inside it
+            let holds_everything =
+                path.is_empty() && namespace.backend.is_none();
+            if holds_everything {
+                problems.push(ConfigError::InvalidPath {
+                    namespace: name.clone(),
+                    reason: "it's empty, so it would hold every other \
+                             namespace in `storage.backend`"
+                        .into(),
+                });
+            } else if path.split(['/', '\\']).any(|segment| segment == "..") {
+                problems.push(ConfigError::InvalidPath {
+                    namespace: name.clone(),
+                    reason: "it can't contain `..`".into(),
+                });
+            } else if path
+                .split(['/', '\\'])
+                .any(|segment| segment.starts_with('.'))
+            {
+                // like the uploads directory's own `.state` and `.temp`
+                problems.push(ConfigError::InvalidPath {
+                    namespace: name.clone(),
+                    reason: "names starting with `.` are kept for the server"
+                        .into(),
+                });
+            }
+
+            // already reported as holding every other one
+            if !holds_everything {
+                let backend =
+                    namespace.backend.as_ref().unwrap_or(&self.storage.backend);
+                let backend = toml::to_string(backend).ok();
+                let segments: Vec<&str> = if path.is_empty() {
+                    Vec::new()
+                } else {
+                    path.split(['/', '\\']).collect()
+                };
+                for (other_backend, other_segments, other) in &paths {
+                    if *other_backend != backend {
+                        continue;
+                    }
+                    let other_path = self.namespaces[*other].path.clone();
+                    if *other_segments == segments {
+                        problems.push(ConfigError::DuplicatePath {
+                            path: namespace.path.clone(),
+                            namespaces: (other.to_string(), name.clone()),
+                        });
+                    } else if segments.starts_with(other_segments) {
+                        problems.push(ConfigError::NestedPath {
+                            outer: other.to_string(),
+                            outer_path: other_path,
+                            inner: name.clone(),
+                            inner_path: namespace.path.clone(),
+                        });
+                    } else if other_segments.starts_with(&segments) {
+                        problems.push(ConfigError::NestedPath {
+                            outer: name.clone(),
+                            outer_path: namespace.path.clone(),
+                            inner: other.to_string(),
+                            inner_path: other_path,
+                        });
+                    }
+                }
+                paths.push((backend, segments, name));
+            }
+
+            if namespace.key.trim().is_empty() {
+                problems.push(ConfigError::EmptyKey {
+                    namespace: name.clone(),
+                });
+            }
+
+            if let StringGenerator::Random { length: 0, .. } =
+                namespace.file_name_generator
+            {
+                problems.push(ConfigError::EmptyFileNames {
+                    namespace: name.clone(),
+                });
+            }
+        }
+
+        // anyone could sign URLs with an empty secret
+        let signing = names.iter().find(|name| {
+            let namespace = &self.namespaces[name.as_str()];
+            namespace.require_signed_urls || namespace.requires_viewer_auth()
+        });
+        if let Some(name) = signing
+            && self.signing.secret.trim().is_empty()
+        {
+            problems.push(ConfigError::EmptySigningSecret {
+                namespace: name.to_string(),
+            });
+        }
+
+        if self.default_namespace().is_none() {
+            problems.push(ConfigError::UnknownDefaultNamespace {
+                path: self.storage.default_namespace_path.clone(),
+            });
+        }
+
+        if self.admin.session_lifetime_seconds > MAX_SESSION_LIFETIME_SECONDS {
+            problems.push(ConfigError::SessionLifetime {
+                seconds: self.admin.session_lifetime_seconds,
+                max: MAX_SESSION_LIFETIME_SECONDS,
+            });
+        }
+
+        problems
+    }
+}
+
+/// Why a namespace can't be called `name`, if it can't.
+fn invalid_name(name: &str) -> Option<String> {
+    if name.is_empty() {
+        Some("it's empty".into())
+    } else if name.contains('/') {
+        Some("it can't contain `/`".into())
+    } else if name.starts_with('_') {
+        Some("names starting with `_` are kept for the server".into())
+    } else if RESERVED_NAMES.contains(&name) {
+        Some(format!("`/{}` is one of the server's own routes", name))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs};
+
+    use super::*;
+    use crate::config::{
+        FilesystemConfig, NamespaceDefinition, StorageBackendConfig,
+    };
+
+    fn namespace(path: &str) -> NamespaceDefinition {
+        NamespaceDefinition {
+            path: path.to_string(),
+            ..NamespaceDefinition::default()
+        }
+    }
+
+    fn config(namespaces: &[(&str, NamespaceDefinition)]) -> Config {
+        let mut config = Config::default();
+        config.namespaces.extend(
+            namespaces
+                .iter()
+                .map(|(name, namespace)| (name.to_string(), namespace.clone())),
+        );
+        config
+    }
+
+    fn messages(config: &Config) -> Vec<String> {
+        config
+            .problems()
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn accepts_the_default_config() {
+        assert!(Config::default().problems().is_empty());
+    }
+
+    #[test]
+    fn refuses_reserved_names() {
+        let config = config(&[
+            ("upload", namespace("a")),
+            ("_admin", namespace("b")),
+            ("a/b", namespace("c")),
+            ("", namespace("d")),
+        ]);
+
+        assert_eq!(
+            messages(&config),
+            [
+                "Namespace `` has an invalid name: it's empty",
+                "Namespace `_admin` has an invalid name: names starting with \
+                 `_` are kept for the server",
+                "Namespace `a/b` has an invalid name: it can't contain `/`",
+                "Namespace `upload` has an invalid name: `/upload` is one of \
+                 the server's own routes",
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_unusable_paths() {
+        let config = config(&[
+            ("empty", namespace("/")),
+            ("escaping", namespace("a/../../etc")),
+            ("here", namespace("a/./b")),
+            ("state", namespace(".state")),
+        ]);
+
+        assert_eq!(
+            messages(&config),
+            [
+                "Namespace `empty` has an invalid `path`: it's empty, so it \
+                 would hold every other namespace in `storage.backend`",
+                "Namespace `escaping` has an invalid `path`: it can't contain \
+                 `..`",
+                "Namespace `here` has an invalid `path`: names starting with \
+                 `.` are kept for the server",
+                "Namespace `state` has an invalid `path`: names starting with \
+                 `.` are kept for the server",
+            ]
+        );
+    }
+
+    #[test]
+    fn allows_an_empty_path_in_a_backend_of_its_own() {
+        let config = config(&[(
+            "own",
+            NamespaceDefinition {
+                backend: Some(StorageBackendConfig::Filesystem(
+                    FilesystemConfig {
+                        directory: Some("elsewhere".into()),
+                    },
+                )),
+                ..namespace("")
+            },
+        )]);
+
+        assert!(config.problems().is_empty());
+    }
+
+    #[test]
+    fn refuses_shared_paths_in_the_same_backend() {
+        let config = config(&[
+            ("copy", namespace("/ferris/")),
+            ("inner", namespace("ferris/inner")),
+            ("pub", namespace("pub")),
+            ("public", namespace("public")),
+            ("secret", namespace("pub/secret/")),
+            (
+                "separate",
+                NamespaceDefinition {
+                    backend: Some(StorageBackendConfig::Filesystem(
+                        FilesystemConfig {
+                            directory: Some("elsewhere".into()),
+                        },
+                    )),
+                    ..namespace("ferris")
+                },
+            ),
+        ]);
+
+        assert_eq!(
+            messages(&config),
+            [
+                "Namespaces `copy` and `ferris` both store their files at \
+                 `ferris`",
+                "Namespace `inner` stores its files at `ferris/inner`, inside \
+                 `/ferris/` where namespace `copy` stores its own",
+                "Namespace `inner` stores its files at `ferris/inner`, inside \
+                 `ferris` where namespace `ferris` stores its own",
+                "Namespace `secret` stores its files at `pub/secret/`, inside \
+                 `pub` where namespace `pub` stores its own",
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_empty_keys_and_file_names() {
+        let config = config(&[(
+            "broken",
+            NamespaceDefinition {
+                key: "  ".into(),
+                file_name_generator: StringGenerator::Random {
+                    length: 0,
+                    max_attempts_before_grow: 32,
+                },
+                ..namespace("broken")
+            },
+        )]);
+
+        assert_eq!(
+            messages(&config),
+            [
+                "Namespace `broken` has an empty `key`",
+                "Namespace `broken` generates file names with a `length` of 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_an_empty_signing_secret_when_urls_are_signed() {
+        let mut config = config(&[
+            (
+                "private",
+                NamespaceDefinition {
+                    viewers: HashMap::from([("a".into(), "b".into())]),
+                    ..namespace("private")
+                },
+            ),
+            (
+                "signed",
+                NamespaceDefinition {
+                    require_signed_urls: true,
+                    ..namespace("signed")
+                },
+            ),
+        ]);
+        config.signing.secret = " ".into();
+
+        assert_eq!(
+            messages(&config),
+            [
+                "`signing.secret` is empty, but namespace `private` needs it to \
+              sign URLs"
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_an_unknown_default_namespace() {
+        let mut config = Config::default();
+        config.storage.default_namespace_path = "missing".into();
+
+        assert_eq!(
+            messages(&config),
+            ["`storage.default_namespace_path` is `missing`, but no \
+              namespace stores its files there"]
+        );
+    }
+
+    #[test]
+    fn refuses_unreasonable_session_lifetimes() {
+        let mut config = Config::default();
+        config.admin.session_lifetime_seconds = u64::MAX;
+
+        assert_eq!(
+            messages(&config),
+            [format!(
+                "`admin.session_lifetime_seconds` is {}, but can't be more \
+                 than 31536000 (a year)",
+                u64::MAX
+            )]
+        );
+    }
+
+    #[test]
+    fn loading_reports_every_problem_at_once() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("config.toml");
+        fs::write(
+            &path,
+            "[storage]\n\
+             default_namespace_path = \"missing\"\n\
+             [namespaces.sign]\n\
+             path = \"sign\"\n\
+             key = \"k\"\n\
+             [namespaces.blank]\n\
+             path = \"blank\"\n\
+             key = \"\"\n",
+        )
+        .unwrap();
+
+        let Err(ConfigError::Invalid { problems, .. }) = Config::load(&path)
+        else {
+            panic!("the config should be invalid");
+        };
+        let messages: Vec<String> =
+            problems.iter().map(|problem| problem.to_string()).collect();
+
+        assert_eq!(
+            messages,
+            [
+                "Namespace `blank` has an empty `key`",
+                "Namespace `sign` has an invalid name: `/sign` is one of the \
+                 server's own routes",
+                "`storage.default_namespace_path` is `missing`, but no \
+                 namespace stores its files there",
+            ]
+        );
+    }
+}
@@ -0,0 +1,37 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Which files get a page around them when opened in a browser, rather than
+/// being served as they are. Any file can still be viewed with `?view=`, and
+/// fetched as it is with `?view=raw`.
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+#[serde(default)]
+pub struct ViewConfig {
+    /// Play video and audio files in a player page.
+    pub media_player: bool,
+    /// Render Markdown files as (sanitized) HTML.
+    pub markdown: bool,
+    /// Pretty-print JSON files, with collapsible objects and arrays.
+    pub json: bool,
+    /// Show plain text files and logs with line numbers, and their ANSI
+    /// colours as actual colours.
+    pub text: bool,
+    /// List what's in zip and tar archives, from which single files can be
+    /// downloaded.
+    pub archives: bool,
+    /// Larger files are always served as they are, rather than rendered.
+    pub max_rendered_size_bytes: u64,
+}
+
+impl Default for ViewConfig {
+    fn default() -> Self {
+        Self {
+            media_player: false,
+            markdown: false,
+            json: false,
+            text: false,
+            archives: false,
+            max_rendered_size_bytes: 5 * 1024 * 1024,
+        }
+    }
+}
@@ -1,17 +1,23 @@
 use std::str::FromStr;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
 #[serde(default)]
 pub struct WebServerConfig {
     pub host: String,
     pub port: u16,
     #[serde(with = "url_serde")]
+    #[schemars(with = "String")]
     pub listen_url: Url,
     #[serde(with = "url_serde")]
+    #[schemars(with = "String")]
     pub redirect_index_to: Url,
+    /// How long requests still in progress, like uploads, get to finish when
+    /// the server stops, or is replaced after the configuration is reloaded.
+    pub shutdown_timeout_seconds: u64,
 }
 
 impl Default for WebServerConfig {
@@ -25,6 +31,7 @@ impl Default for WebServerConfig {
                 "http://github.com/kokiriglade/mediaserver",
             )
             .expect("default 'redirect_index_to' should be parseable"),
+            shutdown_timeout_seconds: 300,
         }
     }
 }
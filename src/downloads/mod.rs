@@ -0,0 +1,240 @@
+mod store;
+
+use actix_web::{
+    Error, HttpResponse,
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        Method, StatusCode,
+        header::{self, CacheControl, CacheDirective},
+    },
+    middleware::Next,
+    web::{self, Data},
+};
+use log::{error, info};
+use percent_encoding::percent_decode_str;
+
+use crate::{
+    media::MediaLibrary,
+    mirror::Mirror,
+    render::ListingCache,
+    storage::{self, Storage},
+};
+
+pub use store::{Counted, DownloadLimits, Exhausted};
+
+/// Middleware counting downloads of limited files served from `namespace`,
+/// deleting them once their limit is reached and answering `410 Gone` from
+/// then on.
+///
+/// Every `GET` counts as a download, except for ranges starting past the
+/// file's first byte. Players and download managers ask for the rest of a
+/// file in those, and resume cut off downloads the same way, which shouldn't
+/// use up a download each; they're still refused once the limit is reached.
+pub async fn enforce_download_limits<B: MessageBody>(
+    namespace: String,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let storage = req
+        .app_data::<Data<Storage>>()
+        .and_then(|storage| storage.namespace(&namespace).cloned());
+    let limits = req.app_data::<Data<DownloadLimits>>().cloned();
+    let mirror = req.app_data::<Data<Mirror>>().cloned();
+    let media = req.app_data::<Data<MediaLibrary>>().cloned();
+    let listing_cache = req.app_data::<Data<ListingCache>>().cloned();
+
+    let tail = percent_decode_str(req.match_info().unprocessed())
+        .decode_utf8_lossy()
+        .into_owned();
+
+    let (storage, limits, file) =
+        match (storage, limits, storage::sanitize_key(&tail)) {
+            (Some(storage), Some(limits), Some(file)) => {
+                (storage, limits, file)
+            }
+            _ => {
+                return next.call(req).await.map(|r| r.map_into_left_body());
+            }
+        };
+
+    let key = storage.backend_key(&file);
+
+    // every download is taken from the limit before it's served, so
+    // concurrent requests can't get around it
+    let taken = req.method() == Method::GET
+        && req
+            .headers()
+            .get(header::RANGE)
+            .and_then(|range| range.to_str().ok())
+            .is_none_or(|range| !skips_first_byte(range));
+    let counted = if taken {
+        limits.try_take(&key)
+    } else {
+        match limits.get(&key) {
+            None => Ok(Counted::Unlimited),
+            Some(limit) if limit.is_exhausted() => Err(Exhausted),
+            Some(limit) => {
+                Ok(Counted::Remaining(limit.max_downloads - limit.downloads))
+            }
+        }
+    };
+
+    let counted = match counted {
+        Ok(Counted::Unlimited) => {
+            return next.call(req).await.map(|r| r.map_into_left_body());
+        }
+        Ok(counted) => counted,
+        Err(Exhausted) => {
+            return Ok(req
+                .into_response(
+                    HttpResponse::Gone()
+                        .body("This file has reached its download limit"),
+                )
+                .map_into_right_body());
+        }
+    };
+
+    let mut res = match next.call(req).await {
+        Ok(res) => res,
+        Err(e) => {
+            if taken {
+                limits.give_back(&key);
+            }
+            return Err(e);
+        }
+    };
+
+    res.headers_mut().insert(
+        header::CACHE_CONTROL,
+        CacheControl(vec![CacheDirective::NoStore])
+            .to_string()
+            .parse()
+            .expect("cache control should be a valid header value"),
+    );
+
+    if !matches!(res.status(), StatusCode::OK | StatusCode::PARTIAL_CONTENT) {
+        if taken {
+            limits.give_back(&key);
+        }
+    } else if taken && counted == Counted::Last {
+        // the response already holds the file open, so removing it here
+        // doesn't cut the last download short
+        let deleted = {
+            let storage = storage.clone();
+            let file = file.clone();
+            web::block(move || storage.delete(&file)).await
+        };
+
+        match deleted {
+            Ok(Ok(_)) => {
+                info!("Deleted {} after its last download", key);
+                limits.remove(&key);
+                if let Some(mirror) = mirror {
+                    mirror.delete(storage.name(), &file);
+                }
+                if let Some(media) = media {
+                    media.remove(&key);
+                }
+                if let Some(listing_cache) = listing_cache {
+                    listing_cache.invalidate_path(&key);
+                }
+            }
+            Ok(Err(e)) => error!("Failed to delete {}: {}", key, e),
+            Err(e) => error!("Failed to delete {}: {}", key, e),
+        }
+    }
+
+    Ok(res.map_into_left_body())
+}
+
+/// Whether the `Range` header `range` asks for the file from past its first
+/// byte, going by the first range in it, the one that's served.
+fn skips_first_byte(range: &str) -> bool {
+    range
+        .trim()
+        .strip_prefix("bytes=")
+        .and_then(|ranges| ranges.split(',').next())
+        .and_then(|first| first.split_once('-'))
+        .and_then(|(start, _)| start.trim().parse::<u64>().ok())
+        .is_some_and(|start| start > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use actix_files::Files;
+    use actix_web::{
+        App,
+        middleware::from_fn,
+        test::{self, TestRequest},
+    };
+
+    use super::*;
+    use crate::{
+        config::{Config, ListingCacheConfig},
+        render::CachedListing,
+    };
+
+    #[actix_web::test]
+    async fn only_ranges_from_the_first_byte_count() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut cfg = Config::default();
+        cfg.storage.uploads_directory = directory.path().display().to_string();
+        let storage = Storage::new(&cfg);
+        cfg.create_uploads_directory(&storage).unwrap();
+        let root = storage.namespace("ferris").unwrap().local_path("").unwrap();
+        fs::write(root.join("a.txt"), "hello").unwrap();
+
+        let limits = Data::new(
+            DownloadLimits::load(directory.path().join("downloads.json"))
+                .unwrap(),
+        );
+        limits.insert("ferris/a.txt".into(), 1).unwrap();
+        let listing_cache =
+            Data::new(ListingCache::new(ListingCacheConfig::default()));
+        let listing = TestRequest::get().uri("/ferris/").to_http_request();
+        listing_cache.insert(
+            "ferris",
+            &listing,
+            Arc::new(CachedListing::new(b"a.txt".to_vec())),
+        );
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(storage))
+                .app_data(limits.clone())
+                .app_data(listing_cache.clone())
+                .service(
+                    web::scope("/ferris").service(Files::new("", &root)).wrap(
+                        from_fn(|req, next| {
+                            enforce_download_limits(
+                                "ferris".to_string(),
+                                req,
+                                next,
+                            )
+                        }),
+                    ),
+                ),
+        )
+        .await;
+        let download = |range: &'static str| {
+            TestRequest::get()
+                .uri("/ferris/a.txt")
+                .insert_header((header::RANGE, range))
+                .to_request()
+        };
+
+        let resumed = test::call_service(&app, download("bytes=2-")).await;
+        assert_eq!(resumed.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(limits.get("ferris/a.txt").unwrap().downloads, 0);
+        assert!(root.join("a.txt").exists());
+
+        let first = test::call_service(&app, download("bytes=0-1")).await;
+        assert_eq!(first.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(!root.join("a.txt").exists());
+        assert!(!limits.is_limited("ferris/a.txt"));
+        assert!(listing_cache.get("ferris", &listing).is_none());
+    }
+}
@@ -0,0 +1,241 @@
+use std::{collections::HashMap, fs, io, path::PathBuf, sync::Mutex};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+/// How many times a limited file may be downloaded, and how many times it has
+/// been so far.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct DownloadLimit {
+    pub max_downloads: u32,
+    pub downloads: u32,
+}
+
+impl DownloadLimit {
+    pub fn is_exhausted(&self) -> bool {
+        self.downloads >= self.max_downloads
+    }
+}
+
+/// A download taken from a file's limit.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Counted {
+    /// The file isn't limited.
+    Unlimited,
+    /// The file can still be downloaded this many more times.
+    Remaining(u32),
+    /// That was the last download; the file should be deleted once served.
+    Last,
+}
+
+/// The file has no downloads left.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Exhausted;
+
+/// Download counts for limited files, persisted as JSON so limits survive
+/// restarts.
+///
+/// Files are keyed by their key in their namespace's storage backend. Records
+/// go along with their files, so a name can be reused by a later upload.
+pub struct DownloadLimits {
+    path: PathBuf,
+    limits: Mutex<HashMap<String, DownloadLimit>>,
+}
+
+impl DownloadLimits {
+    /// Loads the store from `path`, starting empty if it doesn't exist yet.
+    pub fn load(path: PathBuf) -> io::Result<DownloadLimits> {
+        let limits = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path,
+            limits: Mutex::new(limits),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<DownloadLimit> {
+        self.lock().get(key).copied()
+    }
+
+    pub fn is_limited(&self, key: &str) -> bool {
+        self.lock().contains_key(key)
+    }
+
+    /// Starts limiting the file at `key` to `max_downloads` downloads.
+    pub fn insert(&self, key: String, max_downloads: u32) -> io::Result<()> {
+        let mut limits = self.lock();
+        limits.insert(
+            key,
+            DownloadLimit {
+                max_downloads,
+                downloads: 0,
+            },
+        );
+        self.persist(&limits)
+    }
+
+    /// Stops limiting the file at `key`, as when it's deleted or its name is
+    /// claimed by a new upload.
+    pub fn remove(&self, key: &str) {
+        let mut limits = self.lock();
+        if limits.remove(key).is_none() {
+            return;
+        }
+
+        if let Err(e) = self.persist(&limits) {
+            error!("Failed to persist download counts: {}", e);
+        }
+    }
+
+    /// Takes one download of the file at `key` from its limit, before it's
+    /// served. Concurrent downloads each take their own, so none get past
+    /// the limit.
+    pub fn try_take(&self, key: &str) -> Result<Counted, Exhausted> {
+        let mut limits = self.lock();
+
+        let counted = match limits.get_mut(key) {
+            Some(limit) if limit.is_exhausted() => return Err(Exhausted),
+            Some(limit) => {
+                limit.downloads += 1;
+                if limit.is_exhausted() {
+                    Counted::Last
+                } else {
+                    Counted::Remaining(limit.max_downloads - limit.downloads)
+                }
+            }
+            None => return Ok(Counted::Unlimited),
+        };
+
+        if let Err(e) = self.persist(&limits) {
+            error!("Failed to persist download counts: {}", e);
+        }
+
+        Ok(counted)
+    }
+
+    /// Gives back a download taken by [`DownloadLimits::try_take`], when the
+    /// file wasn't served after all.
+    pub fn give_back(&self, key: &str) {
+        let mut limits = self.lock();
+
+        let Some(limit) = limits.get_mut(key) else {
+            return;
+        };
+        limit.downloads = limit.downloads.saturating_sub(1);
+
+        if let Err(e) = self.persist(&limits) {
+            error!("Failed to persist download counts: {}", e);
+        }
+    }
+
+    fn lock(
+        &self,
+    ) -> std::sync::MutexGuard<'_, HashMap<String, DownloadLimit>> {
+        self.limits
+            .lock()
+            .expect("download limits lock should not be poisoned")
+    }
+
+    /// Writes the store next to its final location and renames it into place,
+    /// so a crash can't leave a half-written file behind.
+    fn persist(
+        &self,
+        limits: &HashMap<String, DownloadLimit>,
+    ) -> io::Result<()> {
+        let serialized = serde_json::to_string_pretty(limits)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let temp_path = self.path.with_extension("json.tmp");
+        fs::write(&temp_path, serialized)?;
+        fs::rename(&temp_path, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+
+    fn store() -> (tempfile::TempDir, DownloadLimits) {
+        let dir = tempfile::tempdir().unwrap();
+        let limits =
+            DownloadLimits::load(dir.path().join("downloads.json")).unwrap();
+        (dir, limits)
+    }
+
+    #[test]
+    fn unlimited_files_are_not_counted() {
+        let (_dir, limits) = store();
+        assert_eq!(limits.try_take("ferris/a.png"), Ok(Counted::Unlimited));
+        assert!(!limits.is_limited("ferris/a.png"));
+    }
+
+    #[test]
+    fn takes_downloads_until_exhausted() {
+        let (_dir, limits) = store();
+        limits.insert("ferris/a.png".into(), 2).unwrap();
+
+        assert_eq!(limits.try_take("ferris/a.png"), Ok(Counted::Remaining(1)));
+        assert_eq!(limits.try_take("ferris/a.png"), Ok(Counted::Last));
+        assert_eq!(limits.try_take("ferris/a.png"), Err(Exhausted));
+    }
+
+    #[test]
+    fn given_back_downloads_can_be_taken_again() {
+        let (_dir, limits) = store();
+        limits.insert("ferris/a.png".into(), 1).unwrap();
+
+        assert_eq!(limits.try_take("ferris/a.png"), Ok(Counted::Last));
+        limits.give_back("ferris/a.png");
+        assert_eq!(limits.try_take("ferris/a.png"), Ok(Counted::Last));
+    }
+
+    #[test]
+    fn concurrent_downloads_share_the_limit() {
+        let (_dir, limits) = store();
+        limits.insert("ferris/a.png".into(), 3).unwrap();
+        let limits = Arc::new(limits);
+
+        let taken = (0..16)
+            .map(|_| {
+                let limits = limits.clone();
+                thread::spawn(move || limits.try_take("ferris/a.png").is_ok())
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|&taken| taken)
+            .count();
+        assert_eq!(taken, 3);
+    }
+
+    #[test]
+    fn removed_records_free_the_name() {
+        let (dir, limits) = store();
+        limits.insert("ferris/a.png".into(), 1).unwrap();
+        limits.try_take("ferris/a.png").unwrap();
+        limits.remove("ferris/a.png");
+
+        assert_eq!(limits.try_take("ferris/a.png"), Ok(Counted::Unlimited));
+        let reloaded =
+            DownloadLimits::load(dir.path().join("downloads.json")).unwrap();
+        assert!(!reloaded.is_limited("ferris/a.png"));
+    }
+
+    #[test]
+    fn counts_survive_a_restart() {
+        let (dir, limits) = store();
+        limits.insert("ferris/a.png".into(), 2).unwrap();
+        limits.try_take("ferris/a.png").unwrap();
+
+        let reloaded =
+            DownloadLimits::load(dir.path().join("downloads.json")).unwrap();
+        assert_eq!(reloaded.try_take("ferris/a.png"), Ok(Counted::Last));
+    }
+}
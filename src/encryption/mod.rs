@@ -0,0 +1,473 @@
+//! Encryption at rest for namespace storage.
+//!
+//! Files are split into fixed-size chunks, each sealed with
+//! XChaCha20-Poly1305 under its own nonce, so any byte range can be decrypted
+//! without reading the whole file:
+//!
+//! ```text
+//! header: magic (8) || nonce prefix (16) || plaintext length, u64 LE (8)
+//! chunks: ciphertext (up to CHUNK_SIZE) || tag (16), repeated
+//! ```
+//!
+//! The nonce of chunk `i` is the nonce prefix followed by `i` as a big-endian
+//! u64, and every chunk authenticates the header as associated data, so
+//! chunks can't be reordered, truncated or moved between files.
+
+use std::{
+    fs::File,
+    io::{self, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use chacha20poly1305::{
+    KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, Payload},
+};
+use rand::RngCore;
+
+use crate::{config::EncryptionKey, storage::NamespaceStorage};
+
+const MAGIC: &[u8; 8] = b"MSENC\0\0\x01";
+const HEADER_LEN: u64 = 32;
+const TAG_LEN: u64 = 16;
+
+/// Plaintext bytes per chunk.
+pub const CHUNK_SIZE: u64 = 64 * 1024;
+
+struct Header {
+    raw: [u8; HEADER_LEN as usize],
+}
+
+impl Header {
+    fn new(plaintext_len: u64) -> Header {
+        let mut raw = [0; HEADER_LEN as usize];
+        raw[..8].copy_from_slice(MAGIC);
+        rand::rng().fill_bytes(&mut raw[8..24]);
+        raw[24..].copy_from_slice(&plaintext_len.to_le_bytes());
+        Self { raw }
+    }
+
+    fn read(reader: &mut impl Read) -> io::Result<Header> {
+        let mut raw = [0; HEADER_LEN as usize];
+        reader.read_exact(&mut raw)?;
+
+        if &raw[..8] != MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "file is not encrypted at rest",
+            ));
+        }
+
+        Ok(Self { raw })
+    }
+
+    fn plaintext_len(&self) -> u64 {
+        u64::from_le_bytes(
+            self.raw[24..]
+                .try_into()
+                .expect("header should end with a u64"),
+        )
+    }
+
+    fn nonce(&self, chunk: u64) -> XNonce {
+        let mut nonce = [0; 24];
+        nonce[..16].copy_from_slice(&self.raw[8..24]);
+        nonce[16..].copy_from_slice(&chunk.to_be_bytes());
+        XNonce::from(nonce)
+    }
+}
+
+fn cipher(key: &EncryptionKey) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new(&key.0.into())
+}
+
+/// Encrypts `plaintext_len` bytes from `reader` into `writer`.
+pub fn encrypt(
+    key: &EncryptionKey,
+    plaintext_len: u64,
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    let cipher = cipher(key);
+    let header = Header::new(plaintext_len);
+    writer.write_all(&header.raw)?;
+
+    let mut buffer = vec![0; CHUNK_SIZE as usize];
+    let mut remaining = plaintext_len;
+    let mut chunk = 0;
+
+    while remaining > 0 {
+        let length = remaining.min(CHUNK_SIZE) as usize;
+        reader.read_exact(&mut buffer[..length])?;
+
+        let sealed = cipher
+            .encrypt(
+                &header.nonce(chunk),
+                Payload {
+                    msg: &buffer[..length],
+                    aad: &header.raw,
+                },
+            )
+            .map_err(|_| io::Error::other("failed to encrypt chunk"))?;
+        writer.write_all(&sealed)?;
+
+        remaining -= length as u64;
+        chunk += 1;
+    }
+
+    writer.flush()
+}
+
+/// Encrypts the file at `source` into `destination`.
+pub fn encrypt_file(
+    key: &EncryptionKey,
+    source: &Path,
+    destination: &Path,
+) -> io::Result<()> {
+    let mut source = File::open(source)?;
+    let plaintext_len = source.metadata()?.len();
+    let mut destination = io::BufWriter::new(File::create(destination)?);
+
+    encrypt(key, plaintext_len, &mut source, &mut destination)
+}
+
+/// Whether the file at `key` is encrypted at rest. Files uploaded before a
+/// namespace turned encryption on are left as they were, and are served as
+/// plaintext.
+pub fn is_encrypted(storage: &NamespaceStorage, key: &str) -> io::Result<bool> {
+    let mut magic = [0; MAGIC.len()];
+    let mut reader = storage.get_range(key, 0, MAGIC.len() as u64)?;
+    match reader.read_exact(&mut magic) {
+        Ok(_) => Ok(&magic == MAGIC),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// The size of the contents of the file at `key`, which is `len` bytes in
+/// storage, whether it's encrypted or not.
+pub fn content_len(storage: &NamespaceStorage, key: &str, len: u64) -> u64 {
+    match is_encrypted(storage, key) {
+        Ok(true) => plaintext_len(len),
+        _ => len,
+    }
+}
+
+/// The plaintext size of an encrypted file that is `ciphertext_len` bytes in
+/// storage, worked out without reading it.
+pub fn plaintext_len(ciphertext_len: u64) -> u64 {
+    let body = ciphertext_len.saturating_sub(HEADER_LEN);
+    let chunks = body.div_ceil(CHUNK_SIZE + TAG_LEN);
+    body.saturating_sub(chunks * TAG_LEN)
+}
+
+/// An encrypted file, readable as its plaintext from any position.
+///
+/// The ciphertext is read as one stream from the chunk the position falls
+/// in, so sequential reads cost a single request to the backend.
+pub struct EncryptedFile {
+    storage: NamespaceStorage,
+    key: String,
+    ciphertext_len: u64,
+    header: Header,
+    cipher: XChaCha20Poly1305,
+    position: u64,
+    /// The ciphertext from the start of the given chunk onwards.
+    reader: Option<(u64, Box<dyn Read + Send>)>,
+    /// The decrypted chunk `position` falls in, if it has been read yet.
+    chunk: Option<(u64, Vec<u8>)>,
+}
+
+impl EncryptedFile {
+    /// Opens the file at `key`, which is `ciphertext_len` bytes in storage.
+    pub fn open(
+        storage: &NamespaceStorage,
+        key: &str,
+        ciphertext_len: u64,
+        encryption_key: &EncryptionKey,
+    ) -> io::Result<EncryptedFile> {
+        let header = Header::read(&mut storage.get_range(key, 0, HEADER_LEN)?)?;
+
+        Ok(Self {
+            storage: storage.clone(),
+            key: key.to_string(),
+            ciphertext_len,
+            header,
+            cipher: cipher(encryption_key),
+            position: 0,
+            reader: None,
+            chunk: None,
+        })
+    }
+
+    pub fn plaintext_len(&self) -> u64 {
+        self.header.plaintext_len()
+    }
+
+    /// Moves the read position to `position` in the plaintext, and starts
+    /// reading the ciphertext from there.
+    pub fn seek_to(&mut self, position: u64) -> io::Result<()> {
+        self.position = position.min(self.plaintext_len());
+        self.open_reader(self.position / CHUNK_SIZE)
+    }
+
+    fn open_reader(&mut self, index: u64) -> io::Result<()> {
+        if self.reader.as_ref().is_some_and(|(next, _)| *next == index) {
+            return Ok(());
+        }
+
+        let offset = (HEADER_LEN + index * (CHUNK_SIZE + TAG_LEN))
+            .min(self.ciphertext_len);
+        let reader = self.storage.get_range(
+            &self.key,
+            offset,
+            self.ciphertext_len - offset,
+        )?;
+        self.reader = Some((index, reader));
+
+        Ok(())
+    }
+
+    fn load_chunk(&mut self, index: u64) -> io::Result<()> {
+        if self
+            .chunk
+            .as_ref()
+            .is_some_and(|(loaded, _)| *loaded == index)
+        {
+            return Ok(());
+        }
+
+        self.open_reader(index)?;
+
+        let plain_len =
+            (self.plaintext_len() - index * CHUNK_SIZE).min(CHUNK_SIZE);
+
+        let mut sealed = vec![0; (plain_len + TAG_LEN) as usize];
+        let (next, reader) = self
+            .reader
+            .as_mut()
+            .expect("reader should have been opened");
+        reader.read_exact(&mut sealed)?;
+        *next += 1;
+
+        let plain = self
+            .cipher
+            .decrypt(
+                &self.header.nonce(index),
+                Payload {
+                    msg: &sealed,
+                    aad: &self.header.raw,
+                },
+            )
+            .map_err(|_| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    "encrypted chunk failed to authenticate",
+                )
+            })?;
+
+        self.chunk = Some((index, plain));
+        Ok(())
+    }
+}
+impl Read for EncryptedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.plaintext_len() || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let index = self.position / CHUNK_SIZE;
+        self.load_chunk(index)?;
+
+        let (_, chunk) =
+            self.chunk.as_ref().expect("chunk should have been loaded");
+        let offset = (self.position - index * CHUNK_SIZE) as usize;
+        let length = buf.len().min(chunk.len() - offset);
+
+        buf[..length].copy_from_slice(&chunk[offset..offset + length]);
+        self.position += length as u64;
+
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::storage::{FilesystemBackend, Stat};
+
+    const KEY: EncryptionKey = EncryptionKey([7; 32]);
+
+    /// Two and a half chunks of bytes that differ from chunk to chunk.
+    fn plaintext() -> Vec<u8> {
+        (0..CHUNK_SIZE * 5 / 2).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn stored(plaintext: &[u8]) -> (TempDir, NamespaceStorage, Vec<u8>) {
+        let mut ciphertext = Vec::new();
+        encrypt(
+            &KEY,
+            plaintext.len() as u64,
+            &mut &plaintext[..],
+            &mut ciphertext,
+        )
+        .unwrap();
+
+        let directory = tempfile::tempdir().unwrap();
+        fs::write(directory.path().join("file.enc"), &ciphertext).unwrap();
+        let storage = NamespaceStorage::new(
+            "f",
+            Arc::new(FilesystemBackend::new(directory.path().to_path_buf())),
+            "",
+        );
+
+        (directory, storage, ciphertext)
+    }
+
+    fn open(storage: &NamespaceStorage, key: &EncryptionKey) -> EncryptedFile {
+        let Some(Stat::File { size, .. }) = storage.stat("file.enc").unwrap()
+        else {
+            panic!("file.enc should be a file");
+        };
+        EncryptedFile::open(storage, "file.enc", size, key).unwrap()
+    }
+
+    fn read_all(file: &mut EncryptedFile) -> io::Result<Vec<u8>> {
+        let mut plaintext = Vec::new();
+        file.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn frames_chunks_after_the_header() {
+        let plaintext = plaintext();
+        let (_directory, storage, ciphertext) = stored(&plaintext);
+
+        assert_eq!(&ciphertext[..8], MAGIC);
+        assert_eq!(ciphertext[24..32], (plaintext.len() as u64).to_le_bytes());
+        // two full chunks and a half one, each with a tag
+        assert_eq!(
+            ciphertext.len() as u64,
+            HEADER_LEN + CHUNK_SIZE * 5 / 2 + 3 * TAG_LEN
+        );
+        assert_eq!(
+            plaintext_len(ciphertext.len() as u64),
+            plaintext.len() as u64
+        );
+        assert!(is_encrypted(&storage, "file.enc").unwrap());
+    }
+
+    #[test]
+    fn round_trips() {
+        let plaintext = plaintext();
+        let (_directory, storage, _) = stored(&plaintext);
+
+        let mut file = open(&storage, &KEY);
+        assert_eq!(file.plaintext_len(), plaintext.len() as u64);
+        assert_eq!(read_all(&mut file).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn round_trips_empty_files() {
+        let (_directory, storage, ciphertext) = stored(&[]);
+
+        assert_eq!(ciphertext.len() as u64, HEADER_LEN);
+        assert_eq!(plaintext_len(HEADER_LEN), 0);
+        assert!(read_all(&mut open(&storage, &KEY)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reads_from_any_position() {
+        let plaintext = plaintext();
+        let (_directory, storage, _) = stored(&plaintext);
+        let mut file = open(&storage, &KEY);
+
+        for position in [0, 1, CHUNK_SIZE - 1, CHUNK_SIZE, CHUNK_SIZE * 2 + 5] {
+            file.seek_to(position).unwrap();
+            let mut buffer = vec![0; 10];
+            file.read_exact(&mut buffer).unwrap();
+            let start = position as usize;
+            assert_eq!(buffer, plaintext[start..start + 10]);
+        }
+
+        file.seek_to(plaintext.len() as u64 + 100).unwrap();
+        assert!(read_all(&mut file).unwrap().is_empty());
+    }
+
+    #[test]
+    fn refuses_the_wrong_key() {
+        let (_directory, storage, _) = stored(&plaintext());
+
+        let mut file = open(&storage, &EncryptionKey([8; 32]));
+        let error = read_all(&mut file).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn detects_tampered_chunks() {
+        let plaintext = plaintext();
+        let (directory, storage, mut ciphertext) = stored(&plaintext);
+        let second_chunk = (HEADER_LEN + CHUNK_SIZE + TAG_LEN) as usize;
+        ciphertext[second_chunk + 3] ^= 1;
+        fs::write(directory.path().join("file.enc"), &ciphertext).unwrap();
+
+        let mut file = open(&storage, &KEY);
+        // the first chunk is still fine
+        let mut buffer = vec![0; CHUNK_SIZE as usize];
+        file.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, plaintext[..CHUNK_SIZE as usize]);
+
+        let error = read_all(&mut file).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn detects_a_tampered_length() {
+        let (directory, storage, mut ciphertext) = stored(&plaintext());
+        // claim the file ends after the first chunk
+        ciphertext[24..32].copy_from_slice(&CHUNK_SIZE.to_le_bytes());
+        fs::write(directory.path().join("file.enc"), &ciphertext).unwrap();
+
+        let error = read_all(&mut open(&storage, &KEY)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn detects_truncation() {
+        let (directory, storage, mut ciphertext) = stored(&plaintext());
+        // drop the last chunk
+        ciphertext
+            .truncate(ciphertext.len() - (CHUNK_SIZE / 2 + TAG_LEN) as usize);
+        fs::write(directory.path().join("file.enc"), &ciphertext).unwrap();
+
+        assert!(read_all(&mut open(&storage, &KEY)).is_err());
+    }
+
+    #[test]
+    fn detects_reordered_chunks() {
+        let (directory, storage, mut ciphertext) = stored(&plaintext());
+        let chunk = (CHUNK_SIZE + TAG_LEN) as usize;
+        let start = HEADER_LEN as usize;
+        let (first, rest) = ciphertext[start..].split_at_mut(chunk);
+        first.swap_with_slice(&mut rest[..chunk]);
+        fs::write(directory.path().join("file.enc"), &ciphertext).unwrap();
+
+        let error = read_all(&mut open(&storage, &KEY)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn leaves_plaintext_files_alone() {
+        let (directory, storage, _) = stored(&[]);
+        fs::write(directory.path().join("plain.txt"), b"hello").unwrap();
+        fs::write(directory.path().join("short.txt"), b"hi").unwrap();
+
+        assert!(!is_encrypted(&storage, "plain.txt").unwrap());
+        assert!(!is_encrypted(&storage, "short.txt").unwrap());
+        assert_eq!(content_len(&storage, "plain.txt", 5), 5);
+    }
+}
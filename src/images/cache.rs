@@ -0,0 +1,112 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::PathBuf,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use log::{debug, warn};
+use tempfile::NamedTempFile;
+
+/// Transformed variants of images, kept on disk.
+///
+/// Once they take up more than `max_size` bytes, the least recently used ones
+/// are removed. Variants are never invalidated: their ids include everything
+/// that went into them, so a changed image simply gets new ones.
+pub struct VariantCache {
+    directory: PathBuf,
+    max_size: u64,
+    /// Keeps concurrent inserts from evicting at the same time.
+    evicting: Mutex<()>,
+}
+
+impl VariantCache {
+    pub fn new(directory: PathBuf, max_size: u64) -> VariantCache {
+        Self {
+            directory,
+            max_size,
+            evicting: Mutex::new(()),
+        }
+    }
+
+    /// Opens the variant `id`, if it's cached.
+    pub fn get(&self, id: &str) -> Option<File> {
+        let file = File::open(self.directory.join(id)).ok()?;
+
+        // the modification time doubles as the last time it was used
+        if let Err(e) = file.set_modified(SystemTime::now()) {
+            debug!("Failed to mark {} as used: {}", id, e);
+        }
+
+        Some(file)
+    }
+
+    /// A temporary file to write a variant to before it's inserted.
+    pub fn temp_file(&self) -> io::Result<NamedTempFile> {
+        tempfile::Builder::new()
+            .prefix(".variant")
+            .tempfile_in(&self.directory)
+    }
+
+    /// Stores `file` as the variant `id`, returning it opened for reading.
+    pub fn insert(&self, id: &str, file: NamedTempFile) -> io::Result<File> {
+        let path = self.directory.join(id);
+        file.persist(&path).map_err(|e| e.error)?;
+        // opened before evicting, in case it's the one to go
+        let file = File::open(&path)?;
+
+        if let Err(e) = self.evict() {
+            warn!("Failed to evict cached image variants: {}", e);
+        }
+
+        Ok(file)
+    }
+
+    fn evict(&self) -> io::Result<()> {
+        let _evicting = self
+            .evicting
+            .lock()
+            .expect("variant cache lock should not be poisoned");
+
+        let mut variants = Vec::new();
+        let mut total = 0;
+        for entry in fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            // skip variants still being written
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            let metadata = entry.metadata()?;
+            total += metadata.len();
+            variants.push((
+                metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                metadata.len(),
+                entry.path(),
+            ));
+        }
+
+        if total <= self.max_size {
+            return Ok(());
+        }
+
+        variants.sort();
+        for (_, size, path) in variants {
+            if total <= self.max_size {
+                break;
+            }
+
+            match fs::remove_file(&path) {
+                Ok(_) => {
+                    debug!("Evicted {}", path.display());
+                    total -= size;
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => total -= size,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
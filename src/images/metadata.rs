@@ -0,0 +1,398 @@
+//! Removing metadata from images that aren't re-encoded.
+//!
+//! Animated images would lose their frames if they were decoded, so their
+//! metadata is cut out of the container instead, leaving the image data as
+//! it was. Formats whose metadata can't be told apart from the image this
+//! way are refused.
+
+use std::io::{self, ErrorKind};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// PNG chunks that carry metadata: EXIF, text (XMP included) and the time
+/// the image was last changed.
+const PNG_METADATA: [&[u8; 4]; 5] =
+    [b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+/// GIF application extensions needed to play the animation.
+const GIF_LOOPING: [&[u8]; 2] = [b"NETSCAPE2.0", b"ANIMEXTS1.0"];
+
+/// ISO base media brands of HEIF and AVIF images.
+const HEIF_BRANDS: [&[u8; 4]; 10] = [
+    b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1",
+    b"avif", b"avis",
+];
+
+/// Removes the metadata from PNG, WebP and GIF images, returning the image
+/// without it. Returns `None` for anything that isn't an image.
+///
+/// TIFF, HEIF and AVIF images fail with [`ErrorKind::InvalidData`], as do
+/// images that aren't laid out the way their format says.
+pub fn strip(bytes: &[u8]) -> io::Result<Option<Vec<u8>>> {
+    if bytes.starts_with(PNG_SIGNATURE) {
+        strip_png(bytes).map(Some)
+    } else if bytes.starts_with(b"RIFF")
+        && bytes.get(8..12) == Some(b"WEBP".as_slice())
+    {
+        strip_webp(bytes).map(Some)
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        strip_gif(bytes).map(Some)
+    } else if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+        Err(unsupported("TIFF"))
+    } else if bytes.get(4..8) == Some(b"ftyp".as_slice())
+        && bytes
+            .get(8..12)
+            .is_some_and(|brand| HEIF_BRANDS.iter().any(|b| b == &brand))
+    {
+        Err(unsupported("HEIF and AVIF"))
+    } else {
+        Ok(None)
+    }
+}
+
+fn strip_png(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stripped = PNG_SIGNATURE.to_vec();
+    let mut position = PNG_SIGNATURE.len();
+
+    while position < bytes.len() {
+        let length = read_u32_be(bytes, position)? as usize;
+        // length, type, data and CRC
+        let end = position
+            .checked_add(length + 12)
+            .filter(|&end| end <= bytes.len())
+            .ok_or_else(|| malformed("PNG"))?;
+        let kind = &bytes[position + 4..position + 8];
+
+        if !PNG_METADATA.iter().any(|metadata| metadata == &kind) {
+            stripped.extend_from_slice(&bytes[position..end]);
+        }
+        position = end;
+
+        if kind == b"IEND" {
+            break;
+        }
+    }
+
+    Ok(stripped)
+}
+
+fn strip_webp(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stripped = bytes[..12].to_vec();
+    let mut position = 12;
+
+    while position < bytes.len() {
+        let kind = bytes
+            .get(position..position + 4)
+            .ok_or_else(|| malformed("WebP"))?;
+        let length = read_u32_le(bytes, position + 4)? as usize;
+        let data_end = position + 8 + length;
+        if data_end > bytes.len() {
+            return Err(malformed("WebP"));
+        }
+        // chunks are padded to an even length
+        let end = (data_end + length % 2).min(bytes.len());
+
+        match kind {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let start = stripped.len();
+                stripped.extend_from_slice(&bytes[position..end]);
+                // the flags say whether there's EXIF or XMP to look for
+                if let Some(flags) = stripped.get_mut(start + 8) {
+                    *flags &= !(0x08 | 0x04);
+                }
+            }
+            _ => stripped.extend_from_slice(&bytes[position..end]),
+        }
+        position = end;
+    }
+
+    let riff_length =
+        u32::try_from(stripped.len() - 8).map_err(|_| malformed("WebP"))?;
+    stripped[4..8].copy_from_slice(&riff_length.to_le_bytes());
+
+    Ok(stripped)
+}
+
+fn strip_gif(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let malformed = || malformed("GIF");
+
+    // header and logical screen descriptor, then the global color table
+    let flags = *bytes.get(10).ok_or_else(malformed)?;
+    let mut position = 13 + color_table_length(flags);
+    let mut stripped = bytes.get(..position).ok_or_else(malformed)?.to_vec();
+
+    loop {
+        match bytes.get(position).ok_or_else(malformed)? {
+            0x21 => {
+                let label = *bytes.get(position + 1).ok_or_else(malformed)?;
+                let end = skip_sub_blocks(bytes, position + 2)?;
+                let keep = match label {
+                    // comments
+                    0xfe => false,
+                    // application extensions, only some of which are needed
+                    0xff => bytes
+                        .get(position + 3..position + 14)
+                        .is_some_and(|id| GIF_LOOPING.contains(&id)),
+                    _ => true,
+                };
+                if keep {
+                    stripped.extend_from_slice(&bytes[position..end]);
+                }
+                position = end;
+            }
+            0x2c => {
+                let flags = *bytes.get(position + 9).ok_or_else(malformed)?;
+                // the descriptor, local color table and LZW code size
+                let data = position + 10 + color_table_length(flags) + 1;
+                let end = skip_sub_blocks(bytes, data)?;
+                stripped.extend_from_slice(&bytes[position..end]);
+                position = end;
+            }
+            0x3b => {
+                stripped.push(0x3b);
+                return Ok(stripped);
+            }
+            _ => return Err(malformed()),
+        }
+    }
+}
+
+/// The length of the color table a GIF descriptor with `flags` has.
+fn color_table_length(flags: u8) -> usize {
+    if flags & 0x80 == 0 {
+        0
+    } else {
+        3 << ((flags & 0x07) + 1)
+    }
+}
+
+/// Where the GIF sub-blocks starting at `position` end, past their
+/// terminator.
+fn skip_sub_blocks(bytes: &[u8], mut position: usize) -> io::Result<usize> {
+    loop {
+        let length = *bytes.get(position).ok_or_else(|| malformed("GIF"))?;
+        position += 1 + length as usize;
+        if length == 0 {
+            return Ok(position);
+        }
+    }
+}
+
+fn read_u32_be(bytes: &[u8], position: usize) -> io::Result<u32> {
+    bytes
+        .get(position..position + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| malformed("PNG"))
+}
+
+fn read_u32_le(bytes: &[u8], position: usize) -> io::Result<u32> {
+    bytes
+        .get(position..position + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| malformed("WebP"))
+}
+
+fn malformed(format: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("The {} image is malformed", format),
+    )
+}
+
+fn unsupported(format: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "{} images can't have their metadata removed, so they aren't \
+             accepted here",
+            format
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{
+        Delay, Frame, ImageFormat, ImageReader, Rgba, RgbaImage,
+        codecs::gif::{GifEncoder, Repeat},
+    };
+
+    use super::*;
+
+    fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in bytes {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+
+    fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        let crc = crc32(&chunk[4..]);
+        chunk.extend_from_slice(&crc.to_be_bytes());
+        chunk
+    }
+
+    fn png() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255]))
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    fn webp_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = kind.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn webp(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = chunks.concat();
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        bytes.extend_from_slice(b"WEBP");
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    fn gif() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut bytes);
+            encoder.set_repeat(Repeat::Infinite).unwrap();
+            for color in [[255, 0, 0, 255], [0, 0, 255, 255]] {
+                encoder
+                    .encode_frame(Frame::from_parts(
+                        RgbaImage::from_pixel(2, 2, Rgba(color)),
+                        0,
+                        0,
+                        Delay::from_numer_denom_ms(100, 1),
+                    ))
+                    .unwrap();
+            }
+        }
+        bytes
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    #[test]
+    fn strips_png_metadata_chunks() {
+        let original = png();
+        // put the metadata right after IHDR
+        let ihdr_end = PNG_SIGNATURE.len() + 12 + 13;
+        let mut bytes = original[..ihdr_end].to_vec();
+        bytes.extend(png_chunk(b"tEXt", b"Comment\0secret"));
+        bytes.extend(png_chunk(b"eXIf", b"MM\0*GPS"));
+        bytes.extend_from_slice(&original[ihdr_end..]);
+
+        let stripped = strip(&bytes).unwrap().unwrap();
+
+        assert_eq!(stripped, original);
+        assert!(!contains(&stripped, b"secret"));
+        image::load_from_memory(&stripped).unwrap();
+    }
+
+    #[test]
+    fn strips_webp_metadata_chunks() {
+        // VP8X with the EXIF, XMP and animation flags set
+        let mut vp8x = vec![0x08 | 0x04 | 0x02, 0, 0, 0];
+        vp8x.extend_from_slice(&[1, 0, 0, 1, 0, 0]);
+        let bytes = webp(&[
+            webp_chunk(b"VP8X", &vp8x),
+            webp_chunk(b"ANIM", &[0; 6]),
+            webp_chunk(b"EXIF", b"GPS"),
+            webp_chunk(b"XMP ", b"<x:xmpmeta/>"),
+        ]);
+
+        let stripped = strip(&bytes).unwrap().unwrap();
+
+        let mut flags = vp8x.clone();
+        flags[0] = 0x02;
+        assert_eq!(
+            stripped,
+            webp(&[webp_chunk(b"VP8X", &flags), webp_chunk(b"ANIM", &[0; 6])])
+        );
+    }
+
+    #[test]
+    fn refuses_truncated_webp_chunks() {
+        let mut bytes = webp(&[webp_chunk(b"VP8L", &[0; 8])]);
+        bytes.truncate(bytes.len() - 2);
+
+        assert!(strip(&bytes).is_err());
+    }
+
+    #[test]
+    fn strips_gif_comments_and_application_extensions() {
+        let original = gif();
+        assert!(contains(&original, b"NETSCAPE2.0"));
+
+        // the first block after the header and the global color table
+        let position = 13 + color_table_length(original[10]);
+        let mut bytes = original[..position].to_vec();
+        bytes.extend_from_slice(&[0x21, 0xfe, 6]);
+        bytes.extend_from_slice(b"secret");
+        bytes.push(0);
+        bytes.extend_from_slice(&[0x21, 0xff, 11]);
+        bytes.extend_from_slice(b"XMP DataXMP");
+        bytes.extend_from_slice(&[3, b'g', b'p', b's', 0]);
+        bytes.extend_from_slice(&original[position..]);
+
+        let stripped = strip(&bytes).unwrap().unwrap();
+
+        assert_eq!(stripped, original);
+        assert!(!contains(&stripped, b"secret"));
+        let frames =
+            ImageReader::with_format(Cursor::new(&stripped), ImageFormat::Gif)
+                .decode()
+                .unwrap();
+        assert_eq!(frames.width(), 2);
+    }
+
+    #[test]
+    fn refuses_gifs_without_a_trailer() {
+        let mut bytes = gif();
+        bytes.pop();
+
+        assert!(strip(&bytes).is_err());
+    }
+
+    #[test]
+    fn refuses_tiff_and_heif_images() {
+        let tiff = strip(b"II*\0\x08\0\0\0").unwrap_err();
+        assert_eq!(tiff.kind(), ErrorKind::InvalidData);
+
+        let heic = strip(b"\0\0\0\x18ftypheic\0\0\0\0mif1heic").unwrap_err();
+        assert_eq!(heic.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn leaves_other_files_alone() {
+        assert!(strip(b"just some text").unwrap().is_none());
+        assert!(strip(b"\0\0\0\x18ftypisom").unwrap().is_none());
+        assert!(strip(b"").unwrap().is_none());
+    }
+}
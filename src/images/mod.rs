@@ -0,0 +1,202 @@
+//! Image processing for uploads.
+//!
+//! Images are decoded and encoded again, which leaves their metadata behind:
+//! none of the encoders write EXIF, XMP or ICC data they aren't handed.
+//! Animated images keep their frames and only lose their metadata; see
+//! [`metadata`].
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Seek, Write},
+    path::Path,
+};
+
+use image::{
+    DynamicImage, ImageDecoder, ImageError, ImageFormat, Limits,
+    codecs::{
+        avif::AvifEncoder,
+        jpeg::{JpegDecoder, JpegEncoder},
+        png::{PngDecoder, PngEncoder},
+        webp::{WebPDecoder, WebPEncoder},
+    },
+    imageops::FilterType,
+};
+use tempfile::{NamedTempFile, TempPath};
+
+use crate::config::{ImageOutputFormat, ImageProcessingConfig};
+
+mod cache;
+mod metadata;
+mod transform;
+
+pub use cache::VariantCache;
+pub use transform::transform_images;
+
+/// rav1e's speed preset, from 1 (slowest) to 10. Anything below this takes
+/// noticeably long on photos straight off a phone.
+const AVIF_SPEED: u8 = 7;
+
+/// An image after processing.
+pub struct Processed {
+    pub file: TempPath,
+    /// The extension the new format goes by, if the format changed.
+    pub extension: Option<&'static str>,
+}
+
+/// Processes the image at `path` the way `config` says to, writing the result
+/// to a temporary file in `temp_dir`.
+///
+/// Images [`decode`] doesn't handle only have their metadata removed. Returns
+/// `None` for anything that isn't an image.
+pub fn process(
+    config: &ImageProcessingConfig,
+    path: &Path,
+    temp_dir: &Path,
+) -> io::Result<Option<Processed>> {
+    let reader = BufReader::new(File::open(path)?);
+    let Some((mut image, format)) = decode(reader, config.auto_orient)? else {
+        return strip_metadata(path, temp_dir);
+    };
+
+    let max = config.max_dimension;
+    if max > 0 && (image.width() > max || image.height() > max) {
+        image = image.resize(max, max, FilterType::Lanczos3);
+    }
+
+    let output_format = output_format(config.format).unwrap_or(format);
+
+    let output = NamedTempFile::new_in(temp_dir)?;
+    write(&image, output_format, config.quality, output.as_file())?;
+
+    Ok(Some(Processed {
+        file: output.into_temp_path(),
+        extension: (output_format != format)
+            .then(|| output_format.extensions_str()[0]),
+    }))
+}
+
+/// Removes the metadata from the image at `path` without decoding it.
+fn strip_metadata(
+    path: &Path,
+    temp_dir: &Path,
+) -> io::Result<Option<Processed>> {
+    let Some(stripped) = metadata::strip(&fs::read(path)?)? else {
+        return Ok(None);
+    };
+
+    let mut output = NamedTempFile::new_in(temp_dir)?;
+    output.write_all(&stripped)?;
+
+    Ok(Some(Processed {
+        file: output.into_temp_path(),
+        extension: None,
+    }))
+}
+
+/// Decodes a still JPEG, PNG or WebP image, returning it along with the
+/// format it was in.
+///
+/// Returns `None` for anything else, and for animated images, whose frames
+/// would be lost. Images that can't be decoded fail with
+/// [`ErrorKind::InvalidData`].
+pub fn decode(
+    mut reader: impl BufRead + Seek,
+    auto_orient: bool,
+) -> io::Result<Option<(DynamicImage, ImageFormat)>> {
+    let format = match image::guess_format(reader.fill_buf()?) {
+        Ok(
+            format @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP),
+        ) => format,
+        _ => return Ok(None),
+    };
+
+    let mut decoder: Box<dyn ImageDecoder> = match format {
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(reader).map_err(invalid)?;
+            if decoder.is_apng().map_err(invalid)? {
+                return Ok(None);
+            }
+            Box::new(decoder)
+        }
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(reader).map_err(invalid)?;
+            if decoder.has_animation() {
+                return Ok(None);
+            }
+            Box::new(decoder)
+        }
+        _ => Box::new(JpegDecoder::new(reader).map_err(invalid)?),
+    };
+    // keeps a tiny file claiming to be enormous from eating all the memory
+    decoder.set_limits(Limits::default()).map_err(invalid)?;
+
+    let orientation = decoder.orientation().map_err(invalid)?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(invalid)?;
+
+    if auto_orient {
+        image.apply_orientation(orientation);
+    }
+
+    Ok(Some((image, format)))
+}
+
+/// The format `format` stands for, or `None` to keep the original one.
+pub fn output_format(format: ImageOutputFormat) -> Option<ImageFormat> {
+    match format {
+        ImageOutputFormat::Original => None,
+        ImageOutputFormat::Png => Some(ImageFormat::Png),
+        ImageOutputFormat::Jpeg => Some(ImageFormat::Jpeg),
+        ImageOutputFormat::Webp => Some(ImageFormat::WebP),
+        ImageOutputFormat::Avif => Some(ImageFormat::Avif),
+    }
+}
+
+/// Encodes `image` as `format` into `file`.
+pub fn write(
+    image: &DynamicImage,
+    format: ImageFormat,
+    quality: u8,
+    file: &File,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(file);
+    encode(image, format, quality, &mut writer).map_err(io::Error::other)?;
+    writer.flush()
+}
+
+/// Encodes `image` into memory, for variants that mustn't touch the disk.
+pub fn write_to_vec(
+    image: &DynamicImage,
+    format: ImageFormat,
+    quality: u8,
+) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    encode(image, format, quality, &mut bytes).map_err(io::Error::other)?;
+    Ok(bytes)
+}
+
+fn encode(
+    image: &DynamicImage,
+    format: ImageFormat,
+    quality: u8,
+    writer: &mut impl Write,
+) -> Result<(), ImageError> {
+    let quality = quality.clamp(1, 100);
+
+    match format {
+        // JPEG has no alpha channel
+        ImageFormat::Jpeg => DynamicImage::from(image.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(writer, quality)),
+        // neither encoder takes 16-bit or float samples
+        ImageFormat::WebP => DynamicImage::from(image.to_rgba8())
+            .write_with_encoder(WebPEncoder::new_lossless(writer)),
+        ImageFormat::Avif => DynamicImage::from(image.to_rgba8())
+            .write_with_encoder(AvifEncoder::new_with_speed_quality(
+                writer, AVIF_SPEED, quality,
+            )),
+        _ => image.write_with_encoder(PngEncoder::new(writer)),
+    }
+}
+
+fn invalid(error: ImageError) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, error)
+}
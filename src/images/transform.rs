@@ -0,0 +1,509 @@
+use std::{
+    fs::File,
+    io::{self, Cursor, Read},
+    path::Path,
+    time::SystemTime,
+};
+
+use actix_files::NamedFile;
+use actix_web::{
+    Error, HttpResponse,
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        Method,
+        header::{
+            self, ContentDisposition, DispositionParam, DispositionType, ETag,
+            EntityTag, Header, HeaderMap, HttpDate, IfNoneMatch, LastModified,
+        },
+    },
+    middleware::Next,
+    web::{self, Data},
+};
+use image::{DynamicImage, ImageFormat, imageops::FilterType};
+use log::error;
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::VariantCache;
+use crate::{
+    config::{Config, EncryptionKey, ImageTransformConfig},
+    encryption::{self, EncryptedFile},
+    storage::{self, NamespaceStorage, Stat, Storage},
+};
+
+/// Used when `q` is left out.
+const DEFAULT_QUALITY: u8 = 80;
+
+/// How long clients may keep a variant without checking back.
+const MAX_AGE_SECONDS: u32 = 24 * 60 * 60;
+
+/// How the image is fitted into the box when both `w` and `h` are given.
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum Fit {
+    /// Shrink to fit inside the box, keeping the aspect ratio.
+    #[default]
+    Contain,
+    /// Fill the box, keeping the aspect ratio and cropping the overflow.
+    Cover,
+    /// Stretch to the box.
+    Fill,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum TransformFormat {
+    /// The best format the client says it accepts.
+    Auto,
+    Jpeg,
+    Png,
+    /// Lossless.
+    Webp,
+    Avif,
+}
+
+/// The query parameters asking for a transformed image.
+#[derive(Debug, Deserialize)]
+struct TransformQuery {
+    w: Option<u32>,
+    h: Option<u32>,
+    fit: Option<Fit>,
+    format: Option<TransformFormat>,
+    q: Option<u8>,
+}
+
+/// A validated [`TransformQuery`].
+#[derive(Debug)]
+struct Transform {
+    width: Option<u32>,
+    height: Option<u32>,
+    fit: Fit,
+    format: ImageFormat,
+    quality: u8,
+    /// Whether the format was picked from the `Accept` header.
+    negotiated: bool,
+}
+
+impl TransformQuery {
+    fn is_empty(&self) -> bool {
+        self.w.is_none()
+            && self.h.is_none()
+            && self.fit.is_none()
+            && self.format.is_none()
+            && self.q.is_none()
+    }
+
+    /// Checks the query against the namespace's allow-lists, and settles the
+    /// output format for an image stored as `original`.
+    fn resolve(
+        &self,
+        config: &ImageTransformConfig,
+        original: ImageFormat,
+        headers: &HeaderMap,
+    ) -> Result<Transform, String> {
+        if let Some(w) = self.w.filter(|w| !config.widths.contains(w)) {
+            return Err(format!("Width {} isn't allowed", w));
+        }
+        if let Some(h) = self.h.filter(|h| !config.heights.contains(h)) {
+            return Err(format!("Height {} isn't allowed", h));
+        }
+        if let Some(q) = self.q.filter(|q| !config.qualities.contains(q)) {
+            return Err(format!("Quality {} isn't allowed", q));
+        }
+
+        let (format, negotiated) = match self.format {
+            None => (original, false),
+            Some(TransformFormat::Auto) => {
+                let accept = headers
+                    .get(header::ACCEPT)
+                    .and_then(|accept| accept.to_str().ok())
+                    .unwrap_or_default();
+
+                if accept.contains("image/avif") {
+                    (ImageFormat::Avif, true)
+                } else if accept.contains("image/webp") {
+                    (ImageFormat::WebP, true)
+                } else {
+                    (original, true)
+                }
+            }
+            Some(TransformFormat::Jpeg) => (ImageFormat::Jpeg, false),
+            Some(TransformFormat::Png) => (ImageFormat::Png, false),
+            Some(TransformFormat::Webp) => (ImageFormat::WebP, false),
+            Some(TransformFormat::Avif) => (ImageFormat::Avif, false),
+        };
+
+        Ok(Transform {
+            width: self.w,
+            height: self.h,
+            fit: self.fit.unwrap_or_default(),
+            format,
+            quality: self.q.unwrap_or(DEFAULT_QUALITY),
+            negotiated,
+        })
+    }
+}
+
+impl Transform {
+    /// Identifies the variant of `key` in `namespace` this makes, as it was
+    /// when it had `size` and `modified`.
+    fn variant_id(
+        &self,
+        namespace: &str,
+        key: &str,
+        size: u64,
+        modified: SystemTime,
+    ) -> String {
+        let modified = modified
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos());
+
+        let hash = Sha256::digest(
+            format!(
+                "{}\n{}\n{}\n{}\n{:?}\n{:?}\n{:?}\n{}",
+                namespace,
+                key,
+                size,
+                modified,
+                self.width,
+                self.height,
+                self.fit,
+                self.quality
+            )
+            .as_bytes(),
+        );
+        let hash: String = hash[..16]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        format!("{}.{}", hash, self.format.extensions_str()[0])
+    }
+
+    fn apply(&self, image: DynamicImage) -> DynamicImage {
+        match (self.width, self.height, self.fit) {
+            (Some(w), Some(h), Fit::Cover) => {
+                image.resize_to_fill(w, h, FilterType::Lanczos3)
+            }
+            (Some(w), Some(h), Fit::Fill) => {
+                image.resize_exact(w, h, FilterType::Lanczos3)
+            }
+            (None, None, _) => image,
+            (w, h, _) => {
+                let w = w.unwrap_or(u32::MAX);
+                let h = h.unwrap_or(u32::MAX);
+
+                // never upscale just to fit
+                if image.width() <= w && image.height() <= h {
+                    image
+                } else {
+                    image.resize(w, h, FilterType::Lanczos3)
+                }
+            }
+        }
+    }
+}
+
+/// Middleware serving transformed variants of images in `namespace` when
+/// they're asked for with `?w=&h=&fit=&format=&q=`, if the namespace allows
+/// it.
+///
+/// Anything that can't be transformed (other files, animated images) is left
+/// to the service below, which serves it as it is.
+pub async fn transform_images<B: MessageBody>(
+    namespace: String,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let cfg = req.app_data::<Data<Config>>().cloned();
+    let storage = req
+        .app_data::<Data<Storage>>()
+        .and_then(|storage| storage.namespace(&namespace).cloned());
+    let cache = req.app_data::<Data<VariantCache>>().cloned();
+
+    let (cfg, storage, cache) = match (cfg, storage, cache) {
+        (Some(cfg), Some(storage), Some(cache))
+            if req.method() == Method::GET || req.method() == Method::HEAD =>
+        {
+            (cfg, storage, cache)
+        }
+        _ => return next.call(req).await.map(|r| r.map_into_left_body()),
+    };
+
+    let definition = match cfg.namespaces.get(&namespace) {
+        Some(definition) if definition.image_transforms.enabled => definition,
+        _ => return next.call(req).await.map(|r| r.map_into_left_body()),
+    };
+
+    let query =
+        match web::Query::<TransformQuery>::from_query(req.query_string()) {
+            Ok(query) if query.is_empty() => {
+                return next.call(req).await.map(|r| r.map_into_left_body());
+            }
+            Ok(query) => query.into_inner(),
+            Err(e) => {
+                return Ok(req
+                    .into_response(
+                        HttpResponse::BadRequest()
+                            .body(format!("Invalid image transform: {}", e)),
+                    )
+                    .map_into_right_body());
+            }
+        };
+
+    let tail = percent_decode_str(req.match_info().unprocessed())
+        .decode_utf8_lossy()
+        .into_owned();
+
+    // only images the decoder handles are worth reading
+    let original =
+        storage::sanitize_key(&tail).and_then(
+            |key| match ImageFormat::from_path(&key) {
+                Ok(
+                    format @ (ImageFormat::Jpeg
+                    | ImageFormat::Png
+                    | ImageFormat::WebP),
+                ) => Some((key, format)),
+                _ => None,
+            },
+        );
+    let Some((key, original)) = original else {
+        return next.call(req).await.map(|r| r.map_into_left_body());
+    };
+
+    let transform = match query.resolve(
+        &definition.image_transforms,
+        original,
+        req.headers(),
+    ) {
+        Ok(transform) => transform,
+        Err(reason) => {
+            return Ok(req
+                .into_response(HttpResponse::BadRequest().body(reason))
+                .map_into_right_body());
+        }
+    };
+
+    let stat = {
+        let storage = storage.clone();
+        let key = key.clone();
+        web::block(move || storage.stat(&key)).await
+    };
+    let (size, modified) = match stat {
+        Ok(Ok(Some(Stat::File { size, modified }))) => (size, modified),
+        _ => return next.call(req).await.map(|r| r.map_into_left_body()),
+    };
+
+    let id = transform.variant_id(
+        storage.name(),
+        &storage.backend_key(&key),
+        size,
+        modified,
+    );
+    let encryption_key = definition.encryption_at_rest.key().cloned();
+
+    let format = transform.format;
+    let negotiated = transform.negotiated;
+    let variant = {
+        let id = id.clone();
+        let key = key.clone();
+        web::block(move || match cache.get(&id) {
+            // variants of encrypted images are never cached
+            Some(file) if encryption_key.is_none() => {
+                Ok(Some(Variant::Cached(file)))
+            }
+            _ => create_variant(
+                &cache,
+                &id,
+                &storage,
+                &key,
+                size,
+                encryption_key,
+                &transform,
+            ),
+        })
+        .await
+    };
+
+    let variant = match variant {
+        Ok(Ok(Some(variant))) => variant,
+        // not a still image after all
+        Ok(Ok(None)) => {
+            return next.call(req).await.map(|r| r.map_into_left_body());
+        }
+        Ok(Err(e)) if e.kind() == io::ErrorKind::InvalidData => {
+            return Ok(req
+                .into_response(
+                    HttpResponse::UnprocessableEntity()
+                        .body("This image can't be decoded"),
+                )
+                .map_into_right_body());
+        }
+        Ok(Err(e)) => {
+            error!("Failed to transform {}: {}", key, e);
+            return Ok(req
+                .into_response(
+                    HttpResponse::InternalServerError()
+                        .body("Failed to transform image"),
+                )
+                .map_into_right_body());
+        }
+        Err(e) => {
+            error!("Failed to transform {}: {}", key, e);
+            return Ok(req
+                .into_response(
+                    HttpResponse::InternalServerError()
+                        .body("Failed to transform image"),
+                )
+                .map_into_right_body());
+        }
+    };
+
+    let response =
+        respond(&req, variant, &key, &id, format, negotiated, modified);
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+/// A transformed image, ready to be served.
+enum Variant {
+    Cached(File),
+    /// Of an image encrypted at rest, which mustn't be written out as
+    /// plaintext.
+    InMemory(Vec<u8>),
+}
+
+/// Reads, transforms and caches the image at `key`. Returns `None` if it
+/// turns out not to be a still image.
+fn create_variant(
+    cache: &VariantCache,
+    id: &str,
+    storage: &NamespaceStorage,
+    key: &str,
+    size: u64,
+    encryption_key: Option<EncryptionKey>,
+    transform: &Transform,
+) -> io::Result<Option<Variant>> {
+    let encrypted = encryption_key.is_some();
+    let mut reader: Box<dyn Read> = match encryption_key {
+        Some(encryption_key) if encryption::is_encrypted(storage, key)? => {
+            Box::new(EncryptedFile::open(storage, key, size, &encryption_key)?)
+        }
+        _ => storage.get_range(key, 0, size)?,
+    };
+    let mut source = Vec::new();
+    reader.read_to_end(&mut source)?;
+
+    // browsers ignore the orientation of a variant's original, so it has to
+    // be baked in
+    let Some((image, _)) = super::decode(Cursor::new(source), true)? else {
+        return Ok(None);
+    };
+    let image = transform.apply(image);
+
+    if encrypted {
+        return super::write_to_vec(
+            &image,
+            transform.format,
+            transform.quality,
+        )
+        .map(|bytes| Some(Variant::InMemory(bytes)));
+    }
+
+    let output = cache.temp_file()?;
+    super::write(
+        &image,
+        transform.format,
+        transform.quality,
+        output.as_file(),
+    )?;
+
+    cache
+        .insert(id, output)
+        .map(|file| Some(Variant::Cached(file)))
+}
+
+fn respond(
+    req: &ServiceRequest,
+    variant: Variant,
+    key: &str,
+    id: &str,
+    format: ImageFormat,
+    negotiated: bool,
+    modified: SystemTime,
+) -> HttpResponse {
+    let etag = EntityTag::new_strong(
+        id.split('.').next().unwrap_or_default().to_string(),
+    );
+
+    let mut response = match IfNoneMatch::parse(req.request()) {
+        Ok(IfNoneMatch::Any) => HttpResponse::NotModified().finish(),
+        Ok(IfNoneMatch::Items(tags))
+            if tags.iter().any(|tag| tag.weak_eq(&etag)) =>
+        {
+            HttpResponse::NotModified().finish()
+        }
+        _ => {
+            // named after the original, so saving it gives a sensible name
+            let name = Path::new(key)
+                .with_extension(format.extensions_str()[0])
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| id.to_string());
+            let disposition = ContentDisposition {
+                disposition: DispositionType::Inline,
+                parameters: vec![DispositionParam::Filename(name.clone())],
+            };
+
+            match variant {
+                Variant::InMemory(bytes) => HttpResponse::Ok()
+                    .content_type(format.to_mime_type())
+                    .insert_header(disposition)
+                    .body(bytes),
+                Variant::Cached(file) => {
+                    match NamedFile::from_file(file, &name) {
+                        Ok(file) => file
+                            .use_etag(false)
+                            .use_last_modified(false)
+                            .set_content_disposition(disposition)
+                            .into_response(req.request()),
+                        Err(e) => {
+                            error!("Failed to serve variant {}: {}", id, e);
+                            return HttpResponse::InternalServerError()
+                                .body("Failed to transform image");
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(
+        header::ETAG,
+        ETag(etag)
+            .to_string()
+            .parse()
+            .expect("etag should be a valid header value"),
+    );
+    headers.insert(
+        header::LAST_MODIFIED,
+        LastModified(HttpDate::from(modified))
+            .to_string()
+            .parse()
+            .expect("last modified should be a valid header value"),
+    );
+    headers.insert(
+        header::CACHE_CONTROL,
+        format!("public, max-age={}", MAX_AGE_SECONDS)
+            .parse()
+            .expect("cache control should be a valid header value"),
+    );
+    if negotiated {
+        headers
+            .insert(header::VARY, header::HeaderValue::from_static("Accept"));
+    }
+
+    response
+}
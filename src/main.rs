@@ -1,17 +1,40 @@
+mod access;
+mod admin;
+mod cli;
 mod config;
+mod downloads;
+mod encryption;
+mod images;
+mod media;
+mod mirror;
+mod reload;
 mod render;
 mod routes;
+mod storage;
+mod views;
 
 use actix_files::Files;
 use actix_multipart::form::{MultipartFormConfig, tempfile::TempFileConfig};
 use actix_web::{
-    App, HttpServer, middleware,
+    App, HttpServer,
+    dev::Server,
+    middleware::{self, from_fn},
     web::{self, Data},
 };
+use admin::AdminContext;
+use clap::Parser;
+use cli::{Cli, Command};
 use config::Config;
+use downloads::DownloadLimits;
+use images::VariantCache;
 use log::{LevelFilter, error, info};
-use render::directory_listing;
-use std::io;
+use media::MediaLibrary;
+use mirror::Mirror;
+use notify::RecommendedWatcher;
+use render::{ListingCache, directory_listing};
+use std::{io, net::TcpListener, path::PathBuf};
+use storage::Storage;
+use views::ArchiveIndexes;
 
 #[actix_web::main]
 async fn main() -> io::Result<()> {
@@ -21,8 +44,30 @@ async fn main() -> io::Result<()> {
     .filter_module("actix_server", LevelFilter::Off)
     .init();
 
-    let config = Data::new(Config::read().unwrap_or_else(|e| {
+    let cli = Cli::parse();
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(cli.config).await,
+        command => {
+            if let Err(e) = cli::run(command, &cli.config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Runs the server with the configuration at `config_path` until it's
+/// stopped.
+async fn serve(config_path: PathBuf) -> io::Result<()> {
+    let config = Data::new(Config::load(&config_path).unwrap_or_else(|e| {
         error!("Failed to read configuration: {}", e);
+        if !config_path.exists() {
+            info!(
+                "Write a default one with `mediaserver --config {} init`",
+                config_path.display()
+            );
+        }
         std::process::exit(1);
     }));
 
@@ -36,62 +81,381 @@ async fn main() -> io::Result<()> {
         }
     );
 
-    config.create_uploads_directory()?;
+    let storage = Data::new(Storage::new(&config));
+
+    config.create_uploads_directory(&storage)?;
+
+    let download_limits = Data::new(DownloadLimits::load(
+        config.get_state_path().join("downloads.json"),
+    )?);
+
+    let mirror = Data::new(Mirror::start(
+        &config,
+        storage.clone().into_inner(),
+        config.get_state_path().join("mirror.json"),
+    )?);
+
+    let media = Data::new(MediaLibrary::load(&config)?);
+
+    let listing_cache =
+        Data::new(ListingCache::new(config.file_listing_render.cache.clone()));
+    let variant_cache = Data::new(VariantCache::new(
+        config.get_image_cache_path(),
+        config.storage.image_cache_max_size_bytes,
+    ));
+    let archive_indexes = Data::new(ArchiveIndexes::default());
+
+    let reloads = reload::Reloads::new();
+    let admin =
+        Data::new(AdminContext::new(config_path.clone(), reloads.reloader()));
+
+    let mut state = AppState {
+        download_limits,
+        mirror,
+        media,
+        listing_cache,
+        variant_cache,
+        archive_indexes,
+        admin,
+    };
+
+    // kept alive for the lifetime of the server; dropping them stops the
+    // watchers
+    let mut listing_watchers = watch_listings(&config, &storage, &state);
 
     let bind_address =
         format!("{}:{}", config.web_server.host, config.web_server.port);
+    // shared by the servers of every configuration, so connections keep
+    // being accepted while one takes over from another
+    let listener = TcpListener::bind(&bind_address)?;
+
+    let server = start_server(
+        config.clone(),
+        storage,
+        state.clone(),
+        listener.try_clone()?,
+    )?;
+
+    info!("Server listening on http://{}/", &bind_address);
+    info!("Configured public URL: {}", &config.web_server.listen_url);
+
+    reload::serve(server, config, config_path, reloads, |config| {
+        let storage = Data::new(Storage::new(&config));
+        config.create_uploads_directory(&storage)?;
+
+        // listings rendered under the previous configuration may show
+        // settings it no longer has, so start over with an empty cache
+        state.listing_cache = Data::new(ListingCache::new(
+            config.file_listing_render.cache.clone(),
+        ));
+
+        let server = start_server(
+            config.clone(),
+            storage.clone(),
+            state.clone(),
+            listener.try_clone()?,
+        )?;
+
+        state.mirror.use_storage(storage.clone().into_inner());
+        listing_watchers = watch_listings(&config, &storage, &state);
+
+        Ok(server)
+    })
+    .await
+}
+
+/// What carries over when the configuration is reloaded: bookkeeping and
+/// caches, which belong to the process rather than to one configuration.
+/// The listing cache is the exception, being replaced on every reload since
+/// its listings depend on the configuration they were rendered under.
+#[derive(Clone)]
+struct AppState {
+    download_limits: Data<DownloadLimits>,
+    mirror: Data<Mirror>,
+    media: Data<MediaLibrary>,
+    listing_cache: Data<ListingCache>,
+    variant_cache: Data<VariantCache>,
+    archive_indexes: Data<ArchiveIndexes>,
+    admin: Data<AdminContext>,
+}
+
+/// Watches the local directories of `storage` for changes that invalidate
+/// cached listings, if the listing cache is set to.
+fn watch_listings(
+    config: &Config,
+    storage: &Storage,
+    state: &AppState,
+) -> Vec<RecommendedWatcher> {
+    if !config.file_listing_render.cache.enabled
+        || !config.file_listing_render.cache.watch_filesystem
+    {
+        return Vec::new();
+    }
+
+    storage
+        .local_roots()
+        .iter()
+        .filter_map(|root| {
+            state
+                .listing_cache
+                .clone()
+                .into_inner()
+                .watch(root)
+                .inspect_err(|e| {
+                    error!(
+                        "Failed to watch '{}'; cached listings will only be \
+                         invalidated by uploads: {}",
+                        root.display(),
+                        e
+                    )
+                })
+                .ok()
+        })
+        .collect()
+}
+
+/// Starts serving `config` on `listener`.
+fn start_server(
+    config: Data<Config>,
+    storage: Data<Storage>,
+    state: AppState,
+    listener: TcpListener,
+) -> io::Result<Server> {
+    let shutdown_timeout = config.web_server.shutdown_timeout_seconds;
 
     let server = HttpServer::new({
-        let config_closure = config.clone();
+        let config_closure = config;
         move || {
-            let mut app = App::new()
-                .app_data(config_closure.clone())
-                .app_data(
-                    MultipartFormConfig::default()
-                        .total_limit(config_closure.storage.max_file_size_bytes),
-                )
-                .app_data(TempFileConfig::default().directory(config_closure.get_temp_path()))
-                .route("/", web::get().to(routes::index_redirect))
-                .route("/upload", web::put().to(routes::upload));
-
-            // attach a static file router for all namespaces
-            for namespace in &config_closure.namespaces {
-                let mut files = Files::new(
-                    namespace.0,
-                    namespace
-                        .1
-                        .get_path(&config_closure)
-                        .to_str()
-                        .unwrap_or_else(|| {
-                            panic!(
-                                "should be able to convert path of namespace {} to str",
-                                namespace.0
+            let mut app =
+                App::new()
+                    .app_data(config_closure.clone())
+                    .app_data(storage.clone())
+                    .app_data(state.listing_cache.clone())
+                    .app_data(state.download_limits.clone())
+                    .app_data(state.mirror.clone())
+                    .app_data(state.variant_cache.clone())
+                    .app_data(state.media.clone())
+                    .app_data(state.archive_indexes.clone())
+                    .app_data(state.admin.clone())
+                    .app_data(MultipartFormConfig::default().total_limit(
+                        config_closure.storage.max_file_size_bytes,
+                    ))
+                    .app_data(
+                        TempFileConfig::default()
+                            .directory(config_closure.get_temp_path()),
+                    )
+                    .route("/", web::get().to(routes::index_redirect))
+                    .route("/upload", web::put().to(routes::upload))
+                    .route("/sign", web::post().to(routes::sign))
+                    .route(
+                        "/_archive/{namespace}/{path:.*}",
+                        web::get().to(routes::download_archive),
+                    )
+                    .route(
+                        "/_poster/{namespace}/{path:.*}",
+                        web::get().to(routes::poster),
+                    )
+                    .route(
+                        "/_decrypt/{namespace}/{file}",
+                        web::get().to(routes::decrypt_view),
+                    )
+                    .route(
+                        "/_upload/{namespace}",
+                        web::get().to(routes::upload_page),
+                    )
+                    .service(
+                        web::scope("/_admin/api")
+                            .wrap(from_fn(admin::require_token))
+                            .route(
+                                "/namespaces",
+                                web::get().to(admin::list_namespaces),
                             )
-                        }),
-                );
+                            .route(
+                                "/namespaces/{name}",
+                                web::get().to(admin::get_namespace),
+                            )
+                            .route(
+                                "/namespaces/{name}",
+                                web::post().to(admin::create_namespace),
+                            )
+                            .route(
+                                "/namespaces/{name}",
+                                web::patch().to(admin::update_namespace),
+                            )
+                            .route(
+                                "/namespaces/{name}",
+                                web::delete().to(admin::delete_namespace),
+                            )
+                            .route(
+                                "/namespaces/{name}/key",
+                                web::post().to(admin::rotate_key),
+                            )
+                            .route(
+                                "/namespaces/{name}/file_listing",
+                                web::put().to(admin::update_file_listing),
+                            )
+                            .route(
+                                "/mirror/status",
+                                web::get().to(routes::mirror_status),
+                            ),
+                    )
+                    .route("/_admin/login", web::get().to(admin::login_page))
+                    .route("/_admin/login", web::post().to(admin::log_in))
+                    .route("/_admin/logout", web::post().to(admin::log_out))
+                    .service(
+                        web::scope("/_admin")
+                            .wrap(from_fn(admin::require_login))
+                            .route("", web::get().to(admin::dashboard))
+                            .route("/", web::get().to(admin::dashboard))
+                            .route(
+                                "/namespaces/{name}",
+                                web::get().to(admin::namespace_page),
+                            )
+                            .route(
+                                "/namespaces/{name}/key",
+                                web::post().to(admin::rotate_key_page),
+                            )
+                            .route(
+                                "/namespaces/{name}/file_listing",
+                                web::post().to(admin::update_file_listing_page),
+                            )
+                            .route(
+                                "/namespaces/{name}/delete",
+                                web::post().to(admin::delete_file),
+                            ),
+                    );
+
+            // attach a file router for all namespaces
+            for (namespace_name, namespace) in &config_closure.namespaces {
+                let scope = match storage
+                    .namespace(namespace_name)
+                    .and_then(|storage| storage.local_path(""))
+                {
+                    // actix-files can serve local, unencrypted files itself
+                    Some(root)
+                        if namespace.encryption_at_rest.key().is_none() =>
+                    {
+                        let mut files = Files::new("", root);
 
-                if namespace.1.file_listing.show {
-                    files = files.show_files_listing();
-                    if namespace.1.file_listing.use_fancy_renderer {
-                        files = files.files_listing_renderer(directory_listing);
+                        if namespace.file_listing.show {
+                            files = files.show_files_listing();
+                            if namespace.file_listing.use_fancy_renderer {
+                                files = files
+                                    .files_listing_renderer(directory_listing);
+                            }
+                        }
+
+                        web::scope(namespace_name).service(files)
                     }
-                }
+                    _ => web::scope(namespace_name)
+                        .app_data(Data::new(routes::StoredNamespace(
+                            namespace_name.clone(),
+                        )))
+                        .default_service(web::to(routes::serve_stored)),
+                };
 
-                app = app.service(files);
+                let views_namespace = namespace_name.clone();
+                let transforms_namespace = namespace_name.clone();
+                let limits_namespace = namespace_name.clone();
+                let access_namespace = namespace_name.clone();
+                app = app.service(
+                    scope
+                        .wrap(from_fn(access::protect_stored_files))
+                        .wrap(from_fn(move |req, next| {
+                            views::render_views(
+                                views_namespace.clone(),
+                                req,
+                                next,
+                            )
+                        }))
+                        .wrap(from_fn(move |req, next| {
+                            images::transform_images(
+                                transforms_namespace.clone(),
+                                req,
+                                next,
+                            )
+                        }))
+                        .wrap(from_fn(move |req, next| {
+                            downloads::enforce_download_limits(
+                                limits_namespace.clone(),
+                                req,
+                                next,
+                            )
+                        }))
+                        .wrap(from_fn(move |req, next| {
+                            access::namespace_access(
+                                access_namespace.clone(),
+                                req,
+                                next,
+                            )
+                        })),
+                );
             }
 
-            app.service(Files::new(
-                "/",
-                config_closure
-                    .get_uploads_path()
-                    .join(&config_closure.storage.default_namespace_fs_path),
-            )).wrap(middleware::Compress::default())
+            let default_namespace = config_closure
+                .default_namespace()
+                .map(|(name, _)| name.clone())
+                .unwrap_or_default();
+            let default_encrypted = config_closure
+                .default_namespace()
+                .is_some_and(|(_, ns)| ns.encryption_at_rest.key().is_some());
+            let default_root = match storage.default_namespace() {
+                Some(storage) => storage.local_path(""),
+                None => Some(
+                    config_closure
+                        .get_uploads_path()
+                        .join(&config_closure.storage.default_namespace_path),
+                ),
+            };
+
+            let default_scope = match default_root {
+                Some(root) if !default_encrypted => {
+                    web::scope("").service(Files::new("", root))
+                }
+                _ => web::scope("")
+                    .app_data(Data::new(routes::StoredNamespace(
+                        default_namespace.clone(),
+                    )))
+                    .default_service(web::to(routes::serve_stored)),
+            };
+
+            let views_namespace = default_namespace.clone();
+            let transforms_namespace = default_namespace.clone();
+            let limits_namespace = default_namespace.clone();
+            app.service(
+                default_scope
+                    .wrap(from_fn(access::protect_stored_files))
+                    .wrap(from_fn(move |req, next| {
+                        views::render_views(views_namespace.clone(), req, next)
+                    }))
+                    .wrap(from_fn(move |req, next| {
+                        images::transform_images(
+                            transforms_namespace.clone(),
+                            req,
+                            next,
+                        )
+                    }))
+                    .wrap(from_fn(move |req, next| {
+                        downloads::enforce_download_limits(
+                            limits_namespace.clone(),
+                            req,
+                            next,
+                        )
+                    }))
+                    .wrap(from_fn(move |req, next| {
+                        access::namespace_access(
+                            default_namespace.clone(),
+                            req,
+                            next,
+                        )
+                    })),
+            )
+            .wrap(middleware::Compress::default())
         }
     })
-    .bind(&bind_address)?;
-
-    info!("Server listening on http://{}/", &bind_address);
-    info!("Configured public URL: {}", &config.web_server.listen_url);
+    .listen(listener)?
+    .shutdown_timeout(shutdown_timeout)
+    .run();
 
-    server.run().await
+    Ok(server)
 }
@@ -0,0 +1,92 @@
+//! Reads metadata from audio files through symphonia.
+
+use std::fs::File;
+
+use log::debug;
+use symphonia::core::{
+    codecs::{self, CodecParameters, CodecType},
+    formats::FormatOptions,
+    io::{MediaSourceStream, MediaSourceStreamOptions},
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+use super::{MediaInfo, MediaKind};
+
+/// PCM codecs, which are all just "pcm" as far as anyone looking at a listing
+/// is concerned.
+const PCM: &[CodecType] = &[
+    codecs::CODEC_TYPE_PCM_S16LE,
+    codecs::CODEC_TYPE_PCM_S16BE,
+    codecs::CODEC_TYPE_PCM_S24LE,
+    codecs::CODEC_TYPE_PCM_S24BE,
+    codecs::CODEC_TYPE_PCM_S32LE,
+    codecs::CODEC_TYPE_PCM_S32BE,
+    codecs::CODEC_TYPE_PCM_S8,
+    codecs::CODEC_TYPE_PCM_U8,
+    codecs::CODEC_TYPE_PCM_F32LE,
+    codecs::CODEC_TYPE_PCM_F32BE,
+    codecs::CODEC_TYPE_PCM_F64LE,
+    codecs::CODEC_TYPE_PCM_F64BE,
+    codecs::CODEC_TYPE_PCM_ALAW,
+    codecs::CODEC_TYPE_PCM_MULAW,
+];
+
+/// Reads the default track of the audio file in `file`, using `extension` as
+/// a hint for its format. Returns `None` if symphonia doesn't recognise it.
+pub fn probe(file: File, extension: &str) -> Option<MediaInfo> {
+    let mut hint = Hint::new();
+    hint.with_extension(extension);
+
+    let stream = MediaSourceStream::new(
+        Box::new(file),
+        MediaSourceStreamOptions::default(),
+    );
+    let probed = match symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    ) {
+        Ok(probed) => probed,
+        Err(e) => {
+            debug!("Not a recognised audio file: {}", e);
+            return None;
+        }
+    };
+
+    let params = &probed.format.default_track()?.codec_params;
+
+    let mut info = MediaInfo::new(MediaKind::Audio);
+    info.duration_seconds = duration(params);
+    info.audio_codec = codec_name(params.codec).map(str::to_string);
+    Some(info)
+}
+
+fn duration(params: &CodecParameters) -> Option<f64> {
+    let frames = params.n_frames?;
+
+    match (params.time_base, params.sample_rate) {
+        (Some(time_base), _) => {
+            let time = time_base.calc_time(frames);
+            Some(time.seconds as f64 + time.frac)
+        }
+        (None, Some(sample_rate)) if sample_rate > 0 => {
+            Some(frames as f64 / sample_rate as f64)
+        }
+        _ => None,
+    }
+}
+
+fn codec_name(codec: CodecType) -> Option<&'static str> {
+    Some(match codec {
+        codecs::CODEC_TYPE_MP3 => "mp3",
+        codecs::CODEC_TYPE_FLAC => "flac",
+        codecs::CODEC_TYPE_VORBIS => "vorbis",
+        codecs::CODEC_TYPE_OPUS => "opus",
+        codecs::CODEC_TYPE_AAC => "aac",
+        codecs::CODEC_TYPE_ALAC => "alac",
+        codec if PCM.contains(&codec) => "pcm",
+        _ => return None,
+    })
+}
@@ -0,0 +1,164 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use log::{debug, error, info, warn};
+use sha2::{Digest, Sha256};
+
+use crate::config::{Config, MediaConfig};
+
+use super::{MediaInfo, MediaKind, is_media_extension, poster, probe};
+
+/// Metadata of uploaded video and audio files, persisted as JSON along with
+/// a directory of poster frames.
+///
+/// Files are keyed by their key in their namespace's storage backend, the
+/// same as download limits.
+pub struct MediaLibrary {
+    config: MediaConfig,
+    path: PathBuf,
+    posters: PathBuf,
+    /// Whether ffmpeg could be run when the server started.
+    ffmpeg: bool,
+    media: Mutex<HashMap<String, MediaInfo>>,
+}
+
+impl MediaLibrary {
+    /// Loads the library from the state directory, starting empty if it
+    /// doesn't exist yet.
+    pub fn load(config: &Config) -> io::Result<MediaLibrary> {
+        let path = config.get_state_path().join("media.json");
+        let media = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        let ffmpeg =
+            config.media.enabled && poster::is_available(&config.media);
+        if config.media.enabled && !ffmpeg {
+            info!(
+                "Couldn't run '{}'; videos won't get poster frames",
+                config.media.ffmpeg_path
+            );
+        }
+
+        Ok(Self {
+            config: config.media.clone(),
+            path,
+            posters: config.get_posters_path(),
+            ffmpeg,
+            media: Mutex::new(media),
+        })
+    }
+
+    /// Reads the metadata of the file at `path`, about to be stored at `key`,
+    /// and grabs a poster frame if it's a video and `with_poster` is set.
+    ///
+    /// Blocks while probing and running ffmpeg. Files that aren't media, or
+    /// can't be read, are skipped and return `None`.
+    pub fn analyse(
+        &self,
+        key: &str,
+        path: &Path,
+        extension: &str,
+        with_poster: bool,
+    ) -> Option<MediaInfo> {
+        if !self.config.enabled || !is_media_extension(extension) {
+            return None;
+        }
+
+        let mut info = match probe(path, extension) {
+            Ok(Some(info)) => info,
+            Ok(None) => return None,
+            Err(e) => {
+                debug!("Failed to read media metadata of {}: {}", key, e);
+                return None;
+            }
+        };
+
+        if with_poster && self.ffmpeg && info.kind == MediaKind::Video {
+            let poster_path = self.poster_path(key);
+            match poster::grab(
+                &self.config,
+                path,
+                info.duration_seconds,
+                &poster_path,
+            ) {
+                Ok(()) => info.poster = true,
+                Err(e) => {
+                    warn!("Failed to grab a poster frame of {}: {}", key, e);
+                    let _ = fs::remove_file(&poster_path);
+                }
+            }
+        }
+
+        let mut media = self.lock();
+        media.insert(key.to_string(), info.clone());
+        if let Err(e) = self.persist(&media) {
+            error!("Failed to persist media metadata: {}", e);
+        }
+
+        Some(info)
+    }
+
+    pub fn get(&self, key: &str) -> Option<MediaInfo> {
+        self.lock().get(key).cloned()
+    }
+
+    /// Where the poster frame of the file at `key` is, if it has one.
+    pub fn poster(&self, key: &str) -> Option<PathBuf> {
+        self.get(key)
+            .filter(|info| info.poster)
+            .map(|_| self.poster_path(key))
+    }
+
+    /// Forgets the file at `key`, deleting its poster frame.
+    pub fn remove(&self, key: &str) {
+        let mut media = self.lock();
+        let Some(info) = media.remove(key) else {
+            return;
+        };
+
+        if info.poster
+            && let Err(e) = fs::remove_file(self.poster_path(key))
+        {
+            warn!("Failed to delete poster frame of {}: {}", key, e);
+        }
+        if let Err(e) = self.persist(&media) {
+            error!("Failed to persist media metadata: {}", e);
+        }
+    }
+
+    /// Keys can nest and contain anything, so posters are named after their
+    /// hash instead.
+    fn poster_path(&self, key: &str) -> PathBuf {
+        let name: String = Sha256::digest(key.as_bytes())
+            .iter()
+            .take(16)
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        self.posters.join(format!("{}.jpg", name))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, MediaInfo>> {
+        self.media
+            .lock()
+            .expect("media library lock should not be poisoned")
+    }
+
+    /// Writes the library next to its final location and renames it into
+    /// place, so a crash can't leave a half-written file behind.
+    fn persist(&self, media: &HashMap<String, MediaInfo>) -> io::Result<()> {
+        let serialized = serde_json::to_string_pretty(media)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let temp_path = self.path.with_extension("json.tmp");
+        fs::write(&temp_path, serialized)?;
+        fs::rename(&temp_path, &self.path)
+    }
+}
@@ -0,0 +1,410 @@
+//! Reads metadata from the EBML elements of Matroska and WebM files.
+
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+
+use super::{MediaInfo, MediaKind};
+
+const SEGMENT: u64 = 0x1853_8067;
+const INFO: u64 = 0x1549_A966;
+const TIMESTAMP_SCALE: u64 = 0x2A_D7B1;
+const DURATION: u64 = 0x4489;
+const TRACKS: u64 = 0x1654_AE6B;
+const TRACK_ENTRY: u64 = 0xAE;
+const TRACK_TYPE: u64 = 0x83;
+const CODEC_ID: u64 = 0x86;
+const VIDEO: u64 = 0xE0;
+const PIXEL_WIDTH: u64 = 0xB0;
+const PIXEL_HEIGHT: u64 = 0xBA;
+const CLUSTER: u64 = 0x1F43_B675;
+
+const TRACK_TYPE_VIDEO: u64 = 1;
+const TRACK_TYPE_AUDIO: u64 = 2;
+
+/// An element, located by where its content starts and ends in the file.
+struct Element {
+    id: u64,
+    start: u64,
+    end: u64,
+}
+
+/// Reads a variable-length integer. IDs keep their length marker; sizes
+/// don't, and come back as `None` when they're "unknown".
+fn read_vint(
+    reader: &mut impl Read,
+    keep_marker: bool,
+) -> io::Result<Option<u64>> {
+    let mut first = [0; 1];
+    reader.read_exact(&mut first)?;
+
+    let len = first[0].leading_zeros() as usize + 1;
+    if len > 8 {
+        return Err(io::Error::new(ErrorKind::InvalidData, "invalid vint"));
+    }
+
+    // the bits left in the first byte after the length marker
+    let mask = 0xFF_u64 >> len;
+    let mut value = if keep_marker {
+        first[0] as u64
+    } else {
+        first[0] as u64 & mask
+    };
+    let mut all_ones = value == mask;
+
+    let mut rest = [0; 7];
+    reader.read_exact(&mut rest[..len - 1])?;
+    for byte in &rest[..len - 1] {
+        value = (value << 8) | *byte as u64;
+        all_ones &= *byte == 0xFF;
+    }
+
+    Ok((keep_marker || !all_ones).then_some(value))
+}
+
+/// Lists the elements between `start` and `end`, stopping at the first
+/// cluster: everything of interest comes before the media data.
+fn elements(
+    reader: &mut (impl Read + Seek),
+    start: u64,
+    end: u64,
+) -> io::Result<Vec<Element>> {
+    let mut found = Vec::new();
+    let mut position = start;
+
+    while position < end {
+        reader.seek(SeekFrom::Start(position))?;
+        let id = match read_vint(reader, true) {
+            Ok(Some(id)) => id,
+            Ok(None) => break,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+        if id == CLUSTER {
+            break;
+        }
+
+        let size = read_vint(reader, false)?;
+        let content_start = reader.stream_position()?;
+        let content_end = match size {
+            Some(size) => content_start.saturating_add(size).min(end),
+            // live streams leave the segment's size open
+            None => end,
+        };
+
+        found.push(Element {
+            id,
+            start: content_start,
+            end: content_end,
+        });
+        position = content_end;
+    }
+
+    Ok(found)
+}
+
+fn read_bytes(
+    reader: &mut (impl Read + Seek),
+    element: &Element,
+) -> io::Result<Vec<u8>> {
+    let len = element.end.saturating_sub(element.start).min(64) as usize;
+    let mut bytes = vec![0; len];
+    reader.seek(SeekFrom::Start(element.start))?;
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_uint(
+    reader: &mut (impl Read + Seek),
+    element: &Element,
+) -> io::Result<u64> {
+    Ok(read_bytes(reader, element)?
+        .iter()
+        .take(8)
+        .fold(0, |value, byte| (value << 8) | *byte as u64))
+}
+
+fn read_float(
+    reader: &mut (impl Read + Seek),
+    element: &Element,
+) -> io::Result<Option<f64>> {
+    let bytes = read_bytes(reader, element)?;
+    Ok(match bytes.len() {
+        4 => {
+            Some(f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                as f64)
+        }
+        8 => Some(f64::from_be_bytes(
+            bytes.try_into().expect("length was checked"),
+        )),
+        _ => None,
+    })
+}
+
+/// Reads the segment info and the first video and audio track of the file.
+/// Returns `None` if it has no tracks of either kind.
+pub fn probe(
+    reader: &mut (impl Read + Seek),
+    len: u64,
+) -> io::Result<Option<MediaInfo>> {
+    let Some(segment) = elements(reader, 0, len)?
+        .into_iter()
+        .find(|element| element.id == SEGMENT)
+    else {
+        return Ok(None);
+    };
+
+    let mut info = MediaInfo::new(MediaKind::Audio);
+    let mut has_track = false;
+
+    for child in elements(reader, segment.start, segment.end)? {
+        match child.id {
+            INFO => {
+                let mut scale = 1_000_000;
+                let mut duration = None;
+
+                for element in elements(reader, child.start, child.end)? {
+                    match element.id {
+                        TIMESTAMP_SCALE => scale = read_uint(reader, &element)?,
+                        DURATION => duration = read_float(reader, &element)?,
+                        _ => {}
+                    }
+                }
+
+                // durations are in units of the timestamp scale, in ns
+                info.duration_seconds = duration
+                    .filter(|duration| *duration > 0.0)
+                    .map(|duration| duration * scale as f64 / 1e9);
+            }
+            TRACKS => {
+                for entry in elements(reader, child.start, child.end)?
+                    .into_iter()
+                    .filter(|element| element.id == TRACK_ENTRY)
+                {
+                    has_track |= read_track(reader, &entry, &mut info)?;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(has_track.then_some(info))
+}
+
+/// Fills in `info` from a track entry, returning whether it was the first
+/// video or audio track.
+fn read_track(
+    reader: &mut (impl Read + Seek),
+    entry: &Element,
+    info: &mut MediaInfo,
+) -> io::Result<bool> {
+    let mut track_type = 0;
+    let mut codec = None;
+    let mut dimensions = (None, None);
+
+    for element in elements(reader, entry.start, entry.end)? {
+        match element.id {
+            TRACK_TYPE => track_type = read_uint(reader, &element)?,
+            CODEC_ID => {
+                let bytes = read_bytes(reader, &element)?;
+                codec = Some(codec_name(
+                    String::from_utf8_lossy(&bytes).trim_end_matches('\0'),
+                ));
+            }
+            VIDEO => {
+                for video in elements(reader, element.start, element.end)? {
+                    match video.id {
+                        PIXEL_WIDTH => {
+                            dimensions.0 = Some(read_uint(reader, &video)?)
+                        }
+                        PIXEL_HEIGHT => {
+                            dimensions.1 = Some(read_uint(reader, &video)?)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match track_type {
+        TRACK_TYPE_VIDEO if info.video_codec.is_none() => {
+            info.kind = MediaKind::Video;
+            info.width = dimensions.0.map(|width| width as u32);
+            info.height = dimensions.1.map(|height| height as u32);
+            info.video_codec = codec;
+            Ok(true)
+        }
+        TRACK_TYPE_AUDIO if info.audio_codec.is_none() => {
+            info.audio_codec = codec;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+fn codec_name(codec_id: &str) -> String {
+    match codec_id {
+        "V_MPEG4/ISO/AVC" => "h264".to_string(),
+        "V_MPEGH/ISO/HEVC" => "hevc".to_string(),
+        "A_MPEG/L3" => "mp3".to_string(),
+        // the rest are prefixed with their kind, e.g. `V_VP9` or `A_OPUS`
+        other => other
+            .split_once('_')
+            .map_or(other, |(_, codec)| codec)
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// An element with a one-byte size, or an eight-byte one for bigger
+    /// content.
+    fn element(id: u64, content: &[u8]) -> Vec<u8> {
+        let id_len = 8 - id.leading_zeros() as usize / 8;
+        let mut bytes = id.to_be_bytes()[8 - id_len..].to_vec();
+        if content.len() < 0x7F {
+            bytes.push(0x80 | content.len() as u8);
+        } else {
+            bytes.push(0x01);
+            bytes.extend_from_slice(&(content.len() as u64).to_be_bytes()[1..]);
+        }
+        bytes.extend_from_slice(content);
+        bytes
+    }
+
+    fn uint(id: u64, value: u64) -> Vec<u8> {
+        element(id, &value.to_be_bytes())
+    }
+
+    fn track(track_type: u64, codec_id: &str, video: &[Vec<u8>]) -> Vec<u8> {
+        let mut content = uint(TRACK_TYPE, track_type);
+        content.extend(element(CODEC_ID, codec_id.as_bytes()));
+        if !video.is_empty() {
+            content.extend(element(VIDEO, &video.concat()));
+        }
+        element(TRACK_ENTRY, &content)
+    }
+
+    fn file(segment: &[Vec<u8>]) -> Vec<u8> {
+        // the EBML header, with a doc type
+        let mut bytes = element(0x1A45_DFA3, &element(0x4282, b"webm"));
+        bytes.extend(element(SEGMENT, &segment.concat()));
+        bytes
+    }
+
+    fn probe_bytes(bytes: &[u8]) -> io::Result<Option<MediaInfo>> {
+        probe(&mut Cursor::new(bytes), bytes.len() as u64)
+    }
+
+    #[test]
+    fn reads_vints() {
+        let read = |bytes: &[u8], keep_marker| {
+            read_vint(&mut Cursor::new(bytes), keep_marker).unwrap()
+        };
+
+        assert_eq!(read(&[0x81], false), Some(1));
+        assert_eq!(read(&[0x40, 0x02], false), Some(2));
+        assert_eq!(read(&[0x1A, 0x45, 0xDF, 0xA3], true), Some(0x1A45_DFA3));
+        // all ones is an unknown size
+        assert_eq!(read(&[0xFF], false), None);
+        assert_eq!(
+            read(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF], false),
+            None
+        );
+        assert!(read_vint(&mut Cursor::new([0x00]), false).is_err());
+    }
+
+    #[test]
+    fn reads_info_and_tracks() {
+        let bytes = file(&[
+            element(
+                INFO,
+                &[
+                    uint(TIMESTAMP_SCALE, 1_000_000),
+                    element(DURATION, &83_500.0_f64.to_be_bytes()),
+                ]
+                .concat(),
+            ),
+            element(
+                TRACKS,
+                &[
+                    track(
+                        TRACK_TYPE_VIDEO,
+                        "V_VP9",
+                        &[uint(PIXEL_WIDTH, 1280), uint(PIXEL_HEIGHT, 720)],
+                    ),
+                    track(TRACK_TYPE_AUDIO, "A_OPUS", &[]),
+                ]
+                .concat(),
+            ),
+        ]);
+
+        let info = probe_bytes(&bytes).unwrap().unwrap();
+
+        assert_eq!(info.kind, MediaKind::Video);
+        assert_eq!(info.duration_seconds, Some(83.5));
+        assert_eq!((info.width, info.height), (Some(1280), Some(720)));
+        assert_eq!(info.video_codec.as_deref(), Some("vp9"));
+        assert_eq!(info.audio_codec.as_deref(), Some("opus"));
+    }
+
+    #[test]
+    fn scales_durations() {
+        let bytes = file(&[
+            element(
+                INFO,
+                &[
+                    uint(TIMESTAMP_SCALE, 1_000),
+                    element(DURATION, &2_000_000.0_f32.to_be_bytes()),
+                ]
+                .concat(),
+            ),
+            element(TRACKS, &track(TRACK_TYPE_AUDIO, "A_MPEG/L3", &[])),
+        ]);
+
+        let info = probe_bytes(&bytes).unwrap().unwrap();
+
+        assert_eq!(info.kind, MediaKind::Audio);
+        assert_eq!(info.duration_seconds, Some(2.0));
+        assert_eq!(info.audio_codec.as_deref(), Some("mp3"));
+    }
+
+    #[test]
+    fn reads_segments_of_unknown_size() {
+        let mut bytes = element(0x1A45_DFA3, &[]);
+        bytes.extend_from_slice(&[0x18, 0x53, 0x80, 0x67, 0xFF]);
+        bytes.extend(element(
+            TRACKS,
+            &track(TRACK_TYPE_VIDEO, "V_MPEG4/ISO/AVC", &[]),
+        ));
+        // media data, which isn't read
+        bytes.extend(element(CLUSTER, &[0xAB; 200]));
+
+        let info = probe_bytes(&bytes).unwrap().unwrap();
+
+        assert_eq!(info.video_codec.as_deref(), Some("h264"));
+        assert_eq!(info.width, None);
+    }
+
+    #[test]
+    fn names_codecs() {
+        assert_eq!(codec_name("V_MPEGH/ISO/HEVC"), "hevc");
+        assert_eq!(codec_name("V_AV1"), "av1");
+        assert_eq!(codec_name("A_AAC/MPEG4/LC"), "aac");
+        assert_eq!(codec_name("A_VORBIS"), "vorbis");
+    }
+
+    #[test]
+    fn returns_none_without_tracks() {
+        assert!(probe_bytes(&file(&[element(INFO, &[])])).unwrap().is_none());
+        assert!(probe_bytes(&element(0x1A45_DFA3, &[])).unwrap().is_none());
+        assert!(probe_bytes(b"").unwrap().is_none());
+    }
+}
@@ -0,0 +1,127 @@
+//! Metadata of uploaded video and audio files.
+//!
+//! MP4 and Matroska containers are read natively, and everything else is
+//! handed to symphonia. Only poster frames need ffmpeg, and are skipped when
+//! it isn't around.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+mod audio;
+mod library;
+mod matroska;
+mod mp4;
+mod poster;
+
+pub use library::MediaLibrary;
+
+/// The extensions of files worth probing.
+const EXTENSIONS: &[&str] = &[
+    "mp4", "m4v", "mov", "m4a", "webm", "mkv", "mka", "mp3", "ogg", "oga",
+    "opus", "flac", "wav",
+];
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Video,
+    Audio,
+}
+
+/// What's known about a video or audio file.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MediaInfo {
+    pub kind: MediaKind,
+    pub duration_seconds: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    /// The average bitrate over the whole file, in bits per second.
+    pub bitrate: Option<u64>,
+    /// Whether a poster frame was grabbed.
+    pub poster: bool,
+}
+
+impl MediaInfo {
+    fn new(kind: MediaKind) -> MediaInfo {
+        Self {
+            kind,
+            duration_seconds: None,
+            width: None,
+            height: None,
+            video_codec: None,
+            audio_codec: None,
+            bitrate: None,
+            poster: false,
+        }
+    }
+
+    /// A short description for listings, e.g. `1:23 · 1920×1080 · h264`.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+
+        if let Some(duration) = self.duration_seconds {
+            let seconds = duration.round() as u64;
+            parts.push(if seconds >= 3600 {
+                format!(
+                    "{}:{:02}:{:02}",
+                    seconds / 3600,
+                    seconds / 60 % 60,
+                    seconds % 60
+                )
+            } else {
+                format!("{}:{:02}", seconds / 60, seconds % 60)
+            });
+        }
+        if let (Some(width), Some(height)) = (self.width, self.height) {
+            parts.push(format!("{}×{}", width, height));
+        }
+        if let Some(codec) =
+            self.video_codec.as_ref().or(self.audio_codec.as_ref())
+        {
+            parts.push(codec.clone());
+        }
+
+        parts.join(" · ")
+    }
+}
+
+/// Whether files with `extension` are probed at all.
+pub fn is_media_extension(extension: &str) -> bool {
+    EXTENSIONS.contains(&extension.to_lowercase().as_str())
+}
+
+/// Reads the metadata of the video or audio file at `path`.
+///
+/// Returns `None` if it isn't one, or its container isn't understood.
+pub fn probe(path: &Path, extension: &str) -> io::Result<Option<MediaInfo>> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut magic = [0; 8];
+    let read = reader.read(&mut magic)?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    let info = if read == 8 && &magic[4..8] == b"ftyp" {
+        mp4::probe(&mut reader, len)?
+    } else if read >= 4 && magic[..4] == [0x1A, 0x45, 0xDF, 0xA3] {
+        matroska::probe(&mut reader, len)?
+    } else {
+        audio::probe(reader.into_inner(), extension)
+    };
+
+    Ok(info.map(|mut info| {
+        info.bitrate = info
+            .duration_seconds
+            .filter(|duration| *duration > 0.0)
+            .map(|duration| (len as f64 * 8.0 / duration) as u64);
+        info
+    }))
+}
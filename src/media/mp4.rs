@@ -0,0 +1,373 @@
+//! Reads metadata from the boxes of MP4 and QuickTime files.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use super::{MediaInfo, MediaKind};
+
+/// A box, located by where its content starts and ends in the file.
+#[derive(Clone, Copy)]
+struct Mp4Box {
+    kind: [u8; 4],
+    start: u64,
+    end: u64,
+}
+
+/// Lists the boxes between `start` and `end`.
+fn boxes(
+    reader: &mut (impl Read + Seek),
+    start: u64,
+    end: u64,
+) -> io::Result<Vec<Mp4Box>> {
+    let mut found = Vec::new();
+    let mut position = start;
+
+    while position + 8 <= end {
+        reader.seek(SeekFrom::Start(position))?;
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+
+        let kind = [header[4], header[5], header[6], header[7]];
+        let (size, header_len) = match u32::from_be_bytes([
+            header[0], header[1], header[2], header[3],
+        ]) {
+            // extends to the end of its parent
+            0 => (end - position, 8),
+            1 => (read_u64(reader)?, 16),
+            size => (size as u64, 8),
+        };
+
+        if size < header_len {
+            break;
+        }
+
+        let box_end = position.saturating_add(size).min(end);
+        found.push(Mp4Box {
+            kind,
+            start: position + header_len,
+            end: box_end,
+        });
+        position = box_end;
+    }
+
+    Ok(found)
+}
+
+/// The first box of `kind` found by following `path` down from `parent`.
+fn find(
+    reader: &mut (impl Read + Seek),
+    parent: &Mp4Box,
+    path: &[&[u8; 4]],
+) -> io::Result<Option<Mp4Box>> {
+    let mut current = *parent;
+
+    for kind in path {
+        match boxes(reader, current.start, current.end)?
+            .into_iter()
+            .find(|found| &found.kind == *kind)
+        {
+            Some(found) => current = found,
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(current))
+}
+
+/// Reads the movie header and the first video and audio track of the file.
+/// Returns `None` if it has no `moov` box, or no tracks of either kind.
+pub fn probe(
+    reader: &mut (impl Read + Seek),
+    len: u64,
+) -> io::Result<Option<MediaInfo>> {
+    let file = Mp4Box {
+        kind: *b"file",
+        start: 0,
+        end: len,
+    };
+    let Some(moov) = find(reader, &file, &[b"moov"])? else {
+        return Ok(None);
+    };
+
+    let duration_seconds = match find(reader, &moov, &[b"mvhd"])? {
+        Some(mvhd) => movie_duration(reader, &mvhd)?,
+        None => None,
+    };
+
+    let mut info = MediaInfo::new(MediaKind::Audio);
+    info.duration_seconds = duration_seconds;
+    let mut has_track = false;
+
+    for trak in boxes(reader, moov.start, moov.end)?
+        .into_iter()
+        .filter(|found| &found.kind == b"trak")
+    {
+        let handler = match find(reader, &trak, &[b"mdia", b"hdlr"])? {
+            Some(hdlr) => {
+                reader.seek(SeekFrom::Start(hdlr.start + 8))?;
+                let mut handler = [0; 4];
+                reader.read_exact(&mut handler)?;
+                handler
+            }
+            None => continue,
+        };
+        let Some(stsd) =
+            find(reader, &trak, &[b"mdia", b"minf", b"stbl", b"stsd"])?
+        else {
+            continue;
+        };
+
+        // version and flags, then the entry count, then the first entry
+        let entry = stsd.start + 8;
+        reader.seek(SeekFrom::Start(entry + 4))?;
+        let mut format = [0; 4];
+        reader.read_exact(&mut format)?;
+
+        match &handler {
+            b"vide" if info.video_codec.is_none() => {
+                // the visual sample entry's width and height come after its
+                // reserved and pre-defined fields
+                reader.seek(SeekFrom::Start(entry + 32))?;
+                let mut dimensions = [0; 4];
+                reader.read_exact(&mut dimensions)?;
+
+                info.kind = MediaKind::Video;
+                info.width =
+                    Some(u16::from_be_bytes([dimensions[0], dimensions[1]])
+                        as u32);
+                info.height =
+                    Some(u16::from_be_bytes([dimensions[2], dimensions[3]])
+                        as u32);
+                info.video_codec = Some(codec_name(&format));
+                has_track = true;
+            }
+            b"soun" if info.audio_codec.is_none() => {
+                info.audio_codec = Some(codec_name(&format));
+                has_track = true;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(has_track.then_some(info))
+}
+
+fn movie_duration(
+    reader: &mut (impl Read + Seek),
+    mvhd: &Mp4Box,
+) -> io::Result<Option<f64>> {
+    reader.seek(SeekFrom::Start(mvhd.start))?;
+    let mut version = [0; 4];
+    reader.read_exact(&mut version)?;
+
+    let (timescale, duration) = if version[0] == 1 {
+        // 64-bit creation and modification times
+        reader.seek(SeekFrom::Current(16))?;
+        (read_u32(reader)?, read_u64(reader)?)
+    } else {
+        reader.seek(SeekFrom::Current(8))?;
+        (read_u32(reader)?, read_u32(reader)? as u64)
+    };
+
+    // fragmented files leave it at 0, and all ones means it's unknown
+    if timescale == 0
+        || duration == 0
+        || duration == u64::MAX
+        || duration == u32::MAX as u64
+    {
+        return Ok(None);
+    }
+
+    Ok(Some(duration as f64 / timescale as f64))
+}
+
+fn codec_name(format: &[u8; 4]) -> String {
+    match format {
+        b"avc1" | b"avc3" => "h264".to_string(),
+        b"hvc1" | b"hev1" => "hevc".to_string(),
+        b"av01" => "av1".to_string(),
+        b"vp08" => "vp8".to_string(),
+        b"vp09" => "vp9".to_string(),
+        b"mp4v" => "mpeg4".to_string(),
+        b"mp4a" => "aac".to_string(),
+        b"Opus" => "opus".to_string(),
+        b"fLaC" => "flac".to_string(),
+        b"alac" => "alac".to_string(),
+        b"ac-3" => "ac3".to_string(),
+        b"ec-3" => "eac3".to_string(),
+        b".mp3" => "mp3".to_string(),
+        other => String::from_utf8_lossy(other).trim().to_string(),
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn mp4_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut bytes = (content.len() as u32 + 8).to_be_bytes().to_vec();
+        bytes.extend_from_slice(kind);
+        bytes.extend_from_slice(content);
+        bytes
+    }
+
+    fn mvhd(timescale: u32, duration: u32) -> Vec<u8> {
+        let mut content = vec![0; 12];
+        content.extend_from_slice(&timescale.to_be_bytes());
+        content.extend_from_slice(&duration.to_be_bytes());
+        content.extend_from_slice(&[0; 80]);
+        mp4_box(b"mvhd", &content)
+    }
+
+    fn trak(handler: &[u8; 4], entry: &[u8]) -> Vec<u8> {
+        let mut hdlr = vec![0; 8];
+        hdlr.extend_from_slice(handler);
+        hdlr.extend_from_slice(&[0; 12]);
+
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend_from_slice(entry);
+
+        let stbl = mp4_box(b"stbl", &mp4_box(b"stsd", &stsd));
+        let minf = mp4_box(b"minf", &stbl);
+        let mdia = mp4_box(b"mdia", &[mp4_box(b"hdlr", &hdlr), minf].concat());
+        mp4_box(b"trak", &mdia)
+    }
+
+    fn visual_entry(format: &[u8; 4], width: u16, height: u16) -> Vec<u8> {
+        let mut content = vec![0; 24];
+        content.extend_from_slice(&width.to_be_bytes());
+        content.extend_from_slice(&height.to_be_bytes());
+        content.extend_from_slice(&[0; 50]);
+        mp4_box(format, &content)
+    }
+
+    fn audio_entry(format: &[u8; 4]) -> Vec<u8> {
+        mp4_box(format, &[0; 28])
+    }
+
+    fn file(moov: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = mp4_box(b"ftyp", b"isom\0\0\0\0isomavc1");
+        bytes.extend(mp4_box(b"mdat", &[0xAB; 32]));
+        bytes.extend(mp4_box(b"moov", &moov.concat()));
+        bytes
+    }
+
+    fn probe_bytes(bytes: &[u8]) -> io::Result<Option<MediaInfo>> {
+        probe(&mut Cursor::new(bytes), bytes.len() as u64)
+    }
+
+    #[test]
+    fn reads_video_and_audio_tracks() {
+        let bytes = file(&[
+            mvhd(1000, 83_500),
+            trak(b"vide", &visual_entry(b"avc1", 1920, 1080)),
+            trak(b"soun", &audio_entry(b"mp4a")),
+        ]);
+
+        let info = probe_bytes(&bytes).unwrap().unwrap();
+
+        assert_eq!(info.kind, MediaKind::Video);
+        assert_eq!(info.duration_seconds, Some(83.5));
+        assert_eq!((info.width, info.height), (Some(1920), Some(1080)));
+        assert_eq!(info.video_codec.as_deref(), Some("h264"));
+        assert_eq!(info.audio_codec.as_deref(), Some("aac"));
+    }
+
+    #[test]
+    fn reads_audio_only_files() {
+        let bytes = file(&[
+            mvhd(44_100, 441_000),
+            trak(b"soun", &audio_entry(b"Opus")),
+        ]);
+
+        let info = probe_bytes(&bytes).unwrap().unwrap();
+
+        assert_eq!(info.kind, MediaKind::Audio);
+        assert_eq!(info.duration_seconds, Some(10.0));
+        assert_eq!(info.width, None);
+        assert_eq!(info.audio_codec.as_deref(), Some("opus"));
+    }
+
+    #[test]
+    fn keeps_the_first_track_of_each_kind() {
+        let bytes = file(&[
+            trak(b"vide", &visual_entry(b"hvc1", 640, 480)),
+            trak(b"vide", &visual_entry(b"avc1", 320, 240)),
+        ]);
+
+        let info = probe_bytes(&bytes).unwrap().unwrap();
+
+        assert_eq!(info.duration_seconds, None);
+        assert_eq!((info.width, info.height), (Some(640), Some(480)));
+        assert_eq!(info.video_codec.as_deref(), Some("hevc"));
+    }
+
+    #[test]
+    fn reads_64_bit_movie_headers() {
+        let mut content = vec![1, 0, 0, 0];
+        content.extend_from_slice(&[0; 16]);
+        content.extend_from_slice(&600_u32.to_be_bytes());
+        content.extend_from_slice(&(600_u64 * 90).to_be_bytes());
+        let bytes = file(&[
+            mp4_box(b"mvhd", &content),
+            trak(b"soun", &audio_entry(b"fLaC")),
+        ]);
+
+        let info = probe_bytes(&bytes).unwrap().unwrap();
+
+        assert_eq!(info.duration_seconds, Some(90.0));
+    }
+
+    #[test]
+    fn ignores_unknown_durations() {
+        let bytes =
+            file(&[mvhd(1000, u32::MAX), trak(b"soun", &audio_entry(b"mp4a"))]);
+
+        let info = probe_bytes(&bytes).unwrap().unwrap();
+
+        assert_eq!(info.duration_seconds, None);
+    }
+
+    #[test]
+    fn reads_boxes_with_64_bit_sizes() {
+        let mut moov = 1_u32.to_be_bytes().to_vec();
+        moov.extend_from_slice(b"moov");
+        let content = trak(b"soun", &audio_entry(b"mp4a"));
+        moov.extend_from_slice(&(content.len() as u64 + 16).to_be_bytes());
+        moov.extend(content);
+
+        let info = probe_bytes(&moov).unwrap().unwrap();
+
+        assert_eq!(info.audio_codec.as_deref(), Some("aac"));
+    }
+
+    #[test]
+    fn returns_none_without_tracks() {
+        assert!(probe_bytes(&file(&[mvhd(1000, 1000)])).unwrap().is_none());
+        assert!(probe_bytes(&mp4_box(b"ftyp", b"isom")).unwrap().is_none());
+        assert!(probe_bytes(b"not a video").unwrap().is_none());
+    }
+
+    #[test]
+    fn stops_at_malformed_box_sizes() {
+        let mut bytes = mp4_box(b"ftyp", b"isom");
+        // smaller than its own header
+        bytes.extend_from_slice(&4_u32.to_be_bytes());
+        bytes.extend_from_slice(b"moov");
+
+        assert!(probe_bytes(&bytes).unwrap().is_none());
+    }
+}
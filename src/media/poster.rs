@@ -0,0 +1,88 @@
+//! Grabs poster frames from videos with ffmpeg.
+
+use std::{
+    io,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::config::MediaConfig;
+
+/// Posters are scaled down to fit this wide, which is plenty for embeds.
+const MAX_WIDTH: u32 = 1280;
+
+/// Whether ffmpeg can be run at all.
+pub fn is_available(config: &MediaConfig) -> bool {
+    !config.ffmpeg_path.is_empty()
+        && Command::new(&config.ffmpeg_path)
+            .arg("-version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+}
+
+/// Writes a JPEG of a frame of the video at `input` to `output`.
+///
+/// The frame is taken `poster_offset_seconds` in, or halfway through videos
+/// shorter than that.
+pub fn grab(
+    config: &MediaConfig,
+    input: &Path,
+    duration_seconds: Option<f64>,
+    output: &Path,
+) -> io::Result<()> {
+    let offset = match duration_seconds {
+        Some(duration) if duration < config.poster_offset_seconds => {
+            duration / 2.0
+        }
+        _ => config.poster_offset_seconds,
+    };
+
+    let mut child = Command::new(&config.ffmpeg_path)
+        .args(["-v", "error", "-ss", &format!("{:.3}", offset), "-i"])
+        .arg(input)
+        .args([
+            "-frames:v",
+            "1",
+            "-vf",
+            &format!("scale='min({},iw)':-2", MAX_WIDTH),
+            "-f",
+            "image2",
+            "-y",
+        ])
+        .arg(output)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let timeout = Duration::from_secs(config.poster_timeout_seconds);
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "ffmpeg took too long",
+            ));
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+
+    if !status.success() {
+        return Err(io::Error::other(format!("ffmpeg exited with {}", status)));
+    }
+    if !output.exists() {
+        return Err(io::Error::other("ffmpeg didn't write a frame"));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,276 @@
+mod queue;
+
+use std::{
+    io::{self, ErrorKind},
+    path::PathBuf,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::Serialize;
+
+use crate::{
+    config::{Config, MirrorConfig},
+    storage::{self, Stat, Storage, StorageBackend},
+};
+
+use queue::{Action, Job, Queue};
+
+/// Copies uploads to a second backend in the background, and removes them
+/// from it when they're deleted.
+///
+/// Changes are queued and applied in order by a worker thread, retrying with
+/// a growing delay when the mirror can't be reached. The queue is persisted,
+/// so changes made while the mirror is down survive restarts.
+pub struct Mirror {
+    shared: Option<Arc<Shared>>,
+}
+
+struct Shared {
+    config: MirrorConfig,
+    backend: Arc<dyn StorageBackend>,
+    /// The namespaces changes are read from, replaced when the configuration
+    /// is reloaded.
+    storage: ArcSwap<Storage>,
+    path: PathBuf,
+    queue: Mutex<Queue>,
+    wake: Condvar,
+}
+
+/// What `/_admin/api/mirror/status` answers with.
+#[derive(Debug, Serialize)]
+pub struct MirrorStatus {
+    pub enabled: bool,
+    /// Changes not yet applied to the mirror.
+    pub pending: usize,
+    /// Pending changes that have failed at least once.
+    pub failing: usize,
+    /// How long the oldest pending change has been waiting.
+    pub lag_seconds: u64,
+    pub last_success: Option<String>,
+    pub last_error: Option<String>,
+}
+
+impl Mirror {
+    /// Loads the queue from `path` and starts the worker, if mirroring is
+    /// enabled.
+    pub fn start(
+        config: &Config,
+        storage: Arc<Storage>,
+        path: PathBuf,
+    ) -> io::Result<Mirror> {
+        let mirror_config = &config.storage.mirror;
+        if !mirror_config.enabled {
+            return Ok(Self { shared: None });
+        }
+
+        let backend = storage::backend_for(config, &mirror_config.backend);
+        if let Err(e) = backend.initialise("") {
+            // uploads shouldn't stop because the backup disk is missing;
+            // everything is queued until it's back
+            error!("Failed to set up mirror, changes will be queued: {}", e);
+        }
+
+        let queue = Queue::load(&path)?;
+        if !queue.jobs.is_empty() {
+            info!("Resuming {} pending mirror changes", queue.jobs.len());
+        }
+
+        let shared = Arc::new(Shared {
+            config: mirror_config.clone(),
+            backend,
+            storage: ArcSwap::new(storage),
+            path,
+            queue: Mutex::new(queue),
+            wake: Condvar::new(),
+        });
+
+        let worker = Arc::clone(&shared);
+        thread::Builder::new()
+            .name("mirror".to_string())
+            .spawn(move || worker.run())?;
+
+        Ok(Self {
+            shared: Some(shared),
+        })
+    }
+
+    /// Reads files from the namespaces in `storage` from now on, after the
+    /// configuration has been reloaded.
+    pub fn use_storage(&self, storage: Arc<Storage>) {
+        if let Some(shared) = &self.shared {
+            shared.storage.store(storage);
+        }
+    }
+
+    /// Queues a copy of the file at `key` in `namespace`.
+    pub fn put(&self, namespace: &str, key: &str) {
+        self.push(Action::Put, namespace, key);
+    }
+
+    /// Queues the removal of the file at `key` in `namespace`.
+    pub fn delete(&self, namespace: &str, key: &str) {
+        self.push(Action::Delete, namespace, key);
+    }
+
+    fn push(&self, action: Action, namespace: &str, key: &str) {
+        let Some(shared) = &self.shared else {
+            return;
+        };
+
+        let mut queue = shared.lock();
+        queue.push(action, namespace, key, now());
+        shared.persist(&queue);
+        shared.wake.notify_one();
+    }
+
+    pub fn status(&self) -> MirrorStatus {
+        let Some(shared) = &self.shared else {
+            return MirrorStatus {
+                enabled: false,
+                pending: 0,
+                failing: 0,
+                lag_seconds: 0,
+                last_success: None,
+                last_error: None,
+            };
+        };
+
+        let queue = shared.lock();
+        let now = now();
+
+        MirrorStatus {
+            enabled: true,
+            pending: queue.jobs.len(),
+            failing: queue.jobs.iter().filter(|job| job.attempts > 0).count(),
+            lag_seconds: queue
+                .jobs
+                .iter()
+                .map(|job| now.saturating_sub(job.queued_at))
+                .max()
+                .unwrap_or(0),
+            last_success: queue.last_success_at.map(|at| {
+                DateTime::<Utc>::from(
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(at),
+                )
+                .to_rfc3339()
+            }),
+            last_error: queue
+                .jobs
+                .iter()
+                .filter_map(|job| job.last_error.clone())
+                .next(),
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue
+            .lock()
+            .expect("mirror queue lock should not be poisoned")
+    }
+
+    fn persist(&self, queue: &Queue) {
+        if let Err(e) = queue.persist(&self.path) {
+            error!("Failed to persist mirror queue: {}", e);
+        }
+    }
+
+    /// Applies due jobs one at a time, sleeping until the next one is due or
+    /// a new one is queued.
+    fn run(&self) {
+        loop {
+            let job = {
+                let mut queue = self.lock();
+                loop {
+                    let now = now();
+                    if let Some(job) = queue.next_due(now) {
+                        break job.clone();
+                    }
+
+                    let wait = queue
+                        .next_attempt_at()
+                        .map(|at| Duration::from_secs(at - now))
+                        .unwrap_or(Duration::from_secs(60 * 60));
+                    queue = self
+                        .wake
+                        .wait_timeout(queue, wait)
+                        .expect("mirror queue lock should not be poisoned")
+                        .0;
+                }
+            };
+
+            let result = self.apply(&job);
+
+            let mut queue = self.lock();
+            match result {
+                Ok(_) => {
+                    queue.remove(job.id);
+                    queue.last_success_at = Some(now());
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to mirror {:?} of {}/{}: {}",
+                        job.action, job.namespace, job.key, e
+                    );
+
+                    let delay = self.retry_delay(job.attempts);
+                    if let Some(job) = queue.get_mut(job.id) {
+                        job.attempts += 1;
+                        job.next_attempt_at = now() + delay;
+                        job.last_error = Some(e.to_string());
+                    }
+                }
+            }
+            self.persist(&queue);
+        }
+    }
+
+    fn retry_delay(&self, attempts: u32) -> u64 {
+        self.config
+            .retry_interval_seconds
+            .saturating_mul(1 << attempts.min(20))
+            .min(self.config.max_retry_interval_seconds)
+    }
+
+    fn apply(&self, job: &Job) -> io::Result<()> {
+        let storage = self.storage.load();
+        let Some(storage) = storage.namespace(&job.namespace) else {
+            warn!(
+                "Dropping mirror change for unknown namespace '{}'",
+                job.namespace
+            );
+            return Ok(());
+        };
+        let mirror_key = storage.backend_key(&job.key);
+
+        match job.action {
+            Action::Put => {
+                let size = match storage.stat(&job.key)? {
+                    Some(Stat::File { size, .. }) => size,
+                    // deleted before it could be copied; its deletion is
+                    // queued behind it
+                    _ => return Ok(()),
+                };
+
+                let mut reader = storage.get_range(&job.key, 0, size)?;
+                self.backend.put(&mirror_key, &mut reader, size)
+            }
+            Action::Delete => match self.backend.delete(&mirror_key) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
@@ -0,0 +1,111 @@
+use std::{collections::VecDeque, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+/// What a job does to the mirror.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Copy the file to the mirror.
+    Put,
+    /// Remove the file from the mirror.
+    Delete,
+}
+
+/// A change waiting to be applied to the mirror.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Job {
+    pub id: u64,
+    pub action: Action,
+    pub namespace: String,
+    /// Key of the file in its namespace's storage.
+    pub key: String,
+    /// Unix timestamps, in seconds.
+    pub queued_at: u64,
+    pub next_attempt_at: u64,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+/// Jobs waiting to be applied to the mirror, persisted as JSON so nothing is
+/// forgotten over a restart.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Queue {
+    next_id: u64,
+    pub jobs: VecDeque<Job>,
+    /// When a job last succeeded, as a Unix timestamp in seconds.
+    pub last_success_at: Option<u64>,
+}
+
+impl Queue {
+    /// Loads the queue from `path`, starting empty if it doesn't exist yet.
+    pub fn load(path: &Path) -> io::Result<Queue> {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Ok(Queue::default())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the queue next to its final location and renames it into
+    /// place, so a crash can't leave a half-written file behind.
+    pub fn persist(&self, path: &Path) -> io::Result<()> {
+        let serialized = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serialized)?;
+        fs::rename(&temp_path, path)
+    }
+
+    pub fn push(
+        &mut self,
+        action: Action,
+        namespace: &str,
+        key: &str,
+        now: u64,
+    ) {
+        // a pending copy of a file that's being deleted is pointless, and
+        // could otherwise land after the deletion
+        if action == Action::Delete {
+            self.jobs.retain(|job| {
+                !(job.action == Action::Put
+                    && job.namespace == namespace
+                    && job.key == key)
+            });
+        }
+
+        self.jobs.push_back(Job {
+            id: self.next_id,
+            action,
+            namespace: namespace.to_string(),
+            key: key.to_string(),
+            queued_at: now,
+            next_attempt_at: now,
+            attempts: 0,
+            last_error: None,
+        });
+        self.next_id += 1;
+    }
+
+    /// The first job that's due at `now`.
+    pub fn next_due(&self, now: u64) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.next_attempt_at <= now)
+    }
+
+    /// When the next job will be due, if there are any.
+    pub fn next_attempt_at(&self) -> Option<u64> {
+        self.jobs.iter().map(|job| job.next_attempt_at).min()
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        self.jobs.retain(|job| job.id != id);
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+}
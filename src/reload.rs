@@ -0,0 +1,297 @@
+//! Reloading the configuration while the server is running.
+//!
+//! When the configuration file changes, or the process gets `SIGHUP`, the
+//! file is read again and a server for it takes over the listening socket.
+//! The previous server stops accepting connections, but finishes the requests
+//! it's already handling, so uploads in progress aren't dropped. A
+//! configuration that fails to load is logged and ignored, and the previous
+//! one keeps serving.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    pin::pin,
+    time::Duration,
+};
+
+use actix_web::{dev::Server, rt, web::Data};
+use futures_util::future::{Either, select};
+use log::{error, info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::config::{Config, Format};
+
+/// Editors often write a file in several steps, so changes are only acted on
+/// once they've settled for this long.
+const SETTLE_TIME: Duration = Duration::from_millis(250);
+
+/// Asks for the configuration to be reloaded, like after it's been changed
+/// through the admin API.
+#[derive(Clone)]
+pub struct Reloader(UnboundedSender<()>);
+
+impl Reloader {
+    pub fn reload(&self) {
+        let _ = self.0.send(());
+    }
+}
+
+/// Requests to reload the configuration, from wherever they come from.
+pub struct Reloads {
+    sender: UnboundedSender<()>,
+    receiver: UnboundedReceiver<()>,
+}
+
+impl Reloads {
+    pub fn new() -> Reloads {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Reloads { sender, receiver }
+    }
+
+    pub fn reloader(&self) -> Reloader {
+        Reloader(self.sender.clone())
+    }
+}
+
+/// Runs `server` until it stops, replacing it with one built by `rebuild`
+/// whenever the configuration at `config_path` is reloaded.
+pub async fn serve(
+    mut server: Server,
+    mut config: Data<Config>,
+    config_path: PathBuf,
+    reloads: Reloads,
+    mut rebuild: impl FnMut(Data<Config>) -> io::Result<Server>,
+) -> io::Result<()> {
+    let Reloads {
+        sender,
+        receiver: mut reloads,
+    } = reloads;
+
+    // kept alive for as long as the configuration they're for is used
+    let watch = |config: &Config| {
+        let mut watchers: Vec<_> = watch_file(&config_path, sender.clone())
+            .inspect_err(|e| {
+                error!(
+                    "Failed to watch `{}`; the configuration will only be \
+                     reloaded on SIGHUP: {}",
+                    config_path.display(),
+                    e
+                )
+            })
+            .into_iter()
+            .collect();
+        // ones that don't exist yet are picked up on the next reload
+        watchers.extend(
+            config
+                .namespace_directories(&config_path)
+                .iter()
+                .filter(|directory| directory.is_dir())
+                .filter_map(|directory| {
+                    watch_directory(directory, sender.clone())
+                        .inspect_err(|e| {
+                            error!(
+                                "Failed to watch `{}`; changes in it will \
+                                 only be picked up on SIGHUP: {}",
+                                directory.display(),
+                                e
+                            )
+                        })
+                        .ok()
+                }),
+        );
+        watchers
+    };
+    let mut _watchers = watch(&config);
+    handle_hangups(sender.clone())?;
+
+    loop {
+        let reload = match select(&mut server, pin!(reloads.recv())).await {
+            Either::Left((result, _)) => return result,
+            Either::Right((reload, _)) => reload,
+        };
+        if reload.is_none() {
+            return server.await;
+        }
+        settle(&mut reloads).await;
+
+        let mut new_config = match Config::load(&config_path) {
+            Ok(new_config) => new_config,
+            Err(e) => {
+                error!(
+                    "Failed to reload configuration, keeping the current \
+                     one: {}",
+                    e
+                );
+                continue;
+            }
+        };
+
+        keep_restart_only_settings(&config, &mut new_config);
+
+        let new_config = Data::new(new_config);
+        let new_server = match rebuild(new_config.clone()) {
+            Ok(new_server) => new_server,
+            Err(e) => {
+                error!(
+                    "Failed to apply reloaded configuration, keeping the \
+                     current one: {}",
+                    e
+                );
+                continue;
+            }
+        };
+
+        let old_server = std::mem::replace(&mut server, new_server);
+        let stopped = old_server.handle().stop(true);
+        rt::spawn(old_server);
+        rt::spawn(stopped);
+
+        config = new_config;
+        _watchers = watch(&config);
+        info!(
+            "Reloaded configuration with {} upload namespace{}",
+            config.namespaces.len(),
+            if config.namespaces.len() == 1 {
+                ""
+            } else {
+                "s"
+            }
+        );
+    }
+}
+
+/// Waits for further changes to stop coming in, then drops them; they're
+/// all covered by one reload.
+async fn settle(reloads: &mut UnboundedReceiver<()>) {
+    rt::time::sleep(SETTLE_TIME).await;
+    while reloads.try_recv().is_ok() {}
+}
+
+/// Sends to `sender` whenever the file at `path` changes.
+///
+/// Its directory is watched rather than the file itself, as editors and
+/// tools like Kubernetes replace files instead of writing to them.
+fn watch_file(
+    path: &Path,
+    sender: UnboundedSender<()>,
+) -> notify::Result<RecommendedWatcher> {
+    let file_name = path.file_name().map(|name| name.to_os_string());
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            if matches!(event.kind, EventKind::Access(_)) {
+                return;
+            }
+            if event
+                .paths
+                .iter()
+                .any(|path| path.file_name() == file_name.as_deref())
+            {
+                let _ = sender.send(());
+            }
+        })?;
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+
+    Ok(watcher)
+}
+
+/// Sends to `sender` whenever a configuration file in `directory` changes.
+fn watch_directory(
+    directory: &Path,
+    sender: UnboundedSender<()>,
+) -> notify::Result<RecommendedWatcher> {
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            if matches!(event.kind, EventKind::Access(_)) {
+                return;
+            }
+            if event.paths.iter().any(|path| Format::is_config_file(path)) {
+                let _ = sender.send(());
+            }
+        })?;
+    watcher.watch(directory, RecursiveMode::NonRecursive)?;
+
+    Ok(watcher)
+}
+
+/// Sends to `sender` whenever the process gets `SIGHUP`.
+#[cfg(unix)]
+fn handle_hangups(sender: UnboundedSender<()>) -> io::Result<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangups = signal(SignalKind::hangup())?;
+    rt::spawn(async move {
+        while hangups.recv().await.is_some() {
+            info!("Got SIGHUP, reloading configuration");
+            if sender.send(()).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn handle_hangups(_sender: UnboundedSender<()>) -> io::Result<()> {
+    Ok(())
+}
+
+/// Puts back the settings that are only read when the server starts, with
+/// a warning for each one that changed, so the configuration being served is
+/// the one that's actually in effect.
+fn keep_restart_only_settings(old: &Config, new: &mut Config) {
+    keep(
+        "web_server.host",
+        &old.web_server.host,
+        &mut new.web_server.host,
+    );
+    keep(
+        "web_server.port",
+        &old.web_server.port,
+        &mut new.web_server.port,
+    );
+    keep(
+        "storage.uploads_directory",
+        &old.storage.uploads_directory,
+        &mut new.storage.uploads_directory,
+    );
+    keep(
+        "storage.image_cache_max_size_bytes",
+        &old.storage.image_cache_max_size_bytes,
+        &mut new.storage.image_cache_max_size_bytes,
+    );
+    keep(
+        "storage.mirror",
+        &old.storage.mirror,
+        &mut new.storage.mirror,
+    );
+    keep(
+        "file_listing_render.cache",
+        &old.file_listing_render.cache,
+        &mut new.file_listing_render.cache,
+    );
+    keep("media", &old.media, &mut new.media);
+}
+
+fn keep<T: Serialize + Clone>(setting: &str, old: &T, new: &mut T) {
+    if toml::Value::try_from(old).ok() != toml::Value::try_from(&*new).ok() {
+        warn!(
+            "`{}` changed, but only takes effect after a restart",
+            setting
+        );
+        *new = old.clone();
+    }
+}
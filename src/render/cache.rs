@@ -0,0 +1,361 @@
+use std::{
+    collections::HashMap,
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use actix_web::{
+    HttpRequest, HttpResponse,
+    http::header::{
+        self, ContentEncoding, ContentType, ETag, EntityTag, Header,
+        IfNoneMatch,
+    },
+    web::Bytes,
+};
+use flate2::{Compression, write::GzEncoder};
+use log::{debug, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::config::ListingCacheConfig;
+
+/// Identifies a rendered listing: the directory's key in storage, plus the
+/// request path and query it was rendered for (links in the listing are
+/// relative to it).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    directory: String,
+    request_path: String,
+    query: String,
+}
+
+/// A rendered, minified listing along with a gzipped copy of it.
+#[derive(Debug)]
+pub struct CachedListing {
+    etag: EntityTag,
+    html: Bytes,
+    gzip: Option<Bytes>,
+}
+
+impl CachedListing {
+    pub fn new(html: Vec<u8>) -> CachedListing {
+        let mut hasher = DefaultHasher::new();
+        html.hash(&mut hasher);
+        let etag = EntityTag::new_strong(format!("{:016x}", hasher.finish()));
+
+        let gzip = gzip(&html)
+            .inspect_err(|e| warn!("Failed to compress listing: {}", e))
+            .ok()
+            .map(Bytes::from);
+
+        Self {
+            etag,
+            html: Bytes::from(html),
+            gzip,
+        }
+    }
+
+    /// Builds a response for this listing, honouring `If-None-Match` and
+    /// serving the pre-compressed body when the client accepts gzip.
+    pub fn respond_to(&self, req: &HttpRequest) -> HttpResponse {
+        if let Ok(if_none_match) = IfNoneMatch::parse(req) {
+            let matches = match if_none_match {
+                IfNoneMatch::Any => true,
+                IfNoneMatch::Items(tags) => {
+                    tags.iter().any(|tag| tag.weak_eq(&self.etag))
+                }
+            };
+
+            if matches {
+                return HttpResponse::NotModified()
+                    .insert_header(ETag(self.etag.clone()))
+                    .finish();
+            }
+        }
+
+        let mut response = HttpResponse::Ok();
+        response
+            .content_type(ContentType::html())
+            .insert_header(ETag(self.etag.clone()))
+            .insert_header((header::VARY, "Accept-Encoding"));
+
+        match &self.gzip {
+            Some(gzip) if accepts_gzip(req) => response
+                .insert_header(ContentEncoding::Gzip)
+                .body(gzip.clone()),
+            _ => response.body(self.html.clone()),
+        }
+    }
+}
+
+/// A cached listing and when it was last used, by the cache's clock.
+struct Entry {
+    listing: Arc<CachedListing>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    entries: HashMap<CacheKey, Entry>,
+    /// Ticks on every use, ordering entries by how recently they were used.
+    clock: u64,
+}
+
+impl Entries {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+/// In-memory cache of rendered directory listings.
+///
+/// Entries are dropped when the directory they were rendered from changes,
+/// either through our own upload path or through the filesystem watcher.
+/// Once `max_entries` are cached, the least recently used one makes way.
+pub struct ListingCache {
+    config: ListingCacheConfig,
+    entries: Mutex<Entries>,
+}
+
+impl ListingCache {
+    pub fn new(config: ListingCacheConfig) -> ListingCache {
+        Self {
+            config,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    pub fn get(
+        &self,
+        directory: &str,
+        req: &HttpRequest,
+    ) -> Option<Arc<CachedListing>> {
+        if !self.config.enabled {
+            return None;
+        }
+
+        let mut entries = self.lock();
+        let now = entries.tick();
+        let entry = entries.entries.get_mut(&key_for(directory, req))?;
+        entry.last_used = now;
+        Some(entry.listing.clone())
+    }
+
+    pub fn insert(
+        &self,
+        directory: &str,
+        req: &HttpRequest,
+        listing: Arc<CachedListing>,
+    ) {
+        if !self.config.enabled || self.config.max_entries == 0 {
+            return;
+        }
+
+        let mut entries = self.lock();
+        let key = key_for(directory, req);
+
+        if !entries.entries.contains_key(&key)
+            && entries.entries.len() >= self.config.max_entries
+        {
+            let evicted = entries
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(evicted) = evicted {
+                entries.entries.remove(&evicted);
+            }
+        }
+
+        let last_used = entries.tick();
+        entries.entries.insert(key, Entry { listing, last_used });
+    }
+
+    /// Drops every cached listing rendered from `directory`.
+    pub fn invalidate(&self, directory: &str) {
+        self.lock()
+            .entries
+            .retain(|key, _| key.directory != directory);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        self.entries
+            .lock()
+            .expect("listing cache lock should not be poisoned")
+    }
+
+    /// Drops every cached listing that could show `key`, i.e. the listing of
+    /// its parent directory and, if it is a directory, its own listing.
+    pub fn invalidate_path(&self, key: &str) {
+        let parent = key.rsplit_once('/').map_or("", |(parent, _)| parent);
+        self.invalidate(parent);
+        self.invalidate(key);
+    }
+
+    /// Starts watching `path`, the root of a filesystem backend,
+    /// recursively, invalidating listings on any change. The returned
+    /// watcher must be kept alive for as long as the cache should keep
+    /// receiving events.
+    pub fn watch(
+        self: &Arc<Self>,
+        path: &Path,
+    ) -> notify::Result<RecommendedWatcher> {
+        let cache = Arc::clone(self);
+        // events carry the path that was watched, so watch the one that's
+        // stripped from them, whatever symlinks lead to it
+        let root = fs::canonicalize(path)?;
+        let watched = root.clone();
+
+        let mut watcher = notify::recommended_watcher(
+            move |event: notify::Result<Event>| match event {
+                Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                    for path in &event.paths {
+                        let Some(key) = key_in(&root, path) else {
+                            continue;
+                        };
+                        debug!("Invalidating listings for {:?}", path);
+                        cache.invalidate_path(&key);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Filesystem watcher error: {}", e),
+            },
+        )?;
+
+        watcher.watch(&watched, RecursiveMode::Recursive)?;
+
+        Ok(watcher)
+    }
+}
+
+/// The storage key of `path`, which is somewhere below `root`.
+fn key_in(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let segments: Vec<_> = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+
+    Some(segments.join("/"))
+}
+
+fn key_for(directory: &str, req: &HttpRequest) -> CacheKey {
+    CacheKey {
+        directory: directory.to_string(),
+        request_path: req.path().to_string(),
+        query: req.query_string().to_string(),
+    }
+}
+
+fn accepts_gzip(req: &HttpRequest) -> bool {
+    req.headers()
+        .get_all(header::ACCEPT_ENCODING)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| {
+            let mut parts = encoding.split(';');
+            let name = parts.next().unwrap_or("").trim();
+            let rejected = parts.any(|param| {
+                matches!(param.trim(), "q=0" | "q=0.0" | "q=0.00" | "q=0.000")
+            });
+
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !rejected
+        })
+}
+
+fn gzip(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn cache(max_entries: usize) -> ListingCache {
+        ListingCache::new(ListingCacheConfig {
+            max_entries,
+            ..ListingCacheConfig::default()
+        })
+    }
+
+    fn request(path: &str) -> HttpRequest {
+        TestRequest::get().uri(path).to_http_request()
+    }
+
+    fn listing(html: &str) -> Arc<CachedListing> {
+        Arc::new(CachedListing::new(html.as_bytes().to_vec()))
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_listing() {
+        let cache = cache(2);
+        cache.insert("f/a", &request("/f/a/"), listing("a"));
+        cache.insert("f/b", &request("/f/b/"), listing("b"));
+
+        // a was used more recently than b now
+        assert!(cache.get("f/a", &request("/f/a/")).is_some());
+        cache.insert("f/c", &request("/f/c/"), listing("c"));
+
+        assert!(cache.get("f/a", &request("/f/a/")).is_some());
+        assert!(cache.get("f/b", &request("/f/b/")).is_none());
+        assert!(cache.get("f/c", &request("/f/c/")).is_some());
+    }
+
+    #[test]
+    fn replacing_a_listing_evicts_nothing() {
+        let cache = cache(2);
+        cache.insert("f/a", &request("/f/a/"), listing("a"));
+        cache.insert("f/b", &request("/f/b/"), listing("b"));
+        cache.insert("f/a", &request("/f/a/"), listing("a2"));
+
+        assert!(cache.get("f/a", &request("/f/a/")).is_some());
+        assert!(cache.get("f/b", &request("/f/b/")).is_some());
+    }
+
+    #[test]
+    fn invalidates_parents_of_changed_files() {
+        let cache = cache(8);
+        cache.insert("f", &request("/f/"), listing("f"));
+        cache.insert("f/a", &request("/f/a/"), listing("a"));
+        cache.insert("g", &request("/g/"), listing("g"));
+
+        cache.invalidate_path("f/a");
+
+        assert!(cache.get("f", &request("/f/")).is_none());
+        assert!(cache.get("f/a", &request("/f/a/")).is_none());
+        assert!(cache.get("g", &request("/g/")).is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn watches_roots_behind_symlinks() {
+        let directory = tempfile::tempdir().unwrap();
+        let real = directory.path().join("real");
+        fs::create_dir_all(real.join("f")).unwrap();
+        let link = directory.path().join("link");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        let cache = Arc::new(cache(8));
+        cache.insert("f", &request("/f/"), listing("f"));
+        let _watcher = cache.watch(&link).unwrap();
+        fs::write(real.join("f/a.png"), "png").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while cache.get("f", &request("/f/")).is_some() {
+            assert!(Instant::now() < deadline, "the listing was kept");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
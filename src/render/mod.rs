@@ -1,4 +1,4 @@
-use std::{cmp::Reverse, fs, io, path::Path, time::SystemTime};
+use std::{cmp::Reverse, io, sync::Arc};
 
 use actix_files::Directory;
 use actix_web::{
@@ -10,51 +10,108 @@ use bytesize::ByteSize;
 use chrono::{DateTime, Utc};
 use minify_html::{Cfg, minify};
 use num_format::{Locale, ToFormattedString};
+use percent_encoding::{
+    AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode,
+};
 use template::{DirectoryView, IndividualListing};
 
-use crate::config::Config;
+use crate::{
+    config::Config,
+    encryption,
+    media::MediaLibrary,
+    storage::{self, Entry, NamespaceStorage, Storage, join_key},
+};
+
+pub use cache::{CachedListing, ListingCache};
 
+mod cache;
 pub mod template;
 
-/// Reads the given directory and returns its entries, where directories are at
-/// the beginning, and everything is sorted by their last modified timestamp
-/// (where most recent is at the start)
-pub fn sorted_entries(dir: &Directory) -> io::Result<Vec<fs::DirEntry>> {
-    let mut entries: Vec<_> = fs::read_dir(&dir.path)?
-        .filter(|res| dir.is_visible(res))
-        .filter_map(Result::ok)
-        .collect();
+/// Characters that can't appear as-is in a path segment of a link.
+pub const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Minifies a rendered page, including its inline CSS.
+pub fn minify_page(html: &str) -> Vec<u8> {
+    let mut cfg = Cfg::new();
+    cfg.minify_css = true;
+    minify(html.as_bytes(), &cfg)
+}
 
-    entries.sort_by_key(|entry| {
-        // try to get metadata
-        // if that fails, treat as file and epoch time.
-        let meta = entry.metadata().ok();
+/// Renders a standalone page, answering with a 500 if askama fails.
+pub fn render_page<T: Template>(page: &T) -> HttpResponse {
+    match page.render() {
+        Ok(html) => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(minify_page(&html)),
+        Err(_) => {
+            HttpResponse::InternalServerError().body("Failed to render page")
+        }
+    }
+}
 
-        // directories first - `is_file = false` for dirs
-        let is_file = meta.as_ref().map(|m| !m.is_dir()).unwrap_or(true);
+/// Lists the directory at `key`, leaving out hidden files, where directories
+/// are at the beginning, and everything is sorted by their last modified
+/// timestamp (where most recent is at the start).
+///
+/// In namespaces encrypted at rest, file sizes are those of their contents.
+pub fn sorted_entries(
+    storage: &NamespaceStorage,
+    key: &str,
+    encrypted: bool,
+) -> io::Result<Vec<Entry>> {
+    let mut entries: Vec<_> = storage
+        .list(key)?
+        .into_iter()
+        .filter(|entry| !entry.name.starts_with('.'))
+        .collect();
 
-        // pull modified time out of the result, defaulting to the unix epoch
-        let modified = meta
-            .and_then(|m| m.modified().ok())
-            .unwrap_or(SystemTime::UNIX_EPOCH);
+    if encrypted {
+        for entry in entries.iter_mut().filter(|entry| !entry.is_dir) {
+            entry.size = encryption::content_len(
+                storage,
+                &join_key(key, &entry.name),
+                entry.size,
+            );
+        }
+    }
 
-        (is_file, Reverse(modified))
-    });
+    // directories first, then most recently modified
+    entries.sort_by_key(|entry| (!entry.is_dir, Reverse(entry.modified)));
 
     Ok(entries)
 }
 
-/// Renders a given directory to a nice HTML structure using askama.
-/// Referred to as the "fancy renderer" in configuration.
-pub fn directory_listing(
-    dir: &Directory,
+/// The cached listing of the directory at `key`, if there is one.
+pub fn cached_listing(
     req: &HttpRequest,
-) -> Result<ServiceResponse, io::Error> {
-    let config: &Data<Config> = req
-        .app_data::<Data<Config>>()
-        .ok_or_else(|| io::Error::other("Missing Config"))?;
-    let dir_entries = sorted_entries(dir)?;
+    storage: &NamespaceStorage,
+    key: &str,
+) -> Option<HttpResponse> {
+    req.app_data::<Data<ListingCache>>()
+        .and_then(|cache| cache.get(&storage.backend_key(key), req))
+        .map(|cached| cached.respond_to(req))
+}
 
+/// Renders the entries of the directory at `key` to a nice HTML structure
+/// using askama. Referred to as the "fancy renderer" in configuration.
+pub fn render_listing(
+    req: &HttpRequest,
+    config: &Config,
+    storage: &NamespaceStorage,
+    key: &str,
+    dir_entries: Vec<Entry>,
+) -> HttpResponse {
     let back_link: Option<String> = {
         let trimmed = req.path().trim_end_matches('/');
         let segments: Vec<&str> =
@@ -69,45 +126,40 @@ pub fn directory_listing(
         }
     };
 
+    let archive_link: Option<String> = config
+        .namespaces
+        .get(storage.name())
+        .filter(|namespace| namespace.file_listing.archive_downloads)
+        .map(|_| format!("/_archive{}", req.path()));
+
     let items_str = dir_entries.len().to_formatted_string(&Locale::en);
 
     let mut list_items =
         Vec::<IndividualListing>::with_capacity(dir_entries.len());
-    let base = Path::new(req.path());
+    let base = req.path().trim_end_matches('/');
+    let media = req.app_data::<Data<MediaLibrary>>();
 
     for entry in dir_entries {
-        let rel = match entry.path().strip_prefix(&dir.path) {
-            Ok(p) if cfg!(windows) => {
-                base.join(p).to_string_lossy().replace('\\', "/")
-            }
-            Ok(p) => base.join(p).to_string_lossy().into_owned(),
-            Err(_) => continue,
-        };
-
-        let meta = match entry.metadata() {
-            Ok(m) => m,
-            Err(_) => continue,
+        let file_href = format!(
+            "{}/{}",
+            base,
+            utf8_percent_encode(&entry.name, PATH_SEGMENT)
+        );
+
+        let media_info = media.filter(|_| !entry.is_dir).and_then(|media| {
+            media.get(&storage.backend_key(&join_key(key, &entry.name)))
+        });
+        let poster_href = match &media_info {
+            Some(info) if info.poster => format!("/_poster{}", file_href),
+            _ => String::new(),
         };
 
-        let modified = meta.modified().unwrap_or(SystemTime::now());
-        let datetime: DateTime<Utc> = modified.into();
-        let raw_time = datetime.format("%Y-%m-%d %H:%M").to_string();
-
-        let is_directory = meta.is_dir();
-        let emoji = config
-            .file_listing_render
-            .emoji
-            .resolve_emoji(&entry, &meta);
-
-        let byte_size = ByteSize::b(meta.len()).display().iec().to_string();
-
         list_items.push(IndividualListing {
-            emoji,
-            timestamp: raw_time,
-            file_href: rel,
-            file_name: entry.file_name().to_string_lossy().to_string(),
-            byte_size,
-            is_directory,
+            media_summary: media_info
+                .map(|info| info.summary())
+                .unwrap_or_default(),
+            poster_href,
+            ..individual_listing(config, entry, file_href)
         });
     }
 
@@ -115,28 +167,82 @@ pub fn directory_listing(
         current_directory: req.path(),
         total_items: &items_str,
         parent_dir_href: &back_link.unwrap_or_default(),
+        archive_href: &archive_link.unwrap_or_default(),
         individual_listings: &list_items,
     };
 
-    let html = directory_view.render();
-
-    if html.is_err() {
-        Ok(ServiceResponse::new(
-            req.clone(),
-            HttpResponse::InternalServerError()
-                .body("Failed to render directory listing"),
-        ))
-    } else {
-        let html_bytes = html.unwrap().into_bytes();
-        let mut cfg = Cfg::new();
-        cfg.minify_css = true;
-        let minified = minify(&html_bytes, &cfg);
-
-        Ok(ServiceResponse::new(
-            req.clone(),
-            HttpResponse::Ok()
-                .content_type(ContentType::html())
-                .body(minified),
-        ))
+    let html = match directory_view.render() {
+        Ok(html) => html,
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .body("Failed to render directory listing");
+        }
+    };
+
+    let listing = Arc::new(CachedListing::new(minify_page(&html)));
+
+    if let Some(cache) = req.app_data::<Data<ListingCache>>() {
+        cache.insert(&storage.backend_key(key), req, Arc::clone(&listing));
+    }
+
+    listing.respond_to(req)
+}
+
+/// A row of a listing, linking to `file_href`.
+pub fn individual_listing(
+    config: &Config,
+    entry: Entry,
+    file_href: String,
+) -> IndividualListing {
+    let datetime: DateTime<Utc> = entry.modified.into();
+
+    IndividualListing {
+        emoji: config
+            .file_listing_render
+            .emoji
+            .resolve_emoji(&entry.name, entry.is_dir),
+        timestamp: datetime.format("%Y-%m-%d %H:%M").to_string(),
+        file_href,
+        file_name: entry.name,
+        byte_size: ByteSize::b(entry.size).display().iec().to_string(),
+        is_directory: entry.is_dir,
+        media_summary: String::new(),
+        poster_href: String::new(),
+    }
+}
+
+/// Renders a directory served by [`actix_files::Files`] with
+/// [`render_listing`].
+pub fn directory_listing(
+    _dir: &Directory,
+    req: &HttpRequest,
+) -> Result<ServiceResponse, io::Error> {
+    let config = req
+        .app_data::<Data<Config>>()
+        .ok_or_else(|| io::Error::other("Missing Config"))?;
+    let storage = req
+        .app_data::<Data<Storage>>()
+        .ok_or_else(|| io::Error::other("Missing Storage"))?;
+
+    // only namespaces get listings, so the first segment is the namespace
+    let path = percent_decode_str(req.path()).decode_utf8_lossy();
+    let (namespace, key) = path
+        .trim_start_matches('/')
+        .split_once('/')
+        .unwrap_or((&path, ""));
+
+    let (storage, key) =
+        match (storage.namespace(namespace), storage::sanitize_key(key)) {
+            (Some(storage), Some(key)) => (storage, key),
+            _ => return Err(io::Error::from(io::ErrorKind::NotFound)),
+        };
+
+    if let Some(cached) = cached_listing(req, storage, &key) {
+        return Ok(ServiceResponse::new(req.clone(), cached));
     }
+
+    let entries = sorted_entries(storage, &key, false)?;
+    let response = render_listing(req, config, storage, &key, entries);
+
+    Ok(ServiceResponse::new(req.clone(), response))
 }
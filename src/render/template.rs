@@ -1,11 +1,14 @@
 use askama::Template;
 
+use crate::config::FileListingConfig;
+
 #[derive(Template)]
 #[template(path = "directory_view.html")]
 pub struct DirectoryView<'a> {
     pub current_directory: &'a str,
     pub total_items: &'a str,
     pub parent_dir_href: &'a str,
+    pub archive_href: &'a str,
     pub individual_listings: &'a Vec<IndividualListing>,
 }
 
@@ -18,4 +21,139 @@ pub struct IndividualListing {
     pub file_name: String,
     pub byte_size: String,
     pub is_directory: bool,
+    /// Duration, dimensions and codec of video and audio files.
+    pub media_summary: String,
+    pub poster_href: String,
+}
+
+#[derive(Template)]
+#[template(path = "decrypt_view.html")]
+pub struct DecryptView<'a> {
+    pub file_name: &'a str,
+    pub ciphertext_href: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "upload_page.html")]
+pub struct UploadPage<'a> {
+    pub namespace: &'a str,
+    pub max_file_size: &'a str,
+    pub max_file_size_bytes: usize,
+    /// 0 when uploads can't be limited to a number of downloads.
+    pub max_downloads_cap: u32,
+    /// Files are encrypted in the browser before they're sent.
+    pub end_to_end_encrypted: bool,
+}
+
+#[derive(Template)]
+#[template(path = "media_player.html")]
+pub struct MediaPlayer<'a> {
+    pub file_name: &'a str,
+    pub is_video: bool,
+    pub content_type: &'a str,
+    /// Where the player fetches the file from.
+    pub src: &'a str,
+    /// Absolute URLs, for link previews.
+    pub page_url: &'a str,
+    pub media_url: &'a str,
+    pub poster_url: &'a str,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub details: &'a str,
+    pub byte_size: &'a str,
+    pub timestamp: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "text_view.html")]
+pub struct TextView<'a> {
+    pub file_name: &'a str,
+    /// `markdown`, `json` or `text`, which the page is styled by.
+    pub kind: &'a str,
+    /// Already rendered and safe to include as it is.
+    pub content: &'a str,
+    pub raw_href: &'a str,
+    pub byte_size: &'a str,
+    pub timestamp: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "admin_login.html")]
+pub struct AdminLogin<'a> {
+    /// Empty on pages shown to someone who isn't signed in.
+    pub username: &'a str,
+    pub notice: &'a str,
+    pub notice_is_error: bool,
+    pub problems: &'a [String],
+    /// The path of the admin pages under `listen_url`, ending in `/`.
+    pub admin_href: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "admin_dashboard.html")]
+pub struct AdminDashboard<'a> {
+    pub username: &'a str,
+    pub notice: &'a str,
+    pub notice_is_error: bool,
+    pub problems: &'a [String],
+    /// What's shown, the value, and whether it's fine.
+    pub health: &'a [(&'a str, String, bool)],
+    pub namespaces: &'a [AdminNamespaceRow],
+    pub recent_uploads: &'a [AdminFileRow],
+    /// Whether file rows say which namespace they're in.
+    pub show_namespace: bool,
+    /// Where deleting a file comes back to.
+    pub return_to: &'a str,
+    pub admin_href: &'a str,
+}
+
+pub struct AdminNamespaceRow {
+    pub name: String,
+    pub path: String,
+    pub backend: &'static str,
+    pub file_count: String,
+    pub usage: String,
+    pub flags: String,
+    /// Why its files couldn't be counted, if they couldn't.
+    pub error: String,
+}
+
+pub struct AdminFileRow {
+    pub namespace: String,
+    /// The file's path in the namespace.
+    pub key: String,
+    pub emoji: String,
+    pub timestamp: String,
+    pub byte_size: String,
+    pub media_summary: String,
+    /// Empty for files with a download limit, which aren't linked to.
+    pub file_href: String,
+    /// Empty for files without a preview.
+    pub thumbnail_href: String,
+    /// How many downloads a limited file has left, empty for other files.
+    pub downloads_left: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin_namespace.html")]
+pub struct AdminNamespace<'a> {
+    pub username: &'a str,
+    pub notice: &'a str,
+    pub notice_is_error: bool,
+    pub problems: &'a [String],
+    pub name: &'a str,
+    pub settings: &'a [(&'a str, String)],
+    pub key: &'a str,
+    /// Just generated, so shown up front.
+    pub new_key: &'a str,
+    /// Where the key comes from, when it can't be rotated.
+    pub key_source: &'a str,
+    pub file_listing: &'a FileListingConfig,
+    pub file_count: &'a str,
+    pub usage: &'a str,
+    pub files: &'a [AdminFileRow],
+    pub files_truncated: bool,
+    pub show_namespace: bool,
+    pub return_to: &'a str,
+    pub admin_href: &'a str,
 }
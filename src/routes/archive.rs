@@ -0,0 +1,468 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Read, Write},
+    time::SystemTime,
+};
+
+use crate::{
+    access,
+    config::{Config, EncryptionKey},
+    downloads::DownloadLimits,
+    encryption::{self, EncryptedFile},
+    storage::{self, NamespaceStorage, Stat, Storage, join_key},
+};
+use actix_web::{
+    HttpRequest, HttpResponse,
+    http::header::{
+        ContentDisposition, ContentType, DispositionParam, DispositionType,
+    },
+    web::{self, Data},
+};
+use chrono::{DateTime, Utc};
+use flate2::{Compression, write::GzEncoder};
+use log::error;
+use serde::Deserialize;
+
+use super::streaming;
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+/// The kinds of archive that are built for directories, and browsed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// The format of the archive at `key`, going by its extension.
+    pub fn from_key(key: &str) -> Option<ArchiveFormat> {
+        let key = key.to_ascii_lowercase();
+        if key.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if key.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if key.ends_with(".tar.gz") || key.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else {
+            None
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::TarGz => "application/gzip",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ArchiveQuery {
+    #[serde(default)]
+    format: ArchiveFormat,
+}
+
+/// A file or directory that will be written to an archive.
+struct ArchiveEntry {
+    /// Key of the entry in the namespace's storage.
+    key: String,
+    /// Path inside the archive, always `/`-separated.
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: SystemTime,
+}
+
+/// Streams a zip, tar or tar.gz of a directory in a namespace that has file
+/// listings enabled.
+pub async fn download_archive(
+    req: HttpRequest,
+    cfg: Data<Config>,
+    storage: Data<Storage>,
+    download_limits: Data<DownloadLimits>,
+    path: web::Path<(String, String)>,
+    query: web::Query<ArchiveQuery>,
+) -> HttpResponse {
+    let (namespace_name, sub_path) = path.into_inner();
+    let format = query.format;
+
+    let (namespace, storage) = match (
+        cfg.namespaces.get(&namespace_name),
+        storage.namespace(&namespace_name),
+    ) {
+        (Some(ns), Some(storage))
+            if ns.file_listing.show && ns.file_listing.archive_downloads =>
+        {
+            (ns, storage.clone())
+        }
+        _ => return HttpResponse::NotFound().body("Not found"),
+    };
+
+    if let Err(response) =
+        access::check_access(&cfg, &namespace_name, namespace, &req)
+    {
+        return response;
+    }
+
+    let directory = match storage::sanitize_key(&sub_path) {
+        Some(directory) => directory,
+        None => return HttpResponse::NotFound().body("Not found"),
+    };
+
+    let root_name = directory
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty())
+        .unwrap_or(&namespace_name)
+        .to_string();
+
+    // limited files would dodge their download count inside an archive
+    let is_limited = {
+        let storage = storage.clone();
+        move |key: &str| download_limits.is_limited(&storage.backend_key(key))
+    };
+
+    let max_size = namespace.file_listing.max_archive_size_bytes;
+    let collected = {
+        let storage = storage.clone();
+        web::block(move || {
+            collect_entries(
+                &storage, &directory, &root_name, max_size, is_limited,
+            )
+        })
+        .await
+    };
+
+    let entries = match collected {
+        Ok(Ok(Some(entries))) => entries,
+        Ok(Ok(None)) => {
+            return HttpResponse::PayloadTooLarge()
+                .body("Directory is too large to download as an archive");
+        }
+        Ok(Err(e)) if e.kind() == io::ErrorKind::NotFound => {
+            return HttpResponse::NotFound().body("Not found");
+        }
+        Ok(Err(e)) => {
+            error!("Failed to read directory for archive: {}", e);
+            return HttpResponse::InternalServerError()
+                .body("Failed to read directory");
+        }
+        Err(e) => {
+            error!("Failed to read directory for archive: {}", e);
+            return HttpResponse::InternalServerError()
+                .body("Failed to read directory");
+        }
+    };
+
+    let archive_name = format!(
+        "{}.{}",
+        entries
+            .first()
+            .map(|entry| entry.name.as_str())
+            .unwrap_or(&namespace_name),
+        format.extension()
+    );
+
+    let key = namespace.encryption_at_rest.key().cloned();
+    let body = streaming::blocking_body(move |writer| match format {
+        ArchiveFormat::Zip => {
+            write_zip(writer, &storage, &entries, key.as_ref())
+        }
+        ArchiveFormat::Tar => {
+            write_tar(writer, &storage, &entries, key.as_ref())?;
+            Ok(())
+        }
+        ArchiveFormat::TarGz => {
+            let writer = GzEncoder::new(writer, Compression::default());
+            write_tar(writer, &storage, &entries, key.as_ref())?.finish()?;
+            Ok(())
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type(ContentType(
+            format
+                .content_type()
+                .parse()
+                .expect("archive content type should be a valid mime"),
+        ))
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(archive_name)],
+        })
+        .streaming(body)
+}
+
+/// Walks the directory at `root` and returns everything that would be
+/// visible in its file listing, except files matching `skip`. Returns `None`
+/// as soon as the files add up to more than `max_size`.
+///
+/// Directories that have already been walked, like ones a symlink leads
+/// back to, are left out.
+fn collect_entries(
+    storage: &NamespaceStorage,
+    root: &str,
+    root_name: &str,
+    max_size: u64,
+    skip: impl Fn(&str) -> bool,
+) -> io::Result<Option<Vec<ArchiveEntry>>> {
+    if !matches!(storage.stat(root)?, Some(Stat::Directory)) {
+        return Err(io::Error::from(io::ErrorKind::NotFound));
+    }
+
+    // where each directory really is, for backends that have symlinks
+    let mut visited = HashSet::new();
+    let mut first_visit = |key: &str| match storage.local_path(key) {
+        Some(path) => {
+            fs::canonicalize(path).is_ok_and(|path| visited.insert(path))
+        }
+        None => true,
+    };
+    first_visit(root);
+
+    let mut entries = vec![ArchiveEntry {
+        key: root.to_string(),
+        name: root_name.to_string(),
+        is_dir: true,
+        size: 0,
+        modified: SystemTime::now(),
+    }];
+    let mut total_size: u64 = 0;
+    let mut pending = vec![(root.to_string(), root_name.to_string())];
+
+    while let Some((key, name)) = pending.pop() {
+        for entry in storage.list(&key)? {
+            if entry.name.starts_with('.') {
+                continue;
+            }
+
+            let entry_key = join_key(&key, &entry.name);
+            let entry_name = format!("{}/{}", name, entry.name);
+
+            if entry.is_dir {
+                if !first_visit(&entry_key) {
+                    continue;
+                }
+                pending.push((entry_key.clone(), entry_name.clone()));
+            } else if skip(&entry_key) {
+                continue;
+            } else {
+                total_size = total_size.saturating_add(entry.size);
+                if total_size > max_size {
+                    return Ok(None);
+                }
+            }
+
+            entries.push(ArchiveEntry {
+                key: entry_key,
+                name: entry_name,
+                is_dir: entry.is_dir,
+                size: entry.size,
+                modified: entry.modified,
+            });
+        }
+    }
+
+    Ok(Some(entries))
+}
+
+/// Opens a file for reading its contents, decrypting it if the namespace is
+/// encrypted at rest. Returns the reader along with the content length.
+fn open_entry(
+    storage: &NamespaceStorage,
+    entry: &ArchiveEntry,
+    key: Option<&EncryptionKey>,
+) -> io::Result<(Box<dyn Read>, u64)> {
+    match key {
+        Some(key) if encryption::is_encrypted(storage, &entry.key)? => {
+            let file =
+                EncryptedFile::open(storage, &entry.key, entry.size, key)?;
+            let length = file.plaintext_len();
+            Ok((Box::new(file), length))
+        }
+        _ => Ok((storage.get_range(&entry.key, 0, entry.size)?, entry.size)),
+    }
+}
+
+fn write_zip<W: Write>(
+    writer: W,
+    storage: &NamespaceStorage,
+    entries: &[ArchiveEntry],
+    key: Option<&EncryptionKey>,
+) -> io::Result<()> {
+    let mut zip = ZipWriter::new_stream(writer);
+
+    for entry in entries {
+        if entry.is_dir {
+            zip.add_directory(&entry.name, SimpleFileOptions::default())?;
+            continue;
+        }
+
+        let (mut file, length) = open_entry(storage, entry, key)?;
+        let mut options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .large_file(length >= u32::MAX as u64);
+
+        if let Ok(modified) =
+            DateTime::<Utc>::from(entry.modified).naive_utc().try_into()
+        {
+            options = options.last_modified_time(modified);
+        }
+
+        zip.start_file(&entry.name, options)?;
+        io::copy(&mut file, &mut zip)?;
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+/// Writes a tar of `entries` to `writer`, handing it back once it's done.
+fn write_tar<W: Write>(
+    writer: W,
+    storage: &NamespaceStorage,
+    entries: &[ArchiveEntry],
+    key: Option<&EncryptionKey>,
+) -> io::Result<W> {
+    let mut tar = tar::Builder::new(writer);
+
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_mtime(
+            entry
+                .modified
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+        );
+
+        if entry.is_dir {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            tar.append_data(&mut header, &entry.name, io::empty())?;
+            continue;
+        }
+
+        let (file, length) = open_entry(storage, entry, key)?;
+        header.set_mode(0o644);
+        header.set_size(length);
+        tar.append_data(&mut header, &entry.name, file)?;
+    }
+
+    tar.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::storage::FilesystemBackend;
+
+    fn storage() -> (TempDir, NamespaceStorage) {
+        let directory = tempfile::tempdir().unwrap();
+        fs::create_dir_all(directory.path().join("f/a/b")).unwrap();
+        fs::write(directory.path().join("f/one.txt"), [0; 10]).unwrap();
+        fs::write(directory.path().join("f/a/b/two.txt"), [0; 20]).unwrap();
+        fs::write(directory.path().join("f/.hidden"), [0; 40]).unwrap();
+
+        let storage = NamespaceStorage::new(
+            "f",
+            Arc::new(FilesystemBackend::new(directory.path().to_path_buf())),
+            "f",
+        );
+        (directory, storage)
+    }
+
+    fn names(entries: &[ArchiveEntry]) -> Vec<&str> {
+        let mut names: Vec<&str> =
+            entries.iter().map(|entry| entry.name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn collects_the_listing() {
+        let (_directory, storage) = storage();
+
+        let entries = collect_entries(&storage, "", "f", 30, |_| false)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            names(&entries),
+            ["f", "f/a", "f/a/b", "f/a/b/two.txt", "f/one.txt"]
+        );
+    }
+
+    #[test]
+    fn leaves_out_skipped_files() {
+        let (_directory, storage) = storage();
+
+        let entries =
+            collect_entries(&storage, "a", "a", 0, |key| key == "a/b/two.txt")
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(names(&entries), ["a", "a/b"]);
+    }
+
+    #[test]
+    fn stops_past_the_size_limit() {
+        let (_directory, storage) = storage();
+
+        assert!(
+            collect_entries(&storage, "", "f", 29, |_| false)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walks_symlinked_directories_once() {
+        let (directory, storage) = storage();
+        std::os::unix::fs::symlink(
+            directory.path().join("f"),
+            directory.path().join("f/a/b/loop"),
+        )
+        .unwrap();
+
+        let entries = collect_entries(&storage, "", "f", 30, |_| false)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            names(&entries),
+            ["f", "f/a", "f/a/b", "f/a/b/two.txt", "f/one.txt"]
+        );
+    }
+
+    #[test]
+    fn refuses_missing_directories() {
+        let (_directory, storage) = storage();
+
+        let error =
+            collect_entries(&storage, "one.txt", "one.txt", 30, |_| false)
+                .err()
+                .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,61 @@
+use crate::{
+    access,
+    config::Config,
+    render::{render_page, template::DecryptView},
+    storage::{Stat, Storage},
+};
+use actix_web::{
+    HttpRequest, HttpResponse,
+    web::{self, Data},
+};
+
+/// Serves the page that fetches an end-to-end encrypted upload and decrypts
+/// it in the browser, using the key carried in the URL fragment.
+pub async fn decrypt_view(
+    req: HttpRequest,
+    cfg: Data<Config>,
+    storage: Data<Storage>,
+    path: web::Path<(String, String)>,
+) -> HttpResponse {
+    let (namespace_name, file_name) = path.into_inner();
+
+    let (namespace, storage) = match (
+        cfg.namespaces.get(&namespace_name),
+        storage.namespace(&namespace_name),
+    ) {
+        (Some(ns), Some(storage)) if ns.end_to_end_encrypted => {
+            (ns, storage.clone())
+        }
+        _ => return HttpResponse::NotFound().body("Not found"),
+    };
+
+    // the page gives away that the file exists, so it's only shown to whoever
+    // could fetch it. signed links carry the signature of the ciphertext
+    let ciphertext_path =
+        req.path().strip_prefix("/_decrypt").unwrap_or_default();
+    if !access::signature_valid_for(&cfg, ciphertext_path, req.query_string())
+        && let Err(response) =
+            access::check_access(&cfg, &namespace_name, namespace, &req)
+    {
+        return response;
+    }
+
+    if file_name.starts_with('.') || file_name.contains(['/', '\\']) {
+        return HttpResponse::NotFound().body("Not found");
+    }
+
+    let stat = {
+        let file_name = file_name.clone();
+        web::block(move || storage.stat(&file_name)).await
+    };
+    if !matches!(stat, Ok(Ok(Some(Stat::File { .. })))) {
+        return HttpResponse::NotFound().body("Not found");
+    }
+
+    let ciphertext_href = format!("/{}/{}", namespace_name, file_name);
+
+    render_page(&DecryptView {
+        file_name: &file_name,
+        ciphertext_href: &ciphertext_href,
+    })
+}
@@ -0,0 +1,8 @@
+use crate::mirror::Mirror;
+use actix_web::{HttpResponse, web::Data};
+
+/// Reports how far behind the mirror is. It names failing files and backend
+/// errors, so it's only served through the admin API.
+pub async fn mirror_status(mirror: Data<Mirror>) -> HttpResponse {
+    HttpResponse::Ok().json(mirror.status())
+}
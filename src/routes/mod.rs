@@ -1,5 +1,22 @@
+mod archive;
+mod decrypt;
 mod index;
+mod mirror;
+mod payload;
+mod poster;
+mod sign;
+mod stored;
+pub mod streaming;
 mod upload;
+mod upload_page;
 
+pub use archive::{ArchiveFormat, download_archive};
+pub use decrypt::decrypt_view;
 pub use index::index_redirect;
+pub use mirror::mirror_status;
+pub use payload::ResponsePayload;
+pub use poster::poster;
+pub use sign::sign;
+pub use stored::{StoredNamespace, serve_stored};
 pub use upload::upload;
+pub use upload_page::upload_page;
@@ -0,0 +1,49 @@
+use serde::Serialize;
+use url::Url;
+
+use crate::media::MediaInfo;
+
+/// The response payload. Contains either a URL or an error message.
+#[derive(Serialize)]
+pub struct ResponsePayload {
+    #[serde(with = "url_serde")]
+    link: Option<Url>,
+    error: Option<String>,
+    /// Metadata of uploaded video and audio files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    media: Option<MediaInfo>,
+    #[serde(with = "url_serde", skip_serializing_if = "Option::is_none")]
+    poster: Option<Url>,
+}
+
+impl ResponsePayload {
+    pub fn of_link(url: Url) -> ResponsePayload {
+        Self {
+            link: Some(url),
+            error: None,
+            media: None,
+            poster: None,
+        }
+    }
+
+    pub fn of_error(error_message: String) -> ResponsePayload {
+        Self {
+            link: None,
+            error: Some(error_message),
+            media: None,
+            poster: None,
+        }
+    }
+
+    /// Adds the metadata of an uploaded video or audio file, along with a
+    /// link to its poster frame if it has one.
+    pub fn with_media(
+        mut self,
+        media: MediaInfo,
+        poster: Option<Url>,
+    ) -> ResponsePayload {
+        self.media = Some(media);
+        self.poster = poster;
+        self
+    }
+}
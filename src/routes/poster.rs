@@ -0,0 +1,66 @@
+use actix_files::NamedFile;
+use actix_web::{
+    HttpRequest, HttpResponse, Responder,
+    http::header::{CacheControl, CacheDirective},
+    web::{self, Data},
+};
+
+use crate::{
+    access,
+    config::Config,
+    media::MediaLibrary,
+    storage::{self, Storage},
+};
+
+/// Serves the poster frame grabbed from a video when it was uploaded.
+///
+/// Access is checked against the video's namespace, so a poster is only
+/// visible to whoever could watch the video.
+pub async fn poster(
+    req: HttpRequest,
+    cfg: Data<Config>,
+    storage: Data<Storage>,
+    media: Data<MediaLibrary>,
+    path: web::Path<(String, String)>,
+) -> HttpResponse {
+    let (namespace_name, file) = path.into_inner();
+
+    let (namespace, storage) = match (
+        cfg.namespaces.get(&namespace_name),
+        storage.namespace(&namespace_name),
+    ) {
+        (Some(ns), Some(storage)) => (ns, storage),
+        _ => return HttpResponse::NotFound().body("Not found"),
+    };
+
+    if let Err(response) =
+        access::check_access(&cfg, &namespace_name, namespace, &req)
+    {
+        return response;
+    }
+
+    let poster = storage::sanitize_key(&file)
+        .and_then(|file| media.poster(&storage.backend_key(&file)));
+
+    // shared caches mustn't hand out posters that are behind a password or
+    // a signature
+    let visibility =
+        if namespace.requires_viewer_auth() || namespace.require_signed_urls {
+            CacheDirective::Private
+        } else {
+            CacheDirective::Public
+        };
+
+    match poster.map(NamedFile::open) {
+        Some(Ok(poster)) => poster
+            .use_last_modified(true)
+            .customize()
+            .insert_header(CacheControl(vec![
+                visibility,
+                CacheDirective::MaxAge(86400),
+            ]))
+            .respond_to(&req)
+            .map_into_boxed_body(),
+        _ => HttpResponse::NotFound().body("Not found"),
+    }
+}
@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use crate::{
+    access,
+    config::{Config, NamespaceDefinition},
+    storage::{Stat, Storage},
+};
+use actix_web::{
+    HttpResponse,
+    web::{self, Data, Form},
+};
+use serde::Deserialize;
+
+use super::ResponsePayload;
+
+/// Data we expect to receive when minting a signed URL.
+#[derive(Debug, Deserialize)]
+pub struct SignData {
+    namespace: String,
+    auth_key: String,
+    /// Path of the file, relative to the namespace.
+    path: String,
+    /// Requested lifetime in seconds, capped at `signing.max_ttl_seconds`.
+    expires_in: Option<u64>,
+}
+
+/// Issues a signed, expiring URL for a file in a namespace the caller can
+/// upload to.
+pub async fn sign(
+    cfg: Data<Config>,
+    storage: Data<Storage>,
+    Form(form): Form<SignData>,
+) -> HttpResponse {
+    if NamespaceDefinition::auth(
+        &cfg.namespaces,
+        &form.namespace,
+        &form.auth_key,
+    )
+    .is_none()
+    {
+        return HttpResponse::Unauthorized().json(ResponsePayload::of_error(
+            "Failed to authenticate".to_string(),
+        ));
+    }
+
+    let segments: Vec<&str> =
+        form.path.split('/').filter(|s| !s.is_empty()).collect();
+
+    if segments.is_empty()
+        || segments
+            .iter()
+            .any(|segment| segment.starts_with('.') || segment.contains('\\'))
+    {
+        return HttpResponse::BadRequest()
+            .json(ResponsePayload::of_error("Invalid file path".to_string()));
+    }
+
+    let file_key = segments.join("/");
+    let stat = match storage.namespace(&form.namespace) {
+        Some(storage) => {
+            let storage = storage.clone();
+            web::block(move || storage.stat(&file_key)).await
+        }
+        None => Ok(Ok(None)),
+    };
+
+    if !matches!(stat, Ok(Ok(Some(Stat::File { .. })))) {
+        return HttpResponse::NotFound()
+            .json(ResponsePayload::of_error("File not found".to_string()));
+    }
+
+    let link = match cfg
+        .web_server
+        .listen_url
+        .join(format!("{}/", form.namespace).as_str())
+        .and_then(|url| url.join(&segments.join("/")))
+    {
+        Ok(link) => link,
+        Err(_) => {
+            return HttpResponse::BadRequest().json(ResponsePayload::of_error(
+                "Invalid file path".to_string(),
+            ));
+        }
+    };
+
+    let ttl = form
+        .expires_in
+        .unwrap_or(cfg.signing.default_ttl_seconds)
+        .min(cfg.signing.max_ttl_seconds);
+
+    HttpResponse::Ok().json(ResponsePayload::of_link(access::sign_url(
+        &cfg,
+        &link,
+        Duration::from_secs(ttl),
+    )))
+}
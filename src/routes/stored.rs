@@ -0,0 +1,214 @@
+use std::io::{self, Read};
+
+use crate::{
+    config::Config,
+    encryption::{self, EncryptedFile},
+    render::{cached_listing, render_listing, sorted_entries},
+    storage::{self, Stat, Storage},
+};
+use actix_files::HttpRange;
+use actix_web::{
+    HttpRequest, HttpResponse,
+    body::SizedStream,
+    http::{
+        Method, StatusCode,
+        header::{self, ContentType, HttpDate, LastModified},
+    },
+    web::Bytes,
+    web::{self, Data},
+};
+use log::error;
+use percent_encoding::percent_decode_str;
+
+use super::streaming;
+
+/// The namespace a stored file service is mounted for.
+pub struct StoredNamespace(pub String);
+
+/// Serves files from a namespace through its storage backend, decrypting
+/// them on the fly if the namespace is encrypted at rest. Stands in for
+/// [`actix_files::Files`] wherever that can't read the files itself,
+/// including range requests and the fancy directory listing.
+pub async fn serve_stored(
+    req: HttpRequest,
+    cfg: Data<Config>,
+    storage: Data<Storage>,
+    served: Data<StoredNamespace>,
+) -> HttpResponse {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return HttpResponse::MethodNotAllowed()
+            .insert_header((header::ALLOW, "GET, HEAD"))
+            .finish();
+    }
+
+    let (namespace, storage) =
+        match (cfg.namespaces.get(&served.0), storage.namespace(&served.0)) {
+            (Some(namespace), Some(storage)) => (namespace, storage.clone()),
+            _ => return HttpResponse::NotFound().body("Not found"),
+        };
+
+    let tail = percent_decode_str(req.match_info().unprocessed())
+        .decode_utf8_lossy()
+        .into_owned();
+    let key = match storage::sanitize_key(&tail) {
+        Some(key) => key,
+        None => return HttpResponse::NotFound().body("Not found"),
+    };
+
+    let stat = {
+        let storage = storage.clone();
+        let key = key.clone();
+        web::block(move || storage.stat(&key)).await
+    };
+
+    let (size, modified) = match stat {
+        Ok(Ok(Some(Stat::File { size, modified }))) => (size, modified),
+        Ok(Ok(Some(Stat::Directory))) if namespace.file_listing.show => {
+            if let Some(cached) = cached_listing(&req, &storage, &key) {
+                return cached;
+            }
+
+            let encrypted = namespace.encryption_at_rest.key().is_some();
+            let listed = {
+                let storage = storage.clone();
+                let key = key.clone();
+                web::block(move || sorted_entries(&storage, &key, encrypted))
+                    .await
+            };
+
+            return match listed {
+                Ok(Ok(entries)) => {
+                    render_listing(&req, &cfg, &storage, &key, entries)
+                }
+                Ok(Err(e)) => {
+                    error!("Failed to list directory: {}", e);
+                    HttpResponse::InternalServerError()
+                        .body("Failed to list directory")
+                }
+                Err(e) => {
+                    error!("Failed to list directory: {}", e);
+                    HttpResponse::InternalServerError()
+                        .body("Failed to list directory")
+                }
+            };
+        }
+        Ok(Ok(_)) => return HttpResponse::NotFound().body("Not found"),
+        Ok(Err(e)) => {
+            error!("Failed to look up {}: {}", key, e);
+            return HttpResponse::InternalServerError()
+                .body("Failed to read file");
+        }
+        Err(e) => {
+            error!("Failed to look up {}: {}", key, e);
+            return HttpResponse::InternalServerError()
+                .body("Failed to read file");
+        }
+    };
+
+    let encryption_key = namespace.encryption_at_rest.key().cloned();
+    let encrypted = match encryption_key {
+        Some(_) => {
+            let storage = storage.clone();
+            let key = key.clone();
+            matches!(
+                web::block(move || encryption::is_encrypted(&storage, &key))
+                    .await,
+                Ok(Ok(true))
+            )
+        }
+        None => false,
+    };
+    let content_len = if encrypted {
+        encryption::plaintext_len(size)
+    } else {
+        size
+    };
+
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type(ContentType(
+            mime_guess::from_path(&key).first_or_octet_stream(),
+        ))
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header(LastModified(HttpDate::from(modified)));
+
+    let (start, length) = match req
+        .headers()
+        .get(header::RANGE)
+        .and_then(|range| range.to_str().ok())
+    {
+        Some(range) => match HttpRange::parse(range, content_len) {
+            Ok(ranges) if !ranges.is_empty() => {
+                let range = ranges[0];
+                response.status(StatusCode::PARTIAL_CONTENT);
+                response.insert_header((
+                    header::CONTENT_RANGE,
+                    format!(
+                        "bytes {}-{}/{}",
+                        range.start,
+                        range.start + range.length - 1,
+                        content_len
+                    ),
+                ));
+                (range.start, range.length)
+            }
+            _ => {
+                return HttpResponse::RangeNotSatisfiable()
+                    .insert_header((
+                        header::CONTENT_RANGE,
+                        format!("bytes */{}", content_len),
+                    ))
+                    .finish();
+            }
+        },
+        None => (0, content_len),
+    };
+
+    if req.method() == Method::HEAD {
+        return response.body(SizedStream::new(
+            length,
+            futures_util::stream::empty::<io::Result<Bytes>>(),
+        ));
+    }
+
+    // open the file before answering, so it's already being read if it gets
+    // deleted after its last download
+    let opened = web::block(move || {
+        let reader: Box<dyn Read + Send> = match encryption_key {
+            Some(encryption_key) if encrypted => {
+                let mut file =
+                    EncryptedFile::open(&storage, &key, size, &encryption_key)?;
+                file.seek_to(start)?;
+                Box::new(file.take(length))
+            }
+            _ => storage.get_range(&key, start, length)?,
+        };
+
+        Ok::<_, io::Error>(reader)
+    })
+    .await;
+
+    let mut reader = match opened {
+        Ok(Ok(reader)) => reader,
+        Ok(Err(e)) if e.kind() == io::ErrorKind::NotFound => {
+            return HttpResponse::NotFound().body("Not found");
+        }
+        Ok(Err(e)) => {
+            error!("Failed to open stored file: {}", e);
+            return HttpResponse::InternalServerError()
+                .body("Failed to read file");
+        }
+        Err(e) => {
+            error!("Failed to open stored file: {}", e);
+            return HttpResponse::InternalServerError()
+                .body("Failed to read file");
+        }
+    };
+
+    let body = streaming::blocking_body(move |writer| {
+        io::copy(&mut reader, writer)?;
+        Ok(())
+    });
+
+    response.body(SizedStream::new(length, body))
+}
@@ -0,0 +1,74 @@
+use std::{
+    io::{self, ErrorKind, Write},
+    mem,
+};
+
+use actix_web::{rt::task, web::Bytes};
+use futures_util::{Stream, stream};
+use log::error;
+use tokio::sync::mpsc;
+
+/// Size of the chunks handed to the response body by [`ChannelWriter`].
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Runs `write` on the blocking thread pool and returns everything it writes
+/// as a response body stream, without buffering it all in memory.
+pub fn blocking_body<F>(
+    write: F,
+) -> impl Stream<Item = io::Result<Bytes>> + 'static
+where
+    F: FnOnce(&mut ChannelWriter) -> io::Result<()> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel::<io::Result<Bytes>>(4);
+
+    task::spawn_blocking(move || {
+        let mut writer = ChannelWriter {
+            sender: sender.clone(),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        };
+
+        if let Err(e) = write(&mut writer).and_then(|_| writer.flush()) {
+            // a broken pipe just means the client went away
+            if e.kind() != ErrorKind::BrokenPipe {
+                error!("Failed to write response body: {}", e);
+                let _ = sender.blocking_send(Err(e));
+            }
+        }
+    });
+
+    stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    })
+}
+
+/// Forwards everything written to it to a response body, in
+/// [`CHUNK_SIZE`]-sized pieces.
+pub struct ChannelWriter {
+    sender: mpsc::Sender<io::Result<Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let chunk = Bytes::from(mem::replace(
+            &mut self.buffer,
+            Vec::with_capacity(CHUNK_SIZE),
+        ));
+
+        self.sender.blocking_send(Ok(chunk)).map_err(|_| {
+            io::Error::new(ErrorKind::BrokenPipe, "response body was dropped")
+        })
+    }
+}
@@ -1,50 +1,46 @@
-use std::path::Path;
+use std::{io, path::Path, time::Duration};
 
-use crate::config::{Config, NamespaceDefinition};
+use crate::{
+    access,
+    config::{Config, NamespaceDefinition},
+    downloads::DownloadLimits,
+    encryption, images,
+    media::MediaLibrary,
+    mirror::Mirror,
+    render::ListingCache,
+    storage::Storage,
+};
 use actix_multipart::form::{MultipartForm, tempfile::TempFile, text::Text};
-use actix_web::{HttpResponse, web::Data};
+use actix_web::{
+    HttpResponse,
+    web::{self, Data},
+};
 use log::error;
-use serde::Serialize;
 use url::Url;
 
+use super::ResponsePayload;
+
 /// Data we expect to receive during uploads.
 #[derive(Debug, MultipartForm)]
 pub struct UploadData {
     file: TempFile,
     namespace: Text<String>,
     auth_key: Text<String>,
-}
-
-/// The response payload. Contains either a URL or an error message.
-#[derive(Serialize)]
-pub struct ResponsePayload {
-    #[serde(with = "url_serde")]
-    link: Option<Url>,
-    error: Option<String>,
-}
-
-impl ResponsePayload {
-    pub fn of_link(url: Url) -> ResponsePayload {
-        Self {
-            link: Some(url),
-            error: None,
-        }
-    }
-
-    pub fn of_error(error_message: String) -> ResponsePayload {
-        Self {
-            link: None,
-            error: Some(error_message),
-        }
-    }
+    /// Delete the file after this many downloads.
+    max_downloads: Option<Text<u32>>,
 }
 
 /// The file upload endpoint.
 pub async fn upload(
     cfg: Data<Config>,
+    storage: Data<Storage>,
+    listing_cache: Data<ListingCache>,
+    download_limits: Data<DownloadLimits>,
+    mirror: Data<Mirror>,
+    media: Data<MediaLibrary>,
     MultipartForm(form): MultipartForm<UploadData>,
 ) -> HttpResponse {
-    let input_namespace = (&form.namespace.0).replace("..", "");
+    let input_namespace = form.namespace.0.replace("..", "");
     let input_auth_key = &form.auth_key.0;
 
     // get the namespace definition and also authenticate
@@ -61,67 +57,249 @@ pub async fn upload(
         }
     };
 
-    let file_path =
-        namespace.create_random_file_name(&cfg, get_file_extension(&form.file));
-
-    if file_path.is_err() {
-        error!(
-            "Failed to create path for uploaded file: {:?}",
-            file_path.err()
-        );
-        return HttpResponse::InternalServerError().json(
-            ResponsePayload::of_error(
-                "Failed to create path for uploaded file".to_string(),
-            ),
-        );
-    }
+    let max_downloads = match form.max_downloads.as_ref().map(|m| m.0) {
+        None => None,
+        Some(0) => {
+            return HttpResponse::BadRequest().json(ResponsePayload::of_error(
+                "max_downloads must be at least 1".to_string(),
+            ));
+        }
+        Some(_) if namespace.max_downloads_cap == 0 => {
+            return HttpResponse::BadRequest().json(ResponsePayload::of_error(
+                "This namespace doesn't allow limited downloads".to_string(),
+            ));
+        }
+        Some(max) => Some(max.min(namespace.max_downloads_cap)),
+    };
 
-    let file_path = file_path.unwrap();
+    let original_extension = get_file_extension(&form.file).to_string();
+    let upload = form.file.file.into_temp_path();
 
-    let namespace_path = namespace.get_path(&cfg);
-    if !file_path.starts_with(&namespace_path) {
-        error!(
-            "Path traversal detected. namespace: {}, file_path: {:?}",
-            &form.namespace.0, file_path
-        );
-        return HttpResponse::BadRequest()
-            .json(ResponsePayload::of_error("Invalid file path".to_string()));
-    }
+    // ciphertext can't be processed, and isn't worth trying to
+    let (upload, processed_extension) = if namespace.image_processing.enabled
+        && !namespace.end_to_end_encrypted
+    {
+        let config = namespace.image_processing.clone();
+        let temp_path = cfg.get_temp_path();
+        let processed = web::block(move || {
+            Ok(match images::process(&config, &upload, &temp_path)? {
+                Some(processed) => (processed.file, processed.extension),
+                None => (upload, None),
+            })
+        })
+        .await
+        .map_err(io::Error::other)
+        .and_then(|processed| processed);
+
+        match processed {
+            Ok(processed) => processed,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                return HttpResponse::BadRequest().json(
+                    ResponsePayload::of_error(format!(
+                        "Failed to process image: {}",
+                        e
+                    )),
+                );
+            }
+            Err(e) => {
+                error!("Failed to process uploaded image: {}", e);
+                return HttpResponse::InternalServerError().json(
+                    ResponsePayload::of_error(
+                        "Failed to process uploaded image".to_string(),
+                    ),
+                );
+            }
+        }
+    } else {
+        (upload, None)
+    };
+
+    // the original extension would leak what's inside the ciphertext
+    let file_extension = if namespace.end_to_end_encrypted {
+        "enc"
+    } else {
+        processed_extension.unwrap_or(&original_extension)
+    };
+
+    let storage = match storage.namespace(&input_namespace) {
+        Some(storage) => storage.clone(),
+        None => {
+            return HttpResponse::InternalServerError().json(
+                ResponsePayload::of_error(
+                    "Namespace has no storage".to_string(),
+                ),
+            );
+        }
+    };
+
+    let file_name = {
+        let namespace = namespace.clone();
+        let storage = storage.clone();
+        let file_extension = file_extension.to_string();
+        web::block(move || {
+            namespace.create_random_file_name(&storage, &file_extension)
+        })
+        .await
+        .map_err(io::Error::other)
+        .and_then(|file_name| file_name)
+    };
+
+    let file_name = match file_name {
+        Ok(file_name) => file_name,
+        Err(e) => {
+            error!("Failed to create path for uploaded file: {:?}", e);
+            return HttpResponse::InternalServerError().json(
+                ResponsePayload::of_error(
+                    "Failed to create path for uploaded file".to_string(),
+                ),
+            );
+        }
+    };
+
+    // a record left by an earlier file of the same name mustn't apply to
+    // this one
+    download_limits.remove(&storage.backend_key(&file_name));
+
+    let encryption_key = namespace.encryption_at_rest.key().cloned();
+    let temp_path = cfg.get_temp_path();
+    let persist = {
+        let storage = storage.clone();
+        let file_name = file_name.clone();
+        let file_extension = file_extension.to_string();
+        let media = media.clone();
+        web::block(move || {
+            // read while the plaintext is at hand. end-to-end encrypted
+            // files go by `.enc`, so they're never probed. a poster would be
+            // plaintext on disk, so files encrypted at rest don't get one
+            let media_info = media.analyse(
+                &storage.backend_key(&file_name),
+                &upload,
+                &file_extension,
+                encryption_key.is_none(),
+            );
+
+            match encryption_key {
+                // the plaintext is dropped along with the temp file once
+                // encrypted
+                Some(key) => {
+                    let encrypted = tempfile::NamedTempFile::new_in(temp_path)?;
+                    encryption::encrypt_file(&key, &upload, encrypted.path())?;
+                    storage.put_file(&file_name, &encrypted.into_temp_path())?
+                }
+                None => storage.put_file(&file_name, &upload)?,
+            }
+
+            Ok(media_info)
+        })
+        .await
+        .map_err(io::Error::other)
+        .and_then(|persisted| persisted)
+    };
 
-    let persist = form.file.file.persist(&file_path);
-
-    if persist.is_err() {
-        error!(
-            "Failed to parsist uploaded file: {}",
-            persist.err().unwrap()
-        );
-        return HttpResponse::InternalServerError().json(
-            ResponsePayload::of_error(
-                "Failed to persist uploaded file".to_string(),
-            ),
-        );
+    let media_info = match persist {
+        Ok(media_info) => media_info,
+        Err(e) => {
+            error!("Failed to persist uploaded file: {}", e);
+            media.remove(&storage.backend_key(&file_name));
+            // give back the name claimed for it
+            let _ = web::block(move || storage.delete(&file_name)).await;
+            return HttpResponse::InternalServerError().json(
+                ResponsePayload::of_error(
+                    "Failed to persist uploaded file".to_string(),
+                ),
+            );
+        }
+    };
+
+    if let Some(max_downloads) = max_downloads {
+        let limited = download_limits
+            .insert(storage.backend_key(&file_name), max_downloads);
+
+        if let Err(e) = limited {
+            error!("Failed to record download limit: {}", e);
+            // never leave a file meant to self-destruct lying around forever
+            media.remove(&storage.backend_key(&file_name));
+            let _ = web::block(move || storage.delete(&file_name)).await;
+            return HttpResponse::InternalServerError().json(
+                ResponsePayload::of_error(
+                    "Failed to record download limit".to_string(),
+                ),
+            );
+        }
     }
 
-    HttpResponse::Ok().json(ResponsePayload::of_link(
-        cfg.web_server
+    listing_cache.invalidate_path(&storage.backend_key(&file_name));
+    mirror.put(&input_namespace, &file_name);
+
+    let link = cfg
+        .web_server
+        .listen_url
+        .join(format!("{}/", input_namespace).as_str())
+        .expect("should be able to join with input_namespace")
+        .join(&file_name)
+        .expect("should be able to join with file stem");
+
+    let sign = |link: Url| {
+        if namespace.require_signed_urls {
+            access::sign_url(
+                &cfg,
+                &link,
+                Duration::from_secs(cfg.signing.default_ttl_seconds),
+            )
+        } else {
+            link
+        }
+    };
+    let link = sign(link);
+
+    // end-to-end encrypted files are linked through the decryption page,
+    // which forwards any signature to the ciphertext. the client appends its
+    // key after the `#`
+    let link = if namespace.end_to_end_encrypted {
+        let mut decrypt_link = cfg
+            .web_server
             .listen_url
-            .join(format!("{}/", input_namespace).as_str())
+            .join(format!("_decrypt/{}/", input_namespace).as_str())
             .expect("should be able to join with input_namespace")
-            .join(
-                file_path
-                    .file_name()
-                    .expect("should have a file name")
-                    .to_str()
-                    .expect("should be able to convert OsStr to str"),
-            )
-            .expect("should be able to join with file stem"),
-    ))
+            .join(&file_name)
+            .expect("should be able to join with file stem");
+        decrypt_link.set_query(link.query());
+        decrypt_link.set_fragment(Some(""));
+        decrypt_link
+    } else {
+        link
+    };
+
+    let payload = ResponsePayload::of_link(link);
+    let payload = match media_info {
+        Some(media_info) => {
+            let poster = media_info.poster.then(|| {
+                sign(
+                    cfg.web_server
+                        .listen_url
+                        .join(format!("_poster/{}/", input_namespace).as_str())
+                        .expect("should be able to join with input_namespace")
+                        .join(&file_name)
+                        .expect("should be able to join with file stem"),
+                )
+            });
+            payload.with_media(media_info, poster)
+        }
+        None => payload,
+    };
+
+    HttpResponse::Ok().json(payload)
 }
 
 /// Extracts file extension from a [`TempFile`].
 fn get_file_extension(file: &TempFile) -> &str {
     let file_name = file.file_name.as_deref().unwrap_or("unnamed");
 
+    // compressed tarballs keep both, so they can still be browsed as archives
+    if file_name.to_ascii_lowercase().ends_with(".tar.gz") {
+        return &file_name[file_name.len() - "tar.gz".len()..];
+    }
+
     Path::new(file_name)
         .extension()
         .and_then(|os| os.to_str())
@@ -0,0 +1,38 @@
+use actix_web::{
+    HttpResponse,
+    web::{self, Data},
+};
+use bytesize::ByteSize;
+
+use crate::{
+    config::Config,
+    render::{render_page, template::UploadPage},
+};
+
+/// Serves the page for uploading to a namespace from a browser, for those
+/// without a client like ShareX. It authenticates with the namespace's key,
+/// which the page asks for and keeps in the browser's local storage.
+pub async fn upload_page(
+    cfg: Data<Config>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let namespace_name = path.into_inner();
+
+    let namespace = match cfg.namespaces.get(&namespace_name) {
+        Some(namespace) if namespace.upload_page => namespace,
+        _ => return HttpResponse::NotFound().body("Not found"),
+    };
+
+    let max_file_size_bytes = cfg.storage.max_file_size_bytes;
+
+    render_page(&UploadPage {
+        namespace: &namespace_name,
+        max_file_size: &ByteSize::b(max_file_size_bytes as u64)
+            .display()
+            .iec()
+            .to_string(),
+        max_file_size_bytes,
+        max_downloads_cap: namespace.max_downloads_cap,
+        end_to_end_encrypted: namespace.end_to_end_encrypted,
+    })
+}
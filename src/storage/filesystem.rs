@@ -0,0 +1,145 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use log::info;
+
+use super::{Entry, Stat, StorageBackend};
+
+/// Stores files in a directory on the local filesystem.
+pub struct FilesystemBackend {
+    root: PathBuf,
+}
+
+impl FilesystemBackend {
+    pub fn new(root: PathBuf) -> FilesystemBackend {
+        Self { root }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        key.split('/')
+            .filter(|segment| !segment.is_empty())
+            .fold(self.root.clone(), |path, segment| path.join(segment))
+    }
+
+    fn create_parent(path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) => fs::create_dir_all(parent),
+            None => Ok(()),
+        }
+    }
+}
+
+impl StorageBackend for FilesystemBackend {
+    fn put(
+        &self,
+        key: &str,
+        reader: &mut dyn Read,
+        length: u64,
+    ) -> io::Result<()> {
+        let path = self.path(key);
+        Self::create_parent(&path)?;
+
+        let mut file = File::create(&path)?;
+        io::copy(&mut reader.take(length), &mut file)?;
+        file.sync_all()
+    }
+
+    fn put_file(&self, key: &str, source: &Path) -> io::Result<()> {
+        let path = self.path(key);
+        Self::create_parent(&path)?;
+
+        // the temp directory lives inside the uploads directory, so this is
+        // normally a rename; copy when it's been mounted somewhere else
+        match fs::rename(source, &path) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                fs::copy(source, &path)?;
+                fs::remove_file(source)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn reserve(&self, key: &str) -> io::Result<bool> {
+        let path = self.path(key);
+        Self::create_parent(&path)?;
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_range(
+        &self,
+        key: &str,
+        start: u64,
+        length: u64,
+    ) -> io::Result<Box<dyn Read + Send>> {
+        let mut file = File::open(self.path(key))?;
+        file.seek(SeekFrom::Start(start))?;
+        Ok(Box::new(file.take(length)))
+    }
+
+    fn stat(&self, key: &str) -> io::Result<Option<Stat>> {
+        let metadata = match fs::metadata(self.path(key)) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        if metadata.is_dir() {
+            return Ok(Some(Stat::Directory));
+        }
+
+        Ok(Some(Stat::File {
+            size: metadata.len(),
+            modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+        }))
+    }
+
+    fn list(&self, key: &str) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+
+        for entry in fs::read_dir(self.path(key))? {
+            let entry = entry?;
+            // follows symlinks, like the listing always has
+            let metadata = match fs::metadata(entry.path()) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+
+            entries.push(Entry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            });
+        }
+
+        Ok(entries)
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        fs::remove_file(self.path(key))
+    }
+
+    fn initialise(&self, key: &str) -> io::Result<()> {
+        let path = self.path(key);
+        if !path.is_dir() {
+            fs::create_dir_all(&path)?;
+            info!("Created namespace directory: '{}'", path.display());
+        }
+
+        Ok(())
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.path(key))
+    }
+}
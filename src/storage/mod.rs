@@ -0,0 +1,267 @@
+mod filesystem;
+mod s3;
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use log::info;
+
+use crate::config::{Config, StorageBackendConfig};
+
+pub use filesystem::FilesystemBackend;
+pub use s3::S3Backend;
+
+/// What a key in a backend points at.
+#[derive(Debug, Clone, Copy)]
+pub enum Stat {
+    File { size: u64, modified: SystemTime },
+    Directory,
+}
+
+/// An immediate child of a directory, as returned by
+/// [`StorageBackend::list`].
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Somewhere files can be stored.
+///
+/// Keys are `/`-separated paths without a leading slash, and `""` is the root.
+/// Directories are implicit: a key is a directory if anything is stored below
+/// it. All methods block, so call them from the blocking thread pool.
+pub trait StorageBackend: Send + Sync {
+    /// Stores `length` bytes from `reader` at `key`, replacing anything there.
+    fn put(
+        &self,
+        key: &str,
+        reader: &mut dyn Read,
+        length: u64,
+    ) -> io::Result<()>;
+
+    /// Moves the local file at `path` to `key`.
+    fn put_file(&self, key: &str, path: &Path) -> io::Result<()> {
+        let mut file = File::open(path)?;
+        let length = file.metadata()?.len();
+        self.put(key, &mut file, length)
+    }
+
+    /// Claims `key` for a new file, returning `false` if it's already taken.
+    fn reserve(&self, key: &str) -> io::Result<bool> {
+        Ok(self.stat(key)?.is_none())
+    }
+
+    /// Reads `length` bytes of the file at `key`, starting at `start`.
+    fn get_range(
+        &self,
+        key: &str,
+        start: u64,
+        length: u64,
+    ) -> io::Result<Box<dyn Read + Send>>;
+
+    /// Looks up `key`, returning `None` if nothing is stored there.
+    fn stat(&self, key: &str) -> io::Result<Option<Stat>>;
+
+    /// Lists the immediate children of the directory at `key`.
+    fn list(&self, key: &str) -> io::Result<Vec<Entry>>;
+
+    fn delete(&self, key: &str) -> io::Result<()>;
+
+    /// Gets the directory at `key` ready for use, failing if the backend
+    /// can't be reached.
+    fn initialise(&self, key: &str) -> io::Result<()>;
+
+    /// Where `key` lives on the local filesystem, for backends that have one.
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Joins two keys.
+pub fn join_key(base: &str, key: &str) -> String {
+    match (base.is_empty(), key.is_empty()) {
+        (true, _) => key.to_string(),
+        (_, true) => base.to_string(),
+        _ => format!("{}/{}", base.trim_end_matches('/'), key),
+    }
+}
+
+/// Turns a request path into a key, refusing anything that would escape the
+/// namespace or that points at a hidden file.
+pub fn sanitize_key(path: &str) -> Option<String> {
+    let mut segments = Vec::new();
+
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        if segment.starts_with('.') || segment.contains('\\') {
+            return None;
+        }
+        segments.push(segment);
+    }
+
+    Some(segments.join("/"))
+}
+
+/// A namespace's view of its backend: every key is relative to the
+/// namespace's directory.
+#[derive(Clone)]
+pub struct NamespaceStorage {
+    name: String,
+    backend: Arc<dyn StorageBackend>,
+    prefix: String,
+}
+
+impl NamespaceStorage {
+    /// The storage of namespace `name`, kept under `prefix` in `backend`.
+    pub fn new(
+        name: &str,
+        backend: Arc<dyn StorageBackend>,
+        prefix: &str,
+    ) -> NamespaceStorage {
+        Self {
+            name: name.to_string(),
+            backend,
+            prefix: prefix.to_string(),
+        }
+    }
+
+    /// The name of the namespace this storage belongs to.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The key `key` has in the backend.
+    pub fn backend_key(&self, key: &str) -> String {
+        join_key(&self.prefix, key)
+    }
+
+    pub fn put_file(&self, key: &str, path: &Path) -> io::Result<()> {
+        self.backend.put_file(&self.backend_key(key), path)
+    }
+
+    pub fn reserve(&self, key: &str) -> io::Result<bool> {
+        self.backend.reserve(&self.backend_key(key))
+    }
+
+    pub fn get_range(
+        &self,
+        key: &str,
+        start: u64,
+        length: u64,
+    ) -> io::Result<Box<dyn Read + Send>> {
+        self.backend
+            .get_range(&self.backend_key(key), start, length)
+    }
+
+    pub fn stat(&self, key: &str) -> io::Result<Option<Stat>> {
+        self.backend.stat(&self.backend_key(key))
+    }
+
+    pub fn list(&self, key: &str) -> io::Result<Vec<Entry>> {
+        self.backend.list(&self.backend_key(key))
+    }
+
+    pub fn delete(&self, key: &str) -> io::Result<()> {
+        self.backend.delete(&self.backend_key(key))
+    }
+
+    pub fn local_path(&self, key: &str) -> Option<PathBuf> {
+        self.backend.local_path(&self.backend_key(key))
+    }
+
+    pub fn initialise(&self) -> io::Result<()> {
+        self.backend.initialise(&self.prefix)
+    }
+}
+
+/// The storage of every namespace.
+pub struct Storage {
+    namespaces: HashMap<String, NamespaceStorage>,
+    default_namespace: Option<String>,
+}
+
+impl Storage {
+    /// Sets up the backend of every namespace, and gives each namespace its
+    /// directory in it. Namespaces without a backend of their own share
+    /// `storage.backend`. Nothing is checked until
+    /// [`Config::create_uploads_directory`] initialises them.
+    pub fn new(config: &Config) -> Storage {
+        let shared = backend_for(config, &config.storage.backend);
+
+        let namespaces = config
+            .namespaces
+            .iter()
+            .map(|(name, namespace)| {
+                let backend = match &namespace.backend {
+                    Some(backend) => backend_for(config, backend),
+                    None => Arc::clone(&shared),
+                };
+
+                (
+                    name.clone(),
+                    NamespaceStorage::new(name, backend, &namespace.path),
+                )
+            })
+            .collect();
+
+        Self {
+            namespaces,
+            default_namespace: config
+                .default_namespace()
+                .map(|(name, _)| name.clone()),
+        }
+    }
+
+    pub fn namespace(&self, name: &str) -> Option<&NamespaceStorage> {
+        self.namespaces.get(name)
+    }
+
+    /// The storage of the namespace served at the root.
+    pub fn default_namespace(&self) -> Option<&NamespaceStorage> {
+        self.default_namespace
+            .as_ref()
+            .and_then(|name| self.namespaces.get(name))
+    }
+
+    /// The root directories of every filesystem backend, each listed once.
+    pub fn local_roots(&self) -> Vec<PathBuf> {
+        let mut roots: Vec<PathBuf> = self
+            .namespaces
+            .values()
+            .filter_map(|storage| storage.backend.local_path(""))
+            .collect();
+        roots.sort();
+        roots.dedup();
+        roots
+    }
+}
+
+/// Builds the backend described by `backend`.
+pub fn backend_for(
+    config: &Config,
+    backend: &StorageBackendConfig,
+) -> Arc<dyn StorageBackend> {
+    match backend {
+        StorageBackendConfig::Filesystem(filesystem) => {
+            Arc::new(FilesystemBackend::new(
+                filesystem
+                    .directory
+                    .as_ref()
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| config.get_uploads_path()),
+            ))
+        }
+        StorageBackendConfig::S3(s3) => {
+            info!("Storing files in S3 bucket '{}'", s3.bucket);
+            Arc::new(S3Backend::new(s3.as_ref().clone()))
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Turns text with ANSI escape codes into numbered HTML lines.
+
+use std::fmt::Write;
+
+use super::escape;
+
+/// The 16 standard terminal colours, then their bright variants.
+const PALETTE: [&str; 16] = [
+    "#3b4252", "#bf616a", "#a3be8c", "#ebcb8b", "#81a1c1", "#b48ead",
+    "#88c0d0", "#e5e9f0", "#4c566a", "#d08770", "#b5d69c", "#f0d399",
+    "#8fbcbb", "#c895bf", "#93ccdc", "#eceff4",
+];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Style {
+    foreground: Option<String>,
+    background: Option<String>,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+}
+
+impl Style {
+    /// Applies the parameters of a "select graphic rendition" sequence.
+    fn apply(&mut self, params: &str) {
+        let mut codes = params
+            .split(';')
+            .map(|code| code.parse::<u16>().unwrap_or(0));
+
+        while let Some(code) = codes.next() {
+            match code {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                23 => self.italic = false,
+                24 => self.underline = false,
+                30..=37 => self.foreground = palette(code - 30),
+                90..=97 => self.foreground = palette(code - 90 + 8),
+                39 => self.foreground = None,
+                40..=47 => self.background = palette(code - 40),
+                100..=107 => self.background = palette(code - 100 + 8),
+                49 => self.background = None,
+                38 => self.foreground = extended_colour(&mut codes),
+                48 => self.background = extended_colour(&mut codes),
+                _ => {}
+            }
+        }
+    }
+
+    fn css(&self) -> String {
+        let mut css = String::new();
+        if let Some(colour) = &self.foreground {
+            let _ = write!(css, "color:{};", colour);
+        }
+        if let Some(colour) = &self.background {
+            let _ = write!(css, "background:{};", colour);
+        }
+        if self.bold {
+            css.push_str("font-weight:bold;");
+        }
+        if self.dim {
+            css.push_str("opacity:0.7;");
+        }
+        if self.italic {
+            css.push_str("font-style:italic;");
+        }
+        if self.underline {
+            css.push_str("text-decoration:underline;");
+        }
+        css
+    }
+}
+
+fn palette(index: u16) -> Option<String> {
+    PALETTE.get(index as usize).map(|colour| colour.to_string())
+}
+
+/// Reads the rest of a `38;5;n` or `38;2;r;g;b` colour.
+fn extended_colour(codes: &mut impl Iterator<Item = u16>) -> Option<String> {
+    match codes.next()? {
+        5 => {
+            let index = codes.next()?;
+            match index {
+                0..=15 => palette(index),
+                // a 6×6×6 colour cube
+                16..=231 => {
+                    let index = index - 16;
+                    let level = |n: u16| if n == 0 { 0 } else { n * 40 + 55 };
+                    Some(format!(
+                        "rgb({},{},{})",
+                        level(index / 36),
+                        level(index / 6 % 6),
+                        level(index % 6)
+                    ))
+                }
+                // then a ramp of greys
+                _ => {
+                    let grey = (index.min(255) - 232) * 10 + 8;
+                    Some(format!("rgb({},{},{})", grey, grey, grey))
+                }
+            }
+        }
+        2 => {
+            let (r, g, b) = (codes.next()?, codes.next()?, codes.next()?);
+            Some(format!("rgb({},{},{})", r.min(255), g.min(255), b.min(255)))
+        }
+        _ => None,
+    }
+}
+
+/// Renders `text` as numbered lines, each linkable with `#L<number>`.
+///
+/// Colours carry over from one line to the next, as they do in a terminal.
+/// Other escape sequences are dropped, and carriage returns overwrite the
+/// line the way progress bars expect.
+pub fn lines_html(text: &str) -> String {
+    let mut html = String::with_capacity(text.len() * 2);
+    let mut style = Style::default();
+
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let _ = write!(
+            html,
+            "<div class=\"line\" id=\"L{0}\"><a href=\"#L{0}\">{0}</a><pre>",
+            number
+        );
+
+        // only what was written after the last carriage return is visible
+        let line = line.trim_end_matches('\r');
+        let visible_from = line.rfind('\r').map_or(0, |i| i + 1);
+        // styles set before it still apply, though
+        let (hidden, line) = line.split_at(visible_from);
+        write_segment(&mut String::new(), hidden, &mut style);
+        write_segment(&mut html, line, &mut style);
+
+        html.push_str("</pre></div>");
+    }
+
+    html
+}
+
+/// Writes `text` with its escape codes turned into styled spans.
+fn write_segment(html: &mut String, text: &str, style: &mut Style) {
+    let mut rest = text;
+
+    loop {
+        let (plain, sequence) = match rest.find('\x1b') {
+            Some(start) => rest.split_at(start),
+            None => (rest, ""),
+        };
+        write_styled(html, plain, style);
+        if sequence.is_empty() {
+            break;
+        }
+
+        // a control sequence runs from `ESC [` to its final byte, @ to ~
+        let body = &sequence[1..];
+        let Some(params) = body.strip_prefix('[') else {
+            // some other escape: drop it and the character after it
+            let mut chars = body.chars();
+            chars.next();
+            rest = chars.as_str();
+            continue;
+        };
+        match params.find(|c: char| ('@'..='~').contains(&c)) {
+            Some(end) => {
+                if params[end..].starts_with('m') {
+                    style.apply(&params[..end]);
+                }
+                rest = &params[end + 1..];
+            }
+            None => break,
+        }
+    }
+}
+
+fn write_styled(html: &mut String, text: &str, style: &Style) {
+    if text.is_empty() {
+        return;
+    }
+
+    if *style == Style::default() {
+        html.push_str(&escape(text));
+    } else {
+        let _ = write!(
+            html,
+            "<span style=\"{}\">{}</span>",
+            style.css(),
+            escape(text)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The content of each line, without the line numbers around it.
+    fn contents(html: &str) -> Vec<&str> {
+        html.split("<pre>")
+            .skip(1)
+            .map(|line| line.split_once("</pre>").unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn numbers_lines() {
+        let html = lines_html("one\ntwo\r\n");
+
+        assert!(html.starts_with(
+            "<div class=\"line\" id=\"L1\"><a href=\"#L1\">1</a><pre>one"
+        ));
+        assert!(html.contains("id=\"L2\""));
+        assert!(!html.contains("id=\"L3\""));
+        assert_eq!(contents(&html), ["one", "two"]);
+    }
+
+    #[test]
+    fn escapes_html() {
+        let html = lines_html("<script>alert('x' & \"y\")</script>");
+
+        assert_eq!(
+            contents(&html),
+            ["&lt;script&gt;alert(&#39;x&#39; &amp; &quot;y&quot;)\
+              &lt;/script&gt;"]
+        );
+    }
+
+    #[test]
+    fn escapes_styled_text() {
+        let html = lines_html("\x1b[31m<b>\x1b[0m");
+
+        assert_eq!(
+            contents(&html),
+            ["<span style=\"color:#bf616a;\">&lt;b&gt;</span>"]
+        );
+    }
+
+    #[test]
+    fn carries_styles_across_lines() {
+        let html = lines_html("\x1b[1;32mok\nstill\x1b[22;39m\nplain");
+
+        assert_eq!(
+            contents(&html),
+            [
+                "<span style=\"color:#a3be8c;font-weight:bold;\">ok</span>",
+                "<span style=\"color:#a3be8c;font-weight:bold;\">still</span>",
+                "plain",
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_extended_colours() {
+        let html = lines_html(
+            "\x1b[38;5;196ma\x1b[0m\x1b[48;2;1;2;300mb\x1b[0m\x1b[38;5;244mc",
+        );
+
+        assert_eq!(
+            contents(&html),
+            ["<span style=\"color:rgb(255,0,0);\">a</span>\
+                 <span style=\"background:rgb(1,2,255);\">b</span>\
+                 <span style=\"color:rgb(128,128,128);\">c</span>"]
+        );
+    }
+
+    #[test]
+    fn drops_other_escape_sequences() {
+        let html = lines_html("a\x1b[2Kb\x1b]c\x1b(Bd\x1b[");
+
+        assert_eq!(contents(&html), ["abcBd"]);
+    }
+
+    #[test]
+    fn overwrites_lines_on_carriage_returns() {
+        let html = lines_html("\x1b[33m 10%\r 50%\r100%");
+
+        assert_eq!(
+            contents(&html),
+            ["<span style=\"color:#ebcb8b;\">100%</span>"]
+        );
+    }
+}
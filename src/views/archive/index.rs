@@ -0,0 +1,415 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Seek, SeekFrom, Write},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::NaiveDateTime;
+use flate2::read::GzDecoder;
+use tar::EntryType;
+use zip::ZipArchive;
+
+use super::reader::ArchiveReader;
+use crate::{routes::ArchiveFormat, storage::Entry};
+
+/// How many archives' indexes are kept around.
+const MAX_CACHED_INDEXES: usize = 32;
+
+/// How many members an archive can have and still be browsed, so listing one
+/// can't fill up memory.
+const MAX_MEMBERS: usize = 100_000;
+
+/// A file or directory inside an archive.
+#[derive(Debug, Clone)]
+pub struct Member {
+    /// `/`-separated, without a leading or trailing slash.
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: SystemTime,
+    /// Its index in a zip, or its position among a tar's entries.
+    index: usize,
+    /// Where its contents start, in an uncompressed tar.
+    offset: u64,
+}
+
+/// Everything in an archive, as read from it.
+#[derive(Debug)]
+pub struct ArchiveIndex {
+    pub format: ArchiveFormat,
+    pub members: Vec<Member>,
+    /// What the archive looked like when it was read, to tell when it's been
+    /// replaced.
+    size: u64,
+    modified: SystemTime,
+}
+
+impl ArchiveIndex {
+    /// Reads the list of members from `reader`, an archive of `size` bytes
+    /// last modified at `modified`.
+    ///
+    /// Zips and uncompressed tars are read only as far as their headers, but
+    /// a compressed tar has to be decompressed in full.
+    pub fn read(
+        mut reader: ArchiveReader,
+        format: ArchiveFormat,
+        size: u64,
+        modified: SystemTime,
+    ) -> io::Result<ArchiveIndex> {
+        let members = match format {
+            ArchiveFormat::Zip => zip_members(reader, MAX_MEMBERS)?,
+            ArchiveFormat::Tar => {
+                let mut archive = tar::Archive::new(&mut reader);
+                tar_members(archive.entries_with_seek()?, MAX_MEMBERS)?
+            }
+            ArchiveFormat::TarGz => {
+                let mut archive = tar::Archive::new(GzDecoder::new(reader));
+                tar_members(archive.entries()?, MAX_MEMBERS)?
+            }
+        };
+
+        Ok(ArchiveIndex {
+            format,
+            members,
+            size,
+            modified,
+        })
+    }
+
+    /// The file at `path`, if there is one.
+    pub fn file(&self, path: &str) -> Option<&Member> {
+        self.members
+            .iter()
+            .find(|member| !member.is_dir && member.path == path)
+    }
+
+    /// What's directly inside the directory at `path`, where `""` is the
+    /// root. Returns `None` if there's no such directory.
+    ///
+    /// Archives don't always have entries for their directories, so they're
+    /// also made up from the paths of what's in them, and dated by the most
+    /// recent thing in them. Like directories in storage, they have no size.
+    pub fn directory(&self, path: &str) -> Option<Vec<Entry>> {
+        let mut found = path.is_empty();
+        let mut children = HashMap::<(String, bool), Entry>::new();
+
+        for member in &self.members {
+            let rest = if path.is_empty() {
+                Some(member.path.as_str())
+            } else {
+                member
+                    .path
+                    .strip_prefix(path)
+                    .and_then(|rest| rest.strip_prefix('/'))
+            };
+            let Some(rest) = rest else {
+                found |= member.is_dir && member.path == path;
+                continue;
+            };
+            found = true;
+
+            let (name, is_dir) = match rest.split_once('/') {
+                Some((directory, _)) => (directory, true),
+                None => (rest, member.is_dir),
+            };
+            let child = children
+                .entry((name.to_string(), is_dir))
+                .or_insert_with(|| Entry {
+                    name: name.to_string(),
+                    is_dir,
+                    size: if is_dir { 0 } else { member.size },
+                    modified: member.modified,
+                });
+            child.modified = child.modified.max(member.modified);
+        }
+
+        found.then(|| children.into_values().collect())
+    }
+
+    /// Writes the contents of `member` to `writer`, reading them from
+    /// `reader`, the archive this index was read from.
+    pub fn copy_member(
+        &self,
+        mut reader: ArchiveReader,
+        member: &Member,
+        writer: &mut impl Write,
+    ) -> io::Result<()> {
+        match self.format {
+            ArchiveFormat::Zip => {
+                let mut archive =
+                    ZipArchive::new(reader).map_err(io::Error::other)?;
+                let mut file =
+                    archive.by_index(member.index).map_err(io::Error::other)?;
+                io::copy(&mut file, writer)?;
+            }
+            ArchiveFormat::Tar => {
+                reader.seek(SeekFrom::Start(member.offset))?;
+                io::copy(&mut reader.take(member.size), writer)?;
+            }
+            ArchiveFormat::TarGz => {
+                let mut archive = tar::Archive::new(GzDecoder::new(reader));
+                let mut entry = archive
+                    .entries()?
+                    .nth(member.index)
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::NotFound,
+                            "archive member has gone missing",
+                        )
+                    })??;
+                io::copy(&mut entry, writer)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn zip_members(
+    reader: ArchiveReader,
+    max_members: usize,
+) -> io::Result<Vec<Member>> {
+    let mut archive = ZipArchive::new(reader).map_err(io::Error::other)?;
+    if archive.len() > max_members {
+        return Err(too_many_members(max_members));
+    }
+    let mut members = Vec::with_capacity(archive.len());
+
+    for index in 0..archive.len() {
+        // the raw entry doesn't need a password, or a supported compression
+        let file = archive.by_index_raw(index).map_err(io::Error::other)?;
+        let path = match normalise_path(file.name()) {
+            Some(path) if !path.is_empty() => path,
+            _ => continue,
+        };
+
+        // zips store local time without a time zone, so it's as good as any
+        let modified = file
+            .last_modified()
+            .and_then(|modified| NaiveDateTime::try_from(modified).ok())
+            .map(|modified| SystemTime::from(modified.and_utc()))
+            .unwrap_or(UNIX_EPOCH);
+
+        members.push(Member {
+            path,
+            is_dir: file.is_dir(),
+            size: file.size(),
+            modified,
+            index,
+            offset: 0,
+        });
+    }
+
+    Ok(members)
+}
+
+fn tar_members<R: Read>(
+    entries: tar::Entries<'_, R>,
+    max_members: usize,
+) -> io::Result<Vec<Member>> {
+    let mut members = Vec::new();
+
+    for (index, entry) in entries.enumerate() {
+        // counting what's skipped too, as it still has to be read through
+        if index >= max_members {
+            return Err(too_many_members(max_members));
+        }
+        let entry = entry?;
+        let is_dir = match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Continuous => false,
+            EntryType::Directory => true,
+            // links and devices have nothing to download
+            _ => continue,
+        };
+        let path = match normalise_path(&entry.path()?.to_string_lossy()) {
+            Some(path) if !path.is_empty() => path,
+            _ => continue,
+        };
+
+        members.push(Member {
+            path,
+            is_dir,
+            size: entry.size(),
+            modified: UNIX_EPOCH
+                + Duration::from_secs(entry.header().mtime().unwrap_or(0)),
+            index,
+            offset: entry.raw_file_position(),
+        });
+    }
+
+    Ok(members)
+}
+
+fn too_many_members(max_members: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("archive has more than {} members", max_members),
+    )
+}
+
+/// `path` without empty and `.` segments, using `/` as its separator, where
+/// `""` is the root. Returns `None` for paths that climb out of it.
+pub fn normalise_path(path: &str) -> Option<String> {
+    let mut segments = Vec::new();
+
+    for segment in path.split(['/', '\\']) {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            segment => segments.push(segment),
+        }
+    }
+
+    Some(segments.join("/"))
+}
+
+/// The indexes of recently browsed archives, so going through one doesn't
+/// read it again for every page.
+#[derive(Default)]
+pub struct ArchiveIndexes {
+    indexes: Mutex<HashMap<String, (Arc<ArchiveIndex>, Instant)>>,
+}
+
+impl ArchiveIndexes {
+    /// The index of the archive at `backend_key`, if it's been read since it
+    /// was last changed.
+    pub fn get(
+        &self,
+        backend_key: &str,
+        size: u64,
+        modified: SystemTime,
+    ) -> Option<Arc<ArchiveIndex>> {
+        let mut indexes = self.indexes.lock().expect("should not be poisoned");
+        let (index, used) = indexes.get_mut(backend_key)?;
+        if index.size != size || index.modified != modified {
+            return None;
+        }
+
+        *used = Instant::now();
+        Some(Arc::clone(index))
+    }
+
+    pub fn insert(&self, backend_key: &str, index: Arc<ArchiveIndex>) {
+        let mut indexes = self.indexes.lock().expect("should not be poisoned");
+
+        if indexes.len() >= MAX_CACHED_INDEXES
+            && !indexes.contains_key(backend_key)
+        {
+            let least_recent = indexes
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = least_recent {
+                indexes.remove(&key);
+            }
+        }
+
+        indexes.insert(backend_key.to_string(), (index, Instant::now()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn member(path: &str, is_dir: bool, size: u64, modified: u64) -> Member {
+        Member {
+            path: path.to_string(),
+            is_dir,
+            size,
+            modified: UNIX_EPOCH + Duration::from_secs(modified),
+            index: 0,
+            offset: 0,
+        }
+    }
+
+    fn index(members: Vec<Member>) -> ArchiveIndex {
+        ArchiveIndex {
+            format: ArchiveFormat::Zip,
+            members,
+            size: 0,
+            modified: UNIX_EPOCH,
+        }
+    }
+
+    fn tar(paths: &[&str]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for path in paths {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(3);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, &b"abc"[..]).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn lists_directories_made_up_from_paths() {
+        let index = index(vec![
+            member("a/b.txt", false, 100, 10),
+            member("a/c/d.txt", false, 200, 30),
+            member("e.txt", false, 5, 20),
+        ]);
+
+        let mut root = index.directory("").unwrap();
+        root.sort_by(|a, b| a.name.cmp(&b.name));
+        let root: Vec<_> = root
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.is_dir, entry.size))
+            .collect();
+        assert_eq!(root, [("a", true, 0), ("e.txt", false, 5)]);
+
+        let a = index.directory("a").unwrap();
+        let c = a.iter().find(|entry| entry.name == "c").unwrap();
+        assert_eq!(c.size, 0);
+        assert_eq!(c.modified, UNIX_EPOCH + Duration::from_secs(30));
+        let dated = index.directory("").unwrap();
+        let a = dated.iter().find(|entry| entry.name == "a").unwrap();
+        assert_eq!(a.modified, UNIX_EPOCH + Duration::from_secs(30));
+
+        assert!(index.directory("missing").is_none());
+        assert!(index.directory("e.txt").is_none());
+    }
+
+    #[test]
+    fn lists_empty_directories_with_entries() {
+        let index = index(vec![member("empty", true, 0, 0)]);
+
+        assert!(index.directory("empty").unwrap().is_empty());
+    }
+
+    #[test]
+    fn reads_tar_members() {
+        let bytes = tar(&["a/b.txt", "c.txt"]);
+        let mut archive = tar::Archive::new(Cursor::new(bytes));
+
+        let members =
+            tar_members(archive.entries_with_seek().unwrap(), 10).unwrap();
+
+        let paths: Vec<_> =
+            members.iter().map(|member| member.path.as_str()).collect();
+        assert_eq!(paths, ["a/b.txt", "c.txt"]);
+        assert_eq!(members[1].size, 3);
+    }
+
+    #[test]
+    fn refuses_tars_with_too_many_members() {
+        let bytes = tar(&["a", "b", "c"]);
+        let mut archive = tar::Archive::new(Cursor::new(bytes));
+
+        let error =
+            tar_members(archive.entries_with_seek().unwrap(), 2).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn normalises_paths() {
+        assert_eq!(normalise_path("./a//b\\c/").as_deref(), Some("a/b/c"));
+        assert_eq!(normalise_path("/").as_deref(), Some(""));
+        assert_eq!(normalise_path("a/../b"), None);
+    }
+}
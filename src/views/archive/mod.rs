@@ -0,0 +1,252 @@
+//! Browsing the contents of zip and tar archives, and downloading single
+//! files from inside them, at `<archive>/!/<path inside it>`.
+
+mod index;
+mod reader;
+
+use std::{cmp::Reverse, io, sync::Arc};
+
+use actix_web::{
+    HttpRequest, HttpResponse,
+    body::SizedStream,
+    http::{
+        Method,
+        header::{
+            self, ContentDisposition, ContentType, DispositionParam,
+            DispositionType, HttpDate, LastModified,
+        },
+    },
+    web::{self, Bytes, Data},
+};
+use log::error;
+use num_format::{Locale, ToFormattedString};
+use percent_encoding::utf8_percent_encode;
+
+pub use index::ArchiveIndexes;
+use index::{ArchiveIndex, normalise_path};
+use reader::ArchiveReader;
+
+use super::{stat_content, without_view};
+use crate::{
+    access,
+    config::{Config, NamespaceDefinition},
+    downloads::DownloadLimits,
+    render::{
+        PATH_SEGMENT, individual_listing, render_page, template::DirectoryView,
+    },
+    routes::{ArchiveFormat, streaming},
+    storage::{Entry, NamespaceStorage},
+};
+
+/// Splits a path to something inside an archive into the path to the
+/// archive and the path inside it.
+pub fn split_member_path(path: &str) -> Option<(&str, &str)> {
+    path.split_once("/!/")
+        .or_else(|| path.strip_suffix("/!").map(|archive| (archive, "")))
+}
+
+/// Answers a request for `path` inside the archive at `key`: a listing of
+/// it if it's a directory, or its contents if it's a file.
+pub async fn respond(
+    req: &HttpRequest,
+    cfg: &Config,
+    definition: &NamespaceDefinition,
+    storage: NamespaceStorage,
+    key: String,
+    path: &str,
+) -> HttpResponse {
+    let Some(format) = ArchiveFormat::from_key(&key) else {
+        return HttpResponse::NotFound().body("Not found");
+    };
+
+    // unpacking one would be a way around its limit
+    let backend_key = storage.backend_key(&key);
+    let is_limited = req
+        .app_data::<Data<DownloadLimits>>()
+        .is_some_and(|limits| limits.is_limited(&backend_key));
+    if is_limited {
+        return HttpResponse::Forbidden().body(
+            "Archives with a download limit can only be downloaded whole",
+        );
+    }
+
+    let encryption_key = definition.encryption_at_rest.key().cloned();
+    let stat = {
+        let storage = storage.clone();
+        let key = key.clone();
+        let encrypted = encryption_key.is_some();
+        web::block(move || stat_content(&storage, &key, encrypted)).await
+    };
+    let Ok(Some((stored_size, _, modified))) = stat else {
+        return HttpResponse::NotFound().body("Not found");
+    };
+
+    let indexes = req.app_data::<Data<ArchiveIndexes>>().cloned();
+    let cached = indexes
+        .as_ref()
+        .and_then(|indexes| indexes.get(&backend_key, stored_size, modified));
+    let index = match cached {
+        Some(index) => index,
+        None => {
+            let storage = storage.clone();
+            let key = key.clone();
+            let encryption_key = encryption_key.clone();
+            let read = web::block(move || {
+                let reader = ArchiveReader::open(
+                    &storage,
+                    &key,
+                    stored_size,
+                    encryption_key.as_ref(),
+                )?;
+                ArchiveIndex::read(reader, format, stored_size, modified)
+            })
+            .await;
+
+            match read {
+                Ok(Ok(index)) => {
+                    let index = Arc::new(index);
+                    if let Some(indexes) = indexes {
+                        indexes.insert(&backend_key, Arc::clone(&index));
+                    }
+                    index
+                }
+                Ok(Err(e)) => {
+                    error!("Failed to read archive {}: {}", backend_key, e);
+                    return HttpResponse::UnprocessableEntity()
+                        .body("Failed to read archive");
+                }
+                Err(e) => {
+                    error!("Failed to read archive {}: {}", backend_key, e);
+                    return HttpResponse::InternalServerError()
+                        .body("Failed to read archive");
+                }
+            }
+        }
+    };
+
+    let Some(path) = normalise_path(path) else {
+        return HttpResponse::NotFound().body("Not found");
+    };
+    if let Some(member) = index.file(&path) {
+        let member = member.clone();
+        let content_type =
+            mime_guess::from_path(&member.path).first_or_octet_stream();
+        let mut response = HttpResponse::Ok();
+        response
+            .insert_header(LastModified(HttpDate::from(member.modified)))
+            .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"));
+
+        // members are only shown inline if they can't run script on the site
+        if access::is_passive(&content_type) {
+            response.content_type(ContentType(content_type));
+        } else {
+            let name = member.path.rsplit('/').next().unwrap_or_default();
+            response
+                .content_type(ContentType::octet_stream())
+                .insert_header(ContentDisposition {
+                    disposition: DispositionType::Attachment,
+                    parameters: vec![DispositionParam::Filename(
+                        name.to_string(),
+                    )],
+                });
+        }
+
+        if req.method() == Method::HEAD {
+            return response.body(SizedStream::new(
+                member.size,
+                futures_util::stream::empty::<io::Result<Bytes>>(),
+            ));
+        }
+
+        let size = member.size;
+        let body = streaming::blocking_body(move |writer| {
+            let reader = ArchiveReader::open(
+                &storage,
+                &key,
+                stored_size,
+                encryption_key.as_ref(),
+            )?;
+            index.copy_member(reader, &member, writer)
+        });
+        return response.body(SizedStream::new(size, body));
+    }
+
+    match index.directory(&path) {
+        Some(entries) => render_directory(req, cfg, &path, entries),
+        None => HttpResponse::NotFound().body("Not found"),
+    }
+}
+
+/// Renders the directory at `path` inside the archive the request is for,
+/// the same way the fancy renderer lists directories in storage.
+fn render_directory(
+    req: &HttpRequest,
+    cfg: &Config,
+    path: &str,
+    mut entries: Vec<Entry>,
+) -> HttpResponse {
+    let (archive_href, _) = split_member_path(req.path())
+        .expect("should only be rendering paths inside archives");
+
+    // links keep the query, so they keep the archive's signature too
+    let query = match without_view(req.query_string()) {
+        query if query.is_empty() => query,
+        query => format!("?{}", query),
+    };
+    let directory_href = |path: &str| {
+        let segments: Vec<_> = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| {
+                format!("{}/", utf8_percent_encode(segment, PATH_SEGMENT))
+            })
+            .collect();
+        format!("{}/!/{}", archive_href, segments.concat())
+    };
+
+    let parent_dir_href = match path.rsplit_once('/') {
+        Some((parent, _)) => format!("{}{}", directory_href(parent), query),
+        None if !path.is_empty() => format!("{}{}", directory_href(""), query),
+        // the directory the archive is in
+        None => archive_href
+            .rsplit_once('/')
+            .map(|(parent, _)| format!("{}/", parent))
+            .unwrap_or_default(),
+    };
+
+    // directories first, then most recently modified
+    entries.sort_by_key(|entry| (!entry.is_dir, Reverse(entry.modified)));
+
+    let base = directory_href(path);
+    let total_items = entries.len().to_formatted_string(&Locale::en);
+    let listings: Vec<_> = entries
+        .into_iter()
+        .map(|entry| {
+            let href = format!(
+                "{}{}{}{}",
+                base,
+                utf8_percent_encode(&entry.name, PATH_SEGMENT),
+                if entry.is_dir { "/" } else { "" },
+                query
+            );
+            individual_listing(cfg, entry, href)
+        })
+        .collect();
+
+    render_page(&DirectoryView {
+        current_directory: req.path(),
+        total_items: &total_items,
+        parent_dir_href: &parent_dir_href,
+        archive_href: "",
+        individual_listings: &listings,
+    })
+}
+
+/// Where a request for the archive at `req`'s path is redirected to be
+/// browsed.
+pub fn browse_href(req: &HttpRequest) -> String {
+    match without_view(req.query_string()) {
+        query if query.is_empty() => format!("{}/!/", req.path()),
+        query => format!("{}/!/?{}", req.path(), query),
+    }
+}
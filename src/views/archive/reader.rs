@@ -0,0 +1,187 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::{
+    config::EncryptionKey,
+    encryption::{self, EncryptedFile},
+    storage::NamespaceStorage,
+};
+
+/// How much is read from storage at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Seeks forward by less than this are read through rather than starting a
+/// new read, which is cheaper than another request to S3.
+const SKIP_THRESHOLD: u64 = 1024 * 1024;
+
+/// Where the bytes of an archive come from.
+enum Source {
+    Stored {
+        storage: NamespaceStorage,
+        key: String,
+        stream: Option<Box<dyn Read + Send>>,
+    },
+    Encrypted(EncryptedFile),
+}
+
+/// A stored archive that can be read from anywhere in it, fetching only the
+/// parts that are actually read. Zips keep their index at the end, so a
+/// member of a huge one can be served without fetching the whole thing.
+pub struct ArchiveReader {
+    source: Source,
+    len: u64,
+    /// Where the next read starts.
+    position: u64,
+    /// Bytes read ahead, starting at `buffer_start`.
+    buffer: Vec<u8>,
+    buffer_start: u64,
+    /// Where the open read from the source is at, if there is one.
+    stream_position: Option<u64>,
+}
+
+impl ArchiveReader {
+    /// Opens the archive at `key`, which is `stored_size` bytes in storage,
+    /// decrypting it with `encryption_key` if it's encrypted at rest.
+    pub fn open(
+        storage: &NamespaceStorage,
+        key: &str,
+        stored_size: u64,
+        encryption_key: Option<&EncryptionKey>,
+    ) -> io::Result<ArchiveReader> {
+        let (source, len) = match encryption_key {
+            Some(encryption_key) if encryption::is_encrypted(storage, key)? => {
+                let file = EncryptedFile::open(
+                    storage,
+                    key,
+                    stored_size,
+                    encryption_key,
+                )?;
+                let len = file.plaintext_len();
+                (Source::Encrypted(file), len)
+            }
+            _ => (
+                Source::Stored {
+                    storage: storage.clone(),
+                    key: key.to_string(),
+                    stream: None,
+                },
+                stored_size,
+            ),
+        };
+
+        Ok(ArchiveReader {
+            source,
+            len,
+            position: 0,
+            buffer: Vec::new(),
+            buffer_start: 0,
+            stream_position: None,
+        })
+    }
+
+    /// Replaces the buffer with the bytes from `position` on.
+    fn refill(&mut self) -> io::Result<()> {
+        let position = self.position;
+
+        match self.stream_position {
+            Some(at) if at == position => {}
+            Some(at) if at < position && position - at < SKIP_THRESHOLD => {
+                let skipped = io::copy(
+                    &mut (&mut self.source).take(position - at),
+                    &mut io::sink(),
+                )?;
+                if skipped < position - at {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+            _ => match &mut self.source {
+                Source::Stored {
+                    storage,
+                    key,
+                    stream,
+                } => {
+                    *stream = Some(storage.get_range(
+                        key,
+                        position,
+                        self.len - position,
+                    )?);
+                }
+                Source::Encrypted(file) => file.seek_to(position)?,
+            },
+        }
+
+        let wanted = CHUNK_SIZE.min((self.len - position) as usize);
+        self.buffer.resize(wanted, 0);
+        let mut filled = 0;
+        while filled < wanted {
+            match self.source.read(&mut self.buffer[filled..]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.stream_position = None;
+                    return Err(e);
+                }
+            }
+        }
+        self.buffer.truncate(filled);
+        self.buffer_start = position;
+        self.stream_position = Some(position + filled as u64);
+
+        Ok(())
+    }
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::Stored {
+                stream: Some(stream),
+                ..
+            } => stream.read(buf),
+            Source::Stored { stream: None, .. } => Ok(0),
+            Source::Encrypted(file) => file.read(buf),
+        }
+    }
+}
+
+impl Read for ArchiveReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let buffer_end = self.buffer_start + self.buffer.len() as u64;
+        if self.position < self.buffer_start || self.position >= buffer_end {
+            self.refill()?;
+        }
+
+        let offset = (self.position - self.buffer_start) as usize;
+        let available = &self.buffer[offset.min(self.buffer.len())..];
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.position += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl Seek for ArchiveReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => {
+                self.position.checked_add_signed(offset)
+            }
+        };
+
+        // past the end is fine, there's just nothing to read there
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to before the start of the archive",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
@@ -0,0 +1,186 @@
+//! Pretty-prints JSON as HTML, with collapsible objects and arrays.
+
+use std::fmt::Write;
+
+use serde_json::Value;
+
+use super::escape;
+
+/// Renders `text` as collapsible HTML, or returns `None` if it isn't JSON.
+pub fn json_html(text: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(text).ok()?;
+
+    let mut html = String::with_capacity(text.len() * 3);
+    write_value(&mut html, &value, false);
+    Some(html)
+}
+
+fn write_value(html: &mut String, value: &Value, trailing_comma: bool) {
+    let comma = if trailing_comma { "," } else { "" };
+
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            write_collapsible(
+                html,
+                "{",
+                "}",
+                map.len(),
+                "key",
+                comma,
+                |html| {
+                    for (index, (key, value)) in map.iter().enumerate() {
+                        let _ = write!(
+                            html,
+                            "<div class=\"entry\">\
+                             <span class=\"key\">{}</span>: ",
+                            escape(&Value::String(key.clone()).to_string())
+                        );
+                        write_value(html, value, index + 1 < map.len());
+                        html.push_str("</div>");
+                    }
+                },
+            );
+        }
+        Value::Array(items) if !items.is_empty() => {
+            write_collapsible(
+                html,
+                "[",
+                "]",
+                items.len(),
+                "item",
+                comma,
+                |html| {
+                    for (index, value) in items.iter().enumerate() {
+                        html.push_str("<div class=\"entry\">");
+                        write_value(html, value, index + 1 < items.len());
+                        html.push_str("</div>");
+                    }
+                },
+            );
+        }
+        Value::Object(_) => {
+            let _ = write!(html, "{{}}{}", comma);
+        }
+        Value::Array(_) => {
+            let _ = write!(html, "[]{}", comma);
+        }
+        Value::String(_) => {
+            let _ = write!(
+                html,
+                "<span class=\"string\">{}</span>{}",
+                escape(&value.to_string()),
+                comma
+            );
+        }
+        Value::Number(number) => {
+            let _ = write!(
+                html,
+                "<span class=\"number\">{}</span>{}",
+                number, comma
+            );
+        }
+        Value::Bool(_) | Value::Null => {
+            let _ = write!(
+                html,
+                "<span class=\"literal\">{}</span>{}",
+                value, comma
+            );
+        }
+    }
+}
+
+/// Writes an object or array that folds away to a count of its children.
+fn write_collapsible(
+    html: &mut String,
+    open: &str,
+    close: &str,
+    len: usize,
+    noun: &str,
+    comma: &str,
+    children: impl FnOnce(&mut String),
+) {
+    let _ = write!(
+        html,
+        "<details open><summary>{}<span class=\"count\">{} {}{}</span></summary>\
+         <div class=\"children\">",
+        open,
+        len,
+        noun,
+        if len == 1 { "" } else { "s" }
+    );
+    children(html);
+    // outside the details, so it's still there when they're collapsed
+    let _ = write!(html, "</div></details>{}{}", close, comma);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_invalid_json() {
+        assert!(json_html("{\"a\": ").is_none());
+        assert!(json_html("not json").is_none());
+    }
+
+    #[test]
+    fn renders_scalars() {
+        assert_eq!(
+            json_html("1.5").unwrap(),
+            "<span class=\"number\">1.5</span>"
+        );
+        assert_eq!(
+            json_html("null").unwrap(),
+            "<span class=\"literal\">null</span>"
+        );
+        assert_eq!(json_html("[]").unwrap(), "[]");
+        assert_eq!(json_html("{}").unwrap(), "{}");
+    }
+
+    #[test]
+    fn escapes_strings_and_keys() {
+        let html =
+            json_html(r#"{"<img src=x>": "</span><script>'&'</script>"}"#)
+                .unwrap();
+
+        assert!(html.contains(
+            "<span class=\"key\">&quot;&lt;img src=x&gt;&quot;</span>"
+        ));
+        assert!(html.contains(
+            "<span class=\"string\">&quot;&lt;/span&gt;&lt;script&gt;\
+             &#39;&amp;&#39;&lt;/script&gt;&quot;</span>"
+        ));
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<img"));
+    }
+
+    #[test]
+    fn keeps_string_escapes_visible() {
+        let html = json_html(r#""line\nbreak \"quoted\"""#).unwrap();
+
+        assert_eq!(
+            html,
+            "<span class=\"string\">&quot;line\\nbreak \
+             \\&quot;quoted\\&quot;&quot;</span>"
+        );
+    }
+
+    #[test]
+    fn collapses_objects_and_arrays() {
+        let html = json_html(r#"{"a": [1, true], "b": {}}"#).unwrap();
+
+        assert_eq!(
+            html,
+            "<details open><summary>{<span class=\"count\">2 keys</span>\
+             </summary><div class=\"children\">\
+             <div class=\"entry\"><span class=\"key\">&quot;a&quot;</span>: \
+             <details open><summary>[<span class=\"count\">2 items</span>\
+             </summary><div class=\"children\">\
+             <div class=\"entry\"><span class=\"number\">1</span>,</div>\
+             <div class=\"entry\"><span class=\"literal\">true</span></div>\
+             </div></details>],</div>\
+             <div class=\"entry\"><span class=\"key\">&quot;b&quot;</span>: \
+             {}</div></div></details>}"
+        );
+    }
+}
@@ -0,0 +1,22 @@
+//! Renders Markdown as sanitized HTML.
+
+use pulldown_cmark::{Options, Parser, html};
+
+/// Renders `text` as HTML, removing anything that could run a script or
+/// restyle the page, however it got into the Markdown.
+pub fn markdown_html(text: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+
+    let mut unsafe_html = String::with_capacity(text.len() * 3 / 2);
+    html::push_html(&mut unsafe_html, Parser::new_ext(text, options));
+
+    ammonia::Builder::default()
+        // task list checkboxes
+        .add_tags(["input"])
+        .add_tag_attributes("input", ["type", "checked", "disabled"])
+        .clean(&unsafe_html)
+        .to_string()
+}
@@ -0,0 +1,370 @@
+//! Pages shown around files opened in a browser.
+//!
+//! Namespaces pick which kinds of files get a page by default, and any file
+//! can ask for one with `?view=`. `?view=raw` always gets the file as it is,
+//! which is also how the pages themselves fetch it.
+
+mod ansi;
+mod archive;
+mod json;
+mod markdown;
+mod player;
+mod text;
+
+use std::time::SystemTime;
+
+use actix_web::{
+    Error, HttpRequest, HttpResponse,
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        Method,
+        header::{self, ContentType, HeaderMap, HeaderValue},
+    },
+    middleware::Next,
+    web::{self, Data},
+};
+use bytesize::ByteSize;
+use chrono::{DateTime, Utc};
+use log::error;
+use mime_guess::{Mime, mime};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+
+pub use archive::ArchiveIndexes;
+
+use crate::{
+    config::{Config, NamespaceDefinition},
+    downloads::DownloadLimits,
+    encryption,
+    routes::ArchiveFormat,
+    storage::{self, NamespaceStorage, Stat, Storage},
+};
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum View {
+    /// The file as it is.
+    Raw,
+    /// A video or audio player.
+    Player,
+    /// Markdown rendered as HTML.
+    Markdown,
+    /// Pretty-printed, collapsible JSON.
+    Json,
+    /// Numbered lines, with ANSI colours.
+    Text,
+    /// A listing of what's in a zip or tar archive.
+    Archive,
+}
+
+#[derive(Debug, Deserialize)]
+struct ViewQuery {
+    view: Option<View>,
+}
+
+/// A file about to be shown in a page.
+struct ViewedFile {
+    key: String,
+    content_type: Mime,
+    /// The size of the file in storage, which is larger than `size` for
+    /// files encrypted at rest.
+    stored_size: u64,
+    size: u64,
+    modified: SystemTime,
+}
+
+impl ViewedFile {
+    fn file_name(&self) -> &str {
+        self.key.rsplit('/').next().unwrap_or(&self.key)
+    }
+
+    fn byte_size(&self) -> String {
+        ByteSize::b(self.size).display().iec().to_string()
+    }
+
+    fn timestamp(&self) -> String {
+        let modified: DateTime<Utc> = self.modified.into();
+        modified.format("%Y-%m-%d %H:%M").to_string()
+    }
+}
+
+impl View {
+    /// The view `namespace` shows the file at `key`, of `content_type`, in
+    /// by default.
+    fn default_for(
+        namespace: &NamespaceDefinition,
+        key: &str,
+        content_type: &Mime,
+    ) -> Option<View> {
+        let views = &namespace.views;
+        if views.archives && ArchiveFormat::from_key(key).is_some() {
+            return Some(View::Archive);
+        }
+
+        match (content_type.type_(), content_type.subtype().as_str()) {
+            (mime::VIDEO | mime::AUDIO, _) if views.media_player => {
+                Some(View::Player)
+            }
+            (mime::TEXT, "markdown") if views.markdown => Some(View::Markdown),
+            (mime::APPLICATION, "json") if views.json => Some(View::Json),
+            (mime::TEXT, "plain") if views.text => Some(View::Text),
+            _ => None,
+        }
+    }
+
+    /// Whether the view can show the file at `key`, of `content_type`, at
+    /// all.
+    fn can_show(&self, key: &str, content_type: &Mime) -> bool {
+        match self {
+            View::Raw => true,
+            View::Player => {
+                matches!(content_type.type_(), mime::VIDEO | mime::AUDIO)
+            }
+            View::Markdown | View::Json | View::Text => {
+                content_type.type_() == mime::TEXT
+                    || content_type.essence_str() == "application/json"
+            }
+            View::Archive => ArchiveFormat::from_key(key).is_some(),
+        }
+    }
+}
+
+/// Middleware showing files in `namespace` in a page, when the namespace
+/// does so by default or the request asks for one with `?view=`.
+///
+/// Default views only apply to browsers navigating to a file, which ask for
+/// HTML; everything else gets the file as it is. Files with a download limit
+/// are always served as they are, so looking at a page doesn't use one up.
+pub async fn render_views<B: MessageBody>(
+    namespace: String,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let cfg = req.app_data::<Data<Config>>().cloned();
+    let storage = req
+        .app_data::<Data<Storage>>()
+        .and_then(|storage| storage.namespace(&namespace).cloned());
+
+    let (cfg, storage) = match (cfg, storage) {
+        (Some(cfg), Some(storage))
+            if req.method() == Method::GET || req.method() == Method::HEAD =>
+        {
+            (cfg, storage)
+        }
+        _ => return next.call(req).await.map(|r| r.map_into_left_body()),
+    };
+    let Some(definition) = cfg.namespaces.get(&namespace) else {
+        return next.call(req).await.map(|r| r.map_into_left_body());
+    };
+
+    let query = match web::Query::<ViewQuery>::from_query(req.query_string()) {
+        Ok(query) => query.into_inner(),
+        Err(e) => {
+            return Ok(req
+                .into_response(
+                    HttpResponse::BadRequest()
+                        .body(format!("Invalid view: {}", e)),
+                )
+                .map_into_right_body());
+        }
+    };
+
+    let tail = percent_decode_str(req.match_info().unprocessed())
+        .decode_utf8_lossy()
+        .into_owned();
+
+    if let Some((archive, path)) = archive::split_member_path(&tail) {
+        let Some(key) = storage::sanitize_key(archive) else {
+            return next.call(req).await.map(|r| r.map_into_left_body());
+        };
+        let response = archive::respond(
+            req.request(),
+            &cfg,
+            definition,
+            storage,
+            key,
+            path,
+        )
+        .await;
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    let Some(key) = storage::sanitize_key(&tail) else {
+        return next.call(req).await.map(|r| r.map_into_left_body());
+    };
+    let content_type = mime_guess::from_path(&key).first_or_octet_stream();
+
+    // the default depends on what the client accepts, so caches have to know
+    let default = View::default_for(definition, &key, &content_type);
+    let negotiated = query.view.is_none() && default.is_some();
+    let view = match query.view {
+        Some(view) => Some(view),
+        None => default.filter(|_| accepts_html(req.headers())),
+    };
+
+    let view = match view {
+        Some(view)
+            if view != View::Raw && view.can_show(&key, &content_type) =>
+        {
+            view
+        }
+        _ => return pass_through(req, next, negotiated).await,
+    };
+
+    let is_limited = req
+        .app_data::<Data<DownloadLimits>>()
+        .is_some_and(|limits| limits.is_limited(&storage.backend_key(&key)));
+    if is_limited {
+        return pass_through(req, next, negotiated).await;
+    }
+
+    let encrypted = definition.encryption_at_rest.key().is_some();
+    let stat = {
+        let storage = storage.clone();
+        let key = key.clone();
+        web::block(move || stat_content(&storage, &key, encrypted)).await
+    };
+    let file = match stat {
+        Ok(Some((stored_size, size, modified))) => ViewedFile {
+            key,
+            content_type,
+            stored_size,
+            size,
+            modified,
+        },
+        // missing files and directories are the service's business
+        _ => return pass_through(req, next, negotiated).await,
+    };
+
+    let mut response = match view {
+        View::Archive => HttpResponse::Found()
+            .insert_header((
+                header::LOCATION,
+                archive::browse_href(req.request()),
+            ))
+            .finish(),
+        View::Player => player::render(
+            req.request(),
+            &cfg,
+            &namespace,
+            definition,
+            &storage,
+            &file,
+        ),
+        View::Markdown | View::Json | View::Text => {
+            // huge files are better off downloaded
+            if file.size > definition.views.max_rendered_size_bytes {
+                return pass_through(req, next, negotiated).await;
+            }
+
+            let raw_href = raw_href(req.request());
+            let encryption_key = definition.encryption_at_rest.key().cloned();
+            let page = web::block(move || {
+                text::render(&storage, encryption_key, &file, view, &raw_href)
+            })
+            .await;
+
+            match page {
+                Ok(Ok(page)) => HttpResponse::Ok()
+                    .content_type(ContentType::html())
+                    .body(page),
+                Ok(Err(e)) => {
+                    error!("Failed to render {:?} view: {}", view, e);
+                    HttpResponse::InternalServerError()
+                        .body("Failed to render file")
+                }
+                Err(e) => {
+                    error!("Failed to render {:?} view: {}", view, e);
+                    HttpResponse::InternalServerError()
+                        .body("Failed to render file")
+                }
+            }
+        }
+        View::Raw => return pass_through(req, next, negotiated).await,
+    };
+
+    if negotiated {
+        add_vary_accept(response.headers_mut());
+    }
+
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+async fn pass_through<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+    negotiated: bool,
+) -> Result<ServiceResponse<EitherBody<B>>, Error> {
+    let mut res = next.call(req).await?;
+    if negotiated {
+        add_vary_accept(res.headers_mut());
+    }
+    Ok(res.map_into_left_body())
+}
+
+fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+fn add_vary_accept(headers: &mut HeaderMap) {
+    headers.append(header::VARY, HeaderValue::from_static("accept"));
+}
+
+/// The size of the file at `key` in storage and as it's served, and when it
+/// was modified. Returns `None` if it isn't a file.
+fn stat_content(
+    storage: &NamespaceStorage,
+    key: &str,
+    encrypted: bool,
+) -> Option<(u64, u64, SystemTime)> {
+    match storage.stat(key) {
+        Ok(Some(Stat::File { size, modified })) if encrypted => {
+            Some((size, encryption::content_len(storage, key, size), modified))
+        }
+        Ok(Some(Stat::File { size, modified })) => Some((size, size, modified)),
+        _ => None,
+    }
+}
+
+/// Where a page fetches the file it shows from, keeping any signature.
+fn raw_href(req: &HttpRequest) -> String {
+    format!("{}?{}", req.path(), with_view(req.query_string(), "raw"))
+}
+
+/// Escapes `text` for use in HTML.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `query` with its `view` replaced by `view`, for links from a page to the
+/// file it shows.
+fn with_view(query: &str, view: &str) -> String {
+    match without_view(query) {
+        query if query.is_empty() => format!("view={}", view),
+        query => format!("{}&view={}", query, view),
+    }
+}
+
+/// `query` without its `view`, for links that keep the rest of it.
+fn without_view(query: &str) -> String {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !pair.starts_with("view="))
+        .collect::<Vec<_>>()
+        .join("&")
+}
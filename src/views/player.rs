@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use actix_web::{HttpRequest, HttpResponse, web::Data};
+use url::Url;
+
+use super::{ViewedFile, raw_href};
+use crate::{
+    access,
+    config::{Config, NamespaceDefinition},
+    media::{MediaKind, MediaLibrary},
+    render::{render_page, template::MediaPlayer},
+    storage::NamespaceStorage,
+};
+
+/// Renders a page playing the video or audio `file` from `namespace`.
+pub fn render(
+    req: &HttpRequest,
+    cfg: &Config,
+    namespace: &str,
+    definition: &NamespaceDefinition,
+    storage: &NamespaceStorage,
+    file: &ViewedFile,
+) -> HttpResponse {
+    let info = req
+        .app_data::<Data<MediaLibrary>>()
+        .and_then(|media| media.get(&storage.backend_key(&file.key)));
+
+    let is_video = match &info {
+        Some(info) => info.kind == MediaKind::Video,
+        None => file.content_type.type_() == mime_guess::mime::VIDEO,
+    };
+
+    let src = raw_href(req);
+    let absolute = |href: &str| {
+        cfg.web_server
+            .listen_url
+            .join(href.trim_start_matches('/'))
+            .expect("should be able to join with a request path")
+    };
+
+    let page_url = absolute(&match req.query_string() {
+        "" => req.path().to_string(),
+        query => format!("{}?{}", req.path(), query),
+    });
+
+    // posters are checked against their own path, so they need a signature
+    // of their own
+    let poster_url = info
+        .as_ref()
+        .filter(|info| info.poster)
+        .map(|_| {
+            let poster = absolute(&format!(
+                "_poster/{}/{}",
+                namespace,
+                req.match_info().unprocessed().trim_start_matches('/')
+            ));
+            sign(cfg, definition, poster).to_string()
+        })
+        .unwrap_or_default();
+
+    render_page(&MediaPlayer {
+        file_name: file.file_name(),
+        is_video,
+        content_type: file.content_type.essence_str(),
+        src: &src,
+        page_url: page_url.as_str(),
+        media_url: absolute(&src).as_str(),
+        poster_url: &poster_url,
+        width: info.as_ref().and_then(|info| info.width),
+        height: info.as_ref().and_then(|info| info.height),
+        details: &info.as_ref().map(|info| info.summary()).unwrap_or_default(),
+        byte_size: &file.byte_size(),
+        timestamp: &file.timestamp(),
+    })
+}
+
+fn sign(cfg: &Config, definition: &NamespaceDefinition, url: Url) -> Url {
+    if definition.require_signed_urls {
+        access::sign_url(
+            cfg,
+            &url,
+            Duration::from_secs(cfg.signing.default_ttl_seconds),
+        )
+    } else {
+        url
+    }
+}
@@ -0,0 +1,75 @@
+use std::io::{self, Read};
+
+use askama::Template;
+
+use super::{
+    View, ViewedFile, ansi::lines_html, json::json_html,
+    markdown::markdown_html,
+};
+use crate::{
+    config::EncryptionKey,
+    encryption::{self, EncryptedFile},
+    render::{minify_page, template::TextView},
+    storage::NamespaceStorage,
+};
+
+/// Reads `file` and renders it as `view` into a minified page. JSON that
+/// doesn't parse is shown as plain text.
+///
+/// Blocks while reading the file, and rendering can take a moment for large
+/// ones, so this belongs in [`actix_web::web::block`].
+pub fn render(
+    storage: &NamespaceStorage,
+    encryption_key: Option<EncryptionKey>,
+    file: &ViewedFile,
+    view: View,
+    raw_href: &str,
+) -> io::Result<Vec<u8>> {
+    let text = read_text(storage, encryption_key, file)?;
+
+    let (kind, content) = match view {
+        View::Markdown => ("markdown", markdown_html(&text)),
+        View::Json => match json_html(&text) {
+            Some(html) => ("json", html),
+            None => ("text", lines_html(&text)),
+        },
+        _ => ("text", lines_html(&text)),
+    };
+
+    let page = TextView {
+        file_name: file.file_name(),
+        kind,
+        content: &content,
+        raw_href,
+        byte_size: &file.byte_size(),
+        timestamp: &file.timestamp(),
+    }
+    .render()
+    .map_err(io::Error::other)?;
+
+    Ok(minify_page(&page))
+}
+
+fn read_text(
+    storage: &NamespaceStorage,
+    encryption_key: Option<EncryptionKey>,
+    file: &ViewedFile,
+) -> io::Result<String> {
+    let mut reader: Box<dyn Read> = match encryption_key {
+        Some(encryption_key)
+            if encryption::is_encrypted(storage, &file.key)? =>
+        {
+            Box::new(EncryptedFile::open(
+                storage,
+                &file.key,
+                file.stored_size,
+                &encryption_key,
+            )?)
+        }
+        _ => storage.get_range(&file.key, 0, file.stored_size)?,
+    };
+
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}